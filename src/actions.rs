@@ -3,51 +3,390 @@
 
 use super::CONFIG;
 use crate::application::Application;
+use crate::cli::Options;
+use crate::command::{self, Command, COMMAND_HELP};
 use crate::fixed::{
-    about_html, Action, APPNAME, BOOKMARKS_SIZE, HELP_HTML, LOAD_ERROR,
-    PATH_SEP, PAUSE_ICON, PLAY_ICON, TICK_TIMEOUT, TINY_TIMEOUT,
-    TOOLBUTTON_SIZE,
+    about_html, Action, APPNAME, AUDIO_SUFFIXES, HELP_HTML,
+    HELP_KEYS_MARKER, LOAD_ERROR, MENU_CHARS, NO_LIBRARY_ROOTS,
+    NO_TRACKS_ERROR, PATH_SEP, PAUSE_ICON, PLAY_ICON, SLEEP_CUSTOM,
+    SLEEP_END_OF_TRACK, SLEEP_EXTEND_SECS, SLEEP_OFF, SPEED_STEP,
+    TICK_TIMEOUT, TINY_TIMEOUT, TOOLBUTTON_SIZE,
 };
 use crate::html_form;
+use crate::ipc::{self, ActionSender};
+use crate::library::{self, Library};
+use crate::library_form;
 use crate::main_window;
 use crate::options_form;
-use crate::util::{self, WhichTrack};
+use crate::playlist::{self, Playlist, PLAYLIST_SUFFIXES};
+use crate::queue_form;
+use crate::status::STATUS;
+use crate::util;
 use fltk::{
     app,
-    dialog::{FileDialog, FileDialogType},
+    dialog::{self, FileDialog, FileDialogOptions, FileDialogType},
     image::SvgImage,
+    menu::MenuItem,
     prelude::*,
 };
 use soloud::prelude::*;
-use std::{path::PathBuf, thread, time::Duration};
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
 
 impl Application {
     pub(crate) fn on_startup(&mut self) {
-        self.load_track();
+        let has_track = {
+            let config = CONFIG.get().read().unwrap();
+            config.track.exists()
+        };
+        if has_track {
+            self.load_track();
+            if self.play_on_startup && !self.engine.playing {
+                self.on_play_or_pause(); // PLAY
+            }
+        } else {
+            self.update_ui();
+        }
+        for script in std::mem::take(&mut self.scripts) {
+            self.run_script(&script);
+        }
     }
 
+    // Several tracks (or playlists) are played as a playlist
     pub(crate) fn on_open(&mut self) {
-        let mut form = FileDialog::new(FileDialogType::BrowseFile);
-        form.set_title(&format!("Choose Track — {APPNAME}"));
+        let mut filenames = choose_tracks("Choose Tracks");
+        if filenames.len() > 1 {
+            self.handle_args(Options {
+                paths: filenames,
+                ..Default::default()
+            });
+        } else if let Some(filename) = filenames.pop() {
+            if playlist::is_playlist(&filename) {
+                self.open_playlist(&filename);
+            } else {
+                self.auto_play_track(filename);
+            }
+        }
+    }
+
+    // Adds the chosen tracks (or playlists' tracks) to the queue; if
+    // nothing is loaded the first queued track is played at once
+    pub(crate) fn on_enqueue(&mut self) {
+        let filenames = choose_tracks("Choose Tracks to Enqueue");
+        if !filenames.is_empty() {
+            self.queue(&filenames);
+        }
+    }
+
+    fn queue(&mut self, paths: &[PathBuf]) {
+        self.engine.add_to_queue(paths);
+        if !self.engine.loaded {
+            self.on_next();
+        }
+    }
+
+    pub(crate) fn on_queue(&mut self) {
+        let queue = CONFIG.get().read().unwrap().queue.clone();
+        let form = queue_form::Form::new(&queue);
+        if *form.ok.borrow() {
+            let queue = form.queue.borrow().clone();
+            self.engine.set_queue(queue);
+        }
+    }
+
+    // Plays the first track in the chosen folder (or if there are none and
+    // folders are recursive, in its first subfolder that has any)
+    pub(crate) fn on_open_folder(&mut self) {
+        let mut form = FileDialog::new(FileDialogType::BrowseDir);
+        form.set_title(&format!("Choose Folder — {APPNAME}"));
         let _ = form.set_directory(&util::get_track_dir()); // Ignore error
-        form.set_filter("Audio Files\t*.{flac,mogg,mp3,oga,ogg,wav}");
         form.show();
-        let filename = form.filename();
-        if filename.exists() {
-            self.auto_play_track(filename);
+        let dir = form.filename();
+        if dir.is_dir() {
+            let recursive = CONFIG.get().read().unwrap().recursive;
+            match util::get_dir_tracks(&dir, recursive).first() {
+                Some(track) => self.auto_play_track(track.clone()),
+                None => self.info_view.set_value(
+                    &NO_TRACKS_ERROR
+                        .replace("FOLDER", &dir.to_string_lossy()),
+                ),
+            }
         }
     }
 
-    pub(crate) fn on_previous(&mut self) {
-        let track = {
+    // Plays the track chosen in the library's search form and queues the
+    // enqueued ones
+    pub(crate) fn on_search_library(&mut self) {
+        let (filename, has_roots) = {
             let config = CONFIG.get().read().unwrap();
-            config.track.clone()
+            (config.library_filename(), !config.library_roots.is_empty())
         };
-        if let Some(track) =
-            util::get_prev_or_next_track(&track, WhichTrack::Previous)
+        if !has_roots {
+            self.library_message(NO_LIBRARY_ROOTS);
+            return;
+        }
+        let tracks = match Library::open(&filename)
+            .and_then(|library| library.tracks())
         {
+            Ok(tracks) if !tracks.is_empty() => tracks,
+            Ok(_) => {
+                self.library_message(
+                    "The library is empty: use Rescan Library (and \
+                     wait for it to finish)",
+                );
+                return;
+            }
+            Err(err) => {
+                self.library_message(&format!(
+                    "Failed to read the library: {err}"
+                ));
+                return;
+            }
+        };
+        let form = library_form::Form::new(tracks);
+        let track = form.play.borrow_mut().take();
+        let queue = form.queue.borrow().clone();
+        if let Some(track) = track {
             self.auto_play_track(track);
         }
+        if !queue.is_empty() {
+            self.queue(&queue);
+        }
+    }
+
+    // The rescan is done in the background; on_library_rescanned() is
+    // called when it has finished
+    pub(crate) fn on_rescan_library(&mut self) {
+        let (filename, roots) = {
+            let config = CONFIG.get().read().unwrap();
+            (config.library_filename(), config.library_roots.clone())
+        };
+        if roots.is_empty() {
+            self.library_message(NO_LIBRARY_ROOTS);
+        } else if !library::rescan_in_background(
+            filename,
+            roots,
+            Some(ActionSender::Fltk(self.sender)),
+        ) {
+            self.library_message("The library is already being rescanned");
+        }
+    }
+
+    pub(crate) fn on_library_rescanned(&mut self) {
+        if let Some(report) = library::take_scan_report() {
+            self.library_message(&report);
+        }
+    }
+
+    fn library_message(&self, message: &str) {
+        dialog::message_title(&format!("Library — {APPNAME}"));
+        dialog::message(util::x() - 200, util::y() - 100, message);
+    }
+
+    pub(crate) fn on_handle_commands(&mut self) {
+        while let Some(command) = ipc::take_command() {
+            self.run_command(command);
+        }
+    }
+
+    pub(crate) fn run_command(&mut self, command: Command) {
+        match command {
+            Command::Args(options) => self.handle_args(options),
+            Command::Do(action) => self.dispatch(action),
+            Command::Play => {
+                if !self.engine.playing {
+                    self.on_play_or_pause();
+                }
+            }
+            Command::Pause => {
+                if self.engine.playing {
+                    self.on_play_or_pause();
+                }
+            }
+            Command::Seek(pos) => self.go_to(pos),
+            Command::Skip(secs) => self.skip(secs),
+            Command::Volume(volume) => self.change_volume(volume),
+            Command::Speed(speed) => self.change_speed(speed),
+            Command::Bookmark(note) => {
+                let pos = self.engine.exact_pos();
+                if self.engine.add_bookmark(pos, note) {
+                    self.populate_bookmarks_menu_button();
+                }
+            }
+            Command::Queue(paths) => self.queue(&paths),
+            Command::Relocate(old, new) => {
+                self.relocate(&old, &new);
+            }
+        }
+    }
+
+    pub(crate) fn on_run_command(&mut self) {
+        dialog::message_title(&format!("Run Command — {APPNAME}"));
+        let text = match dialog::input(
+            util::x() - 200,
+            util::y() - 100,
+            "Command (e.g., seek 1:30; volume 50%)",
+            &self.last_command,
+        ) {
+            Some(text) => text.trim().to_string(),
+            None => return, // Cancelled
+        };
+        if text.is_empty() {
+            return;
+        }
+        self.last_command = text.clone();
+        // Relative paths are relative to the current track's folder
+        match Command::parse(&text, &util::get_track_dir()) {
+            Ok(commands) => {
+                for command in commands {
+                    self.run_command(command);
+                }
+            }
+            Err(err) => self.command_error(&err),
+        }
+    }
+
+    pub(crate) fn run_script(&mut self, filename: &Path) {
+        match command::read_script(filename) {
+            Ok(commands) => {
+                for command in commands {
+                    self.run_command(command);
+                }
+            }
+            Err(err) => self.command_error(&err),
+        }
+    }
+
+    fn command_error(&self, err: &str) {
+        dialog::message_title(&format!("Error — {APPNAME}"));
+        dialog::message(
+            util::x() - 200,
+            util::y() - 100,
+            &format!("{err}\n\nCommands: {COMMAND_HELP}"),
+        );
+    }
+
+    // Handles the arguments forwarded by a later instance
+    fn handle_args(&mut self, options: Options) {
+        if options.quit {
+            self.on_quit();
+            return;
+        }
+        self.main_window.show(); // Raise
+        let changed = self.engine.handle_args(&options);
+        self.update_from_engine(changed);
+        if let Some(script) = &options.script {
+            self.run_script(script);
+        }
+    }
+
+    fn open_playlist(&mut self, filename: &Path) {
+        match Playlist::load(filename) {
+            Ok(playlist) => {
+                let track = playlist.tracks[0].track.clone();
+                {
+                    let mut config = CONFIG.get().write().unwrap();
+                    config.playlist = Some(playlist);
+                }
+                self.auto_play_track(track);
+            }
+            Err(_) => {
+                self.info_view.set_value(
+                    &LOAD_ERROR
+                        .replace("FILE", &filename.to_string_lossy()),
+                );
+            }
+        }
+    }
+
+    pub(crate) fn on_save_playlist(&mut self) {
+        // If there's no playlist the current track's folder is saved
+        let mut playlist = {
+            let config = CONFIG.get().read().unwrap();
+            match &config.playlist {
+                Some(playlist) => playlist.clone(),
+                None => {
+                    if !config.track.exists() {
+                        return;
+                    }
+                    Playlist::new(
+                        "",
+                        util::get_sorted_tracks(
+                            &config.track,
+                            config.recursive,
+                        ),
+                    )
+                }
+            }
+        };
+        let mut form = FileDialog::new(FileDialogType::BrowseSaveFile);
+        form.set_title(&format!("Save Playlist — {APPNAME}"));
+        form.set_option(FileDialogOptions::SaveAsConfirm);
+        let dir = match playlist.filename.parent() {
+            Some(dir) if dir.exists() => dir.to_path_buf(),
+            _ => util::get_track_dir(),
+        };
+        let _ = form.set_directory(&dir); // Ignore error
+        form.set_filter("Extended M3U\t*.m3u8\nM3U\t*.m3u\nPLS\t*.pls");
+        form.show();
+        let mut filename = form.filename();
+        if filename.to_string_lossy() == "" {
+            return; // Cancelled
+        }
+        if !playlist::is_playlist(&filename) {
+            filename.set_extension("m3u8");
+        }
+        match playlist.save(&filename) {
+            Ok(_) => {
+                let mut config = CONFIG.get().write().unwrap();
+                let track = config.track.clone();
+                playlist.set_current(&track);
+                config.playlist = Some(playlist);
+            }
+            Err(err) => {
+                dialog::message_title(&format!("Error — {APPNAME}"));
+                dialog::message(
+                    util::x() - 200,
+                    util::y() - 100,
+                    &format!("Failed to save playlist: {err}"),
+                );
+            }
+        }
+    }
+
+    pub(crate) fn on_close_playlist(&mut self) {
+        {
+            let mut config = CONFIG.get().write().unwrap();
+            if config.playlist.is_none() {
+                return;
+            }
+            config.playlist = None;
+        }
+        let track = {
+            let config = CONFIG.get().read().unwrap();
+            config.track.clone()
+        };
+        if track.exists() {
+            self.info_view.set_value(&util::get_track_data_html(&track));
+        }
+    }
+
+    pub(crate) fn on_previous(&mut self) {
+        let changed = self.engine.previous();
+        self.update_from_engine(changed);
+    }
+
+    // Plays the current track from the given position
+    fn play_from(&mut self, pos: f64) {
+        self.engine.play_from(pos);
+        self.update_from_engine(false);
+    }
+
+    fn go_to(&mut self, pos: f64) {
+        self.engine.go_to(pos);
+        self.update_from_engine(false);
     }
 
     pub(crate) fn on_replay(&mut self) {
@@ -57,37 +396,59 @@ impl Application {
                 return;
             }
         }
-        if self.playing {
-            self.on_play_or_pause(); // PAUSE
+        self.engine.replay();
+        self.update_from_engine(false);
+    }
+
+    pub(crate) fn on_play_or_pause(&mut self) {
+        if !self.engine.playing {
+            if let Some(remaining) = self.sleep_remaining() {
+                if !self.sleep_at_end && remaining <= 0.0 {
+                    self.cancel_sleep_timer(); // Expired while paused
+                }
+            }
         }
-        {
-            let mut config = CONFIG.get().write().unwrap();
-            config.pos = 0.0;
+        self.engine.resume_or_pause();
+        self.update_from_engine(false);
+        if !self.engine.playing {
+            self.save_state(); // Paused
         }
-        self.seek(0.0);
-        self.on_play_or_pause(); // PLAY
     }
 
-    pub(crate) fn on_play_or_pause(&mut self) {
-        if self.first_to_play {
-            self.on_first_to_play();
+    // Shows the engine's track (if it changed), play state, volume,
+    // speed, and position, and keeps ticking while it plays
+    pub(crate) fn update_from_engine(&mut self, changed: bool) {
+        if changed {
+            self.show_track();
+            self.populate_repeat_menu_button(); // The loop was cleared
+            self.update_ui();
         }
-        let icon = if self.playing {
-            self.player.set_pause(self.handle, true);
-            PLAY_ICON
-        } else {
-            self.player.set_pause(self.handle, false);
+        if self.playing_shown != self.engine.playing {
+            self.playing_shown = self.engine.playing;
+            let icon =
+                if self.playing_shown { PAUSE_ICON } else { PLAY_ICON };
+            let mut icon = SvgImage::from_data(icon).unwrap();
+            icon.scale(TOOLBUTTON_SIZE, TOOLBUTTON_SIZE, true, true);
+            self.play_pause_button.set_image(Some(icon));
+        }
+        let volume = self.engine.volume;
+        self.volume_slider.set_value(volume);
+        self.volume_label
+            .set_label(&format!("{}%", (volume * 100.0).round()));
+        let speed = self.engine.speed;
+        self.speed_slider.set_value(speed);
+        self.speed_label.set_label(&format!("{speed:.2}×"));
+        self.time_slider.set_value(self.engine.pos);
+        self.update_time_label();
+        if self.engine.playing && !self.ticking {
+            self.ticking = true;
             #[allow(clippy::clone_on_copy)]
             let sender = self.sender.clone();
-            app::add_timeout3(TINY_TIMEOUT, move |_| {
+            app::add_timeout3(TICK_TIMEOUT, move |_| {
                 sender.send(Action::Tick);
             });
-            PAUSE_ICON
-        };
-        let mut icon = SvgImage::from_data(icon).unwrap();
-        icon.scale(TOOLBUTTON_SIZE, TOOLBUTTON_SIZE, true, true);
-        self.play_pause_button.set_image(Some(icon));
-        self.playing = !self.playing;
+        }
+        app::redraw(); // redraws the world
     }
 
     pub(crate) fn on_space_pressed(&mut self) {
@@ -106,64 +467,131 @@ impl Application {
     }
 
     pub(crate) fn on_next(&mut self) {
-        let track = {
-            let config = CONFIG.get().read().unwrap();
-            config.track.clone()
-        };
-        if let Some(track) =
-            util::get_prev_or_next_track(&track, WhichTrack::Next)
-        {
-            self.auto_play_track(track);
-        }
+        let changed = self.engine.next();
+        self.update_from_engine(changed);
+    }
+
+    // Skips any remaining chapters
+    pub(crate) fn on_next_track(&mut self) {
+        let changed = self.engine.play_next_track();
+        self.update_from_engine(changed);
     }
 
     pub(crate) fn on_volume_down(&mut self) {
-        self.change_volume(
-            (self.volume_slider.value() as f32 - 0.05).max(0.0),
-        );
+        self.change_volume(self.engine.volume - 0.05);
     }
 
     pub(crate) fn on_volume_up(&mut self) {
-        self.change_volume(
-            (self.volume_slider.value() as f32 + 0.05).min(1.0),
-        );
+        self.change_volume(self.engine.volume + 0.05);
     }
 
     pub(crate) fn on_volume_update(&mut self) {
-        let volume = self.volume_slider.value() as f32;
-        self.player.set_volume(self.handle, volume);
-        self.volume_label
-            .set_label(&format!("{}%", (volume * 100.0).round()));
-        app::redraw(); // redraws the world
+        self.change_volume(self.volume_slider.value());
+    }
+
+    pub(crate) fn on_speed_down(&mut self) {
+        self.change_speed(self.engine.speed - SPEED_STEP * 2.0);
+    }
+
+    pub(crate) fn on_speed_normal(&mut self) {
+        self.change_speed(1.0);
+    }
+
+    pub(crate) fn on_speed_up(&mut self) {
+        self.change_speed(self.engine.speed + SPEED_STEP * 2.0);
+    }
+
+    pub(crate) fn on_speed_update(&mut self) {
+        self.change_speed(self.speed_slider.value());
     }
 
     pub(crate) fn on_time_update(&mut self) {
         self.seek(self.time_slider.value());
-        app::redraw(); // redraws the world
     }
 
     pub(crate) fn on_options(&mut self) {
-        let old_size = {
+        let (old_size, old_keys, old_rewind, old_recursive, old_roots) = {
             let config = CONFIG.get().read().unwrap();
-            config.history_size
+            (
+                config.history_size,
+                config.keys.clone(),
+                config.rewind,
+                config.recursive,
+                config.library_roots.clone(),
+            )
         };
         let form = options_form::Form::default();
         let ok = *form.ok.borrow();
         if ok {
-            let new_size = {
+            let (
+                new_size,
+                keys_changed,
+                rewind_changed,
+                recursive_changed,
+                new_roots,
+            ) = {
                 let config = CONFIG.get().read().unwrap();
-                config.history_size
+                (
+                    config.history_size,
+                    config.keys != old_keys,
+                    !util::isclose64(config.rewind, old_rewind),
+                    config.recursive != old_recursive,
+                    config.library_roots.clone(),
+                )
             };
+            // A changed root finds its missing tracks in their new place
+            for (old_root, new_root) in old_roots.iter().zip(&new_roots) {
+                if old_root != new_root {
+                    self.relocate(old_root, new_root);
+                }
+            }
             if old_size != new_size {
                 self.populate_history_menu_button();
             }
+            if recursive_changed {
+                self.engine.drop_preload();
+            }
+            if keys_changed || rewind_changed {
+                self.update_key_hints();
+            }
+        }
+    }
+
+    // Shows the current keys in the tooltips, main menu, and help
+    fn update_key_hints(&mut self) {
+        for (mut button, action) in [
+            (self.load_button.clone(), Action::Load),
+            (self.prev_button.clone(), Action::Previous),
+            (self.replay_button.clone(), Action::Replay),
+            (self.rewind_button.clone(), Action::Rewind),
+            (self.play_pause_button.clone(), Action::PlayOrPause),
+            (self.next_button.clone(), Action::Next),
+            (self.add_bookmark_button.clone(), Action::AddBookmark),
+            (self.delete_bookmark_button.clone(), Action::DeleteBookmark),
+        ] {
+            button.set_tooltip(&main_window::toolbar_tooltip(action));
+        }
+        for (mut button, action) in [
+            (self.repeat_menu_button.clone(), Action::OnRepeatMenu),
+            (self.chapters_menu_button.clone(), Action::OnChapterMenu),
+            (self.history_menu_button.clone(), Action::OnHistoryMenu),
+            (self.bookmarks_menu_button.clone(), Action::OnBookmarkMenu),
+            (self.menu_button.clone(), Action::OnMenuMenu),
+        ] {
+            button.set_tooltip(&main_window::toolbar_tooltip(action));
         }
+        self.speed_slider.set_tooltip(&main_window::speed_tooltip());
+        main_window::initialize_menu_button(
+            &mut self.menu_button,
+            self.sender,
+        );
+        self.helpform = None; // Recreated with the new keys when needed
     }
 
     pub(crate) fn on_about(&mut self) {
         html_form::Form::new(
             "About",
-            &about_html(&self.player),
+            &about_html(&self.engine.player),
             true,
             480,
             300,
@@ -175,138 +603,311 @@ impl Application {
         if let Some(helpform) = &mut self.helpform {
             helpform.show();
         } else {
+            let html = {
+                let config = CONFIG.get().read().unwrap();
+                HELP_HTML
+                    .replace(HELP_KEYS_MARKER, &config.keys.help_rows())
+            };
             self.helpform = Some(html_form::Form::new(
-                "Help", HELP_HTML, false, 400, 440, true,
+                "Help", &html, false, 400, 440, true,
             ));
         }
     }
 
     pub(crate) fn on_quit(&mut self) {
-        let mut config = CONFIG.get().write().unwrap();
-        config.window_x = self.main_window.x();
-        config.window_y = self.main_window.y();
-        config.window_width = self.main_window.width();
-        config.window_height = self.main_window.height();
-        config.volume = self.volume_slider.value();
-        config.pos = self.time_slider.value();
-        // We already have the track, history, and bookmarks
-        config.save();
+        self.save_state();
+        ipc::cleanup();
         self.app.quit();
     }
 
-    pub(crate) fn on_tick(&mut self) {
-        if self.playing {
-            let pos = self.player.stream_position(self.handle);
-            let length = self.wav.length();
-            if self.player.voice_count() == 0 {
-                // Reached the end
-                self.on_next();
-                return;
+    // Saves the window's geometry and the current volume and position
+    fn save_state(&mut self) {
+        {
+            let mut config = CONFIG.get().write().unwrap();
+            config.window_x = self.main_window.x();
+            config.window_y = self.main_window.y();
+            config.window_width = self.main_window.width();
+            config.window_height = self.main_window.height();
+            config.window_scale = app::screen_scale(0);
+        }
+        self.engine.save();
+        self.saved = Instant::now();
+    }
+
+    pub(crate) fn on_sleep_timer(&mut self) {
+        let choice = match self.menu_button.choice() {
+            Some(choice) => choice.replace('&', ""),
+            None => return,
+        };
+        let minutes = if choice == SLEEP_OFF {
+            self.cancel_sleep_timer();
+            return;
+        } else if choice == SLEEP_END_OF_TRACK {
+            self.cancel_sleep_timer();
+            self.sleep_at_end = true;
+            self.engine.drop_preload(); // The next track mustn't start
+            self.show_sleep_timer();
+            return;
+        } else if choice == SLEEP_CUSTOM {
+            dialog::message_title(&format!("Sleep Timer — {APPNAME}"));
+            match dialog::input(
+                util::x() - 200,
+                util::y() - 100,
+                "Minutes until sleep",
+                &format!("{}", (self.sleep_secs / 60.0).round()),
+            ) {
+                Some(minutes) => {
+                    util::get_num(minutes.trim(), 1.0, 24.0 * 60.0, 0.0)
+                }
+                None => return, // Cancelled
             }
-            self.time_slider.set_value(pos);
-            self.time_label.set_label(&format!(
-                "{}/{}",
-                util::humanized_time(pos),
-                util::humanized_time(length)
-            ));
-            app::redraw(); // redraws the world
-            #[allow(clippy::clone_on_copy)]
-            let sender = self.sender.clone();
-            app::add_timeout3(TICK_TIMEOUT, move |_| {
-                sender.send(Action::Tick);
-            });
+        } else {
+            match choice.split_whitespace().next() {
+                Some(minutes) => util::get_num(minutes, 1.0, 60.0, 0.0),
+                None => return,
+            }
+        };
+        if minutes > 0.0 {
+            self.cancel_sleep_timer();
+            self.sleep_secs = minutes * 60.0;
+            self.sleep_at = Some(
+                Instant::now() + Duration::from_secs_f64(self.sleep_secs),
+            );
+            self.show_sleep_timer();
         }
     }
 
-    pub(crate) fn on_first_to_play(&mut self) {
-        let pos = {
-            let config = CONFIG.get().read().unwrap();
-            config.pos
+    // Moving the mouse or pressing a key in the sleep timer's last minute
+    // restarts it
+    pub(crate) fn on_user_activity(&mut self) {
+        if let Some(remaining) = self.sleep_remaining() {
+            if !self.sleep_at_end && remaining < SLEEP_EXTEND_SECS {
+                self.sleep_at = Some(
+                    Instant::now()
+                        + Duration::from_secs_f64(self.sleep_secs),
+                );
+                self.engine.set_fade(1.0);
+                self.show_sleep_timer();
+            }
+        }
+    }
+
+    fn cancel_sleep_timer(&mut self) {
+        if self.sleep_at.is_some() || self.sleep_at_end {
+            self.sleep_at = None;
+            self.sleep_at_end = false;
+            self.engine.set_fade(1.0);
+            self.show_sleep_timer();
+        }
+    }
+
+    // Returns the real time left before sleeping, if the timer is on
+    fn sleep_remaining(&self) -> Option<f64> {
+        if self.sleep_at_end {
+            Some(self.engine.remaining())
+        } else {
+            self.sleep_at.map(|at| {
+                at.saturating_duration_since(Instant::now()).as_secs_f64()
+            })
+        }
+    }
+
+    fn show_sleep_timer(&mut self) {
+        STATUS.get().write().unwrap().sleeping = self.sleep_at.is_some();
+        let title = if self.sleep_at_end {
+            format!("{APPNAME} • Sleep at end of track")
+        } else if let Some(remaining) = self.sleep_remaining() {
+            format!(
+                "{APPNAME} • Sleep in {}",
+                util::humanized_time(remaining)
+            )
+        } else {
+            APPNAME.to_string()
         };
-        self.seek(pos);
-        self.playing = false;
-        self.first_to_play = false;
+        if self.main_window.label() != title {
+            self.main_window.set_label(&title);
+        }
     }
 
-    pub(crate) fn load_track(&mut self) {
-        if self.playing {
+    // Fades out during the sleep timer's last few seconds and then pauses
+    // and saves the position; returns true if it paused
+    fn check_sleep_timer(&mut self) -> bool {
+        let remaining = match self.sleep_remaining() {
+            Some(remaining) => remaining,
+            None => return false,
+        };
+        self.show_sleep_timer();
+        if !self.sleep_at_end && remaining <= 0.0 {
             self.on_play_or_pause(); // PAUSE
-            self.player.stop_all();
+            self.cancel_sleep_timer();
+            self.save_state();
+            return true;
         }
-        let config = CONFIG.get().read().unwrap();
-        let message = match self.wav.load(&config.track) {
-            Ok(_) => {
-                self.handle = self.player.play(&self.wav);
-                self.player.set_pause(self.handle, true);
-                self.player.set_volume(
-                    self.handle,
-                    self.volume_slider.value() as f32,
-                );
-                self.time_slider.set_range(0.0, self.wav.length());
-                self.time_slider.set_step(self.wav.length(), 20);
-                let pos = if self.first_to_play { config.pos } else { 0.0 };
-                self.time_slider.set_value(pos);
-                self.time_label.set_label(&format!(
-                    "{}/{}",
-                    util::humanized_time(pos),
-                    util::humanized_time(self.wav.length())
-                ));
-                #[allow(clippy::clone_on_copy)]
-                let sender = self.sender.clone();
-                app::add_timeout3(TINY_TIMEOUT, move |_| {
-                    sender.send(Action::AddToHistory);
-                });
-                util::get_track_data_html(&config.track)
-            }
-            Err(_) => {
-                LOAD_ERROR.replace("FILE", &config.track.to_string_lossy())
-            }
+        let fade = {
+            let config = CONFIG.get().read().unwrap();
+            config.sleep_fade
         };
-        self.info_view.set_value(&message);
-        self.update_ui();
-        app::redraw(); // redraws the world
+        if remaining < fade {
+            self.engine.set_fade(remaining / fade);
+        }
+        false
     }
 
-    pub(crate) fn change_volume(&mut self, volume: f32) {
-        self.player.set_volume(self.handle, volume);
-        self.volume_slider.set_value(volume as f64);
-        self.volume_label
-            .set_label(&format!("{}%", (volume * 100.0).round()));
-        app::redraw(); // redraws the world
+    // Stops at the end of the track, but ready to play the next one
+    fn sleep_after_track(&mut self) {
+        self.on_play_or_pause(); // PAUSE
+        self.cancel_sleep_timer();
+        if let Some(track) = self.engine.take_next_track() {
+            let pos = {
+                let config = CONFIG.get().read().unwrap();
+                config.position_for(&track)
+            };
+            self.engine.switch_track(track, pos);
+            self.update_from_engine(true);
+        }
+        self.save_state();
     }
 
-    pub(crate) fn auto_play_track(&mut self, track: std::path::PathBuf) {
-        if self.playing {
-            self.on_play_or_pause(); // PAUSE
+    pub(crate) fn on_tick(&mut self) {
+        self.ticking = false;
+        if !self.engine.playing {
+            return;
+        }
+        if self.engine.has_ended() {
+            if self.sleep_at_end {
+                self.sleep_after_track();
+            } else {
+                let changed = self.engine.end_of_track();
+                self.update_from_engine(changed);
+            }
+            return;
         }
+        if self.check_sleep_timer() {
+            return;
+        }
+        // When the sleep timer stops at the end of the track the next
+        // track isn't started
+        let changed = self.engine.tick(!self.sleep_at_end);
+        self.update_from_engine(changed);
+        let autosave = CONFIG.get().read().unwrap().autosave;
+        if autosave > 0.0 && self.saved.elapsed().as_secs_f64() >= autosave
         {
-            let mut config = CONFIG.get().write().unwrap();
-            config.track = track;
-            config.pos = 0.0;
+            self.save_state();
         }
-        self.load_track();
-        self.on_play_or_pause(); // PLAY
     }
 
-    pub(crate) fn seek(&mut self, pos: f64) {
-        if self.player.seek(self.handle, pos).is_ok() {
-            while self.player.stream_position(self.handle) < pos {
-                thread::sleep(Duration::from_millis(100));
+    pub(crate) fn load_track(&mut self) {
+        self.engine.load();
+        self.update_from_engine(true);
+    }
+
+    // Updates the time slider, chapters, and info view for the newly
+    // loaded track
+    fn show_track(&mut self) {
+        let config = CONFIG.get().read().unwrap();
+        if !self.engine.loaded {
+            self.info_view.set_value(
+                &LOAD_ERROR
+                    .replace("FILE", &config.track.to_string_lossy()),
+            );
+            return;
+        }
+        let length = self.engine.audio.length();
+        self.time_slider.set_range(0.0, length);
+        self.time_slider.set_step(length, 20);
+        main_window::populate_chapters_menu_button(
+            &mut self.chapters_menu_button,
+            self.sender,
+            &self.engine.chapters.borrow(),
+        );
+        #[allow(clippy::clone_on_copy)]
+        let sender = self.sender.clone();
+        app::add_timeout3(TINY_TIMEOUT, move |_| {
+            sender.send(Action::AddToHistory);
+        });
+        let mut html = util::get_track_data_html(&config.track);
+        if let Some(playlist) = &config.playlist {
+            if playlist.current() == Some(config.track.as_path()) {
+                html.push_str(&format!(
+                    "<br><font color=purple>{} ({}/{})</font>",
+                    if playlist.name.is_empty() {
+                        "Playlist"
+                    } else {
+                        playlist.name.as_str()
+                    },
+                    playlist.index + 1,
+                    playlist.tracks.len()
+                ));
             }
         }
-        self.time_slider.set_value(pos);
-        self.time_label.set_label(&format!(
-            "{}/{}",
-            util::humanized_time(pos),
-            util::humanized_time(self.wav.length())
+        self.info_view.set_value(&html);
+    }
+
+    pub(crate) fn change_speed(&mut self, speed: f64) {
+        self.engine.set_speed(speed);
+        self.update_from_engine(false);
+    }
+
+    fn update_time_label(&mut self) {
+        self.time_label.set_label(&util::humanized_pos_and_length(
+            self.engine.pos,
+            self.engine.audio.length(),
+            self.engine.speed,
         ));
-        app::redraw(); // redraws the world
+    }
+
+    pub(crate) fn change_volume(&mut self, volume: f64) {
+        self.engine.set_volume(volume);
+        self.update_from_engine(false);
+    }
+
+    pub(crate) fn auto_play_track(&mut self, track: PathBuf) {
+        self.engine.auto_play_track(track);
+        self.update_from_engine(true);
+    }
+
+    pub(crate) fn auto_play_track_at(&mut self, track: PathBuf, pos: f64) {
+        self.engine.auto_play_track_at(track, pos);
+        self.update_from_engine(true);
+    }
+
+    pub(crate) fn seek(&mut self, pos: f64) {
+        self.engine.seek(pos);
+        self.update_from_engine(false);
+    }
+
+    fn skip(&mut self, secs: f64) {
+        self.engine.skip(secs);
+        self.update_from_engine(false);
+    }
+
+    // Skips back or forward by the action's configured interval
+    pub(crate) fn on_skip(&mut self, action: Action) {
+        self.engine.apply_skip_action(action);
+        self.update_from_engine(false);
     }
 
     pub(crate) fn on_menu_menu(&mut self) {
         self.menu_button.popup();
     }
 
+    pub(crate) fn on_repeat_menu(&mut self) {
+        self.repeat_menu_button.popup();
+    }
+
+    pub(crate) fn on_repeat_action(&mut self, action: Action) {
+        self.engine.apply_repeat_action(action);
+        self.populate_repeat_menu_button();
+    }
+
+    pub(crate) fn populate_repeat_menu_button(&mut self) {
+        main_window::populate_repeat_menu_button(
+            &mut self.repeat_menu_button,
+            self.sender,
+            &self.engine,
+        );
+    }
+
     pub(crate) fn on_bookmark_menu(&mut self) {
         {
             let config = CONFIG.get().read().unwrap();
@@ -317,6 +918,24 @@ impl Application {
         self.bookmarks_menu_button.popup();
     }
 
+    pub(crate) fn on_chapter_menu(&mut self) {
+        if !self.engine.chapters.borrow().is_empty() {
+            self.chapters_menu_button.popup();
+        }
+    }
+
+    pub(crate) fn on_load_chapter(&mut self) {
+        let index = self.chapters_menu_button.value();
+        if index < 0 {
+            return;
+        }
+        let pos = match self.engine.chapters.borrow().get(index as usize) {
+            Some(chapter) => chapter.pos,
+            None => return,
+        };
+        self.play_from(pos);
+    }
+
     pub(crate) fn on_history_menu(&mut self) {
         {
             let config = CONFIG.get().read().unwrap();
@@ -328,13 +947,8 @@ impl Application {
     }
 
     pub(crate) fn on_add_to_history(&mut self) {
-        {
-            let mut config = CONFIG.get().write().unwrap();
-            let track = config.track.clone();
-            let size = config.history_size;
-            if !util::maybe_add_to_deque(&mut config.history, track, size) {
-                return; // unchanged
-            }
+        if !self.engine.add_to_history() {
+            return; // unchanged
         }
         main_window::populate_history_menu_button(
             &mut self.history_menu_button,
@@ -353,11 +967,87 @@ impl Application {
 
     pub(crate) fn on_load_bookmarked_track(&mut self) {
         let index = self.bookmarks_menu_button.value();
-        if index > -1 {
-            if let Some(track) = self.bookmarks_menu_button.text(index) {
-                self.load_remembered_track(&track);
+        if index < 0 {
+            return;
+        }
+        let (bookmark, same_track) = {
+            let config = CONFIG.get().read().unwrap();
+            match config.bookmarks.get(index as usize) {
+                Some(bookmark) => {
+                    (bookmark.clone(), bookmark.track == config.track)
+                }
+                None => return,
+            }
+        };
+        if same_track && self.engine.has_started() {
+            self.seek(bookmark.pos);
+            if !self.engine.playing {
+                self.on_play_or_pause(); // PLAY
+            }
+        } else {
+            self.auto_play_track_at(bookmark.track, bookmark.pos);
+        }
+    }
+
+    // Asks for the folder that the missing tracks' paths start with and
+    // the folder to replace it with (e.g., after moving the library)
+    pub(crate) fn on_relocate(&mut self) {
+        let missing = CONFIG.get().read().unwrap().missing_tracks();
+        dialog::message_title(&format!("Relocate — {APPNAME}"));
+        if missing.is_empty() {
+            dialog::message(
+                util::x() - 200,
+                util::y() - 100,
+                "No remembered tracks are missing",
+            );
+            return;
+        }
+        let old = match dialog::input(
+            util::x() - 200,
+            util::y() - 100,
+            &format!(
+                "{} remembered tracks are missing; the folder their paths \
+                 start with",
+                missing.len()
+            ),
+            &util::get_common_dir(&missing).to_string_lossy(),
+        ) {
+            Some(old) if !old.trim().is_empty() => {
+                PathBuf::from(old.trim())
+            }
+            _ => return, // Cancelled
+        };
+        let mut form = FileDialog::new(FileDialogType::BrowseDir);
+        form.set_title(&format!(
+            "Choose the Folder to Replace {} — {APPNAME}",
+            old.display()
+        ));
+        let _ = form.set_directory(&util::get_track_dir()); // Ignore error
+        form.show();
+        let new = form.filename();
+        if !new.is_dir() {
+            return; // Cancelled
+        }
+        let count = self.relocate(&old, &new);
+        dialog::message_title(&format!("Relocate — {APPNAME}"));
+        dialog::message(
+            util::x() - 200,
+            util::y() - 100,
+            &format!("Relocated {count} missing tracks"),
+        );
+    }
+
+    fn relocate(&mut self, old: &Path, new: &Path) -> usize {
+        let loaded = self.engine.loaded;
+        let count = self.engine.relocate(old, new);
+        if count > 0 {
+            self.populate_history_menu_button();
+            self.populate_bookmarks_menu_button();
+            if !loaded && self.engine.loaded {
+                self.update_from_engine(true);
             }
         }
+        count
     }
 
     fn load_remembered_track(&mut self, track: &str) {
@@ -367,48 +1057,86 @@ impl Application {
     }
 
     pub(crate) fn on_add_bookmark(&mut self) {
-        let track = {
+        {
             let config = CONFIG.get().read().unwrap();
-            if config.bookmarks.contains(&config.track) {
-                None
-            } else {
-                Some(config.track.clone())
-            }
-        };
-        let mut changed = false;
-        if let Some(track) = track {
-            let mut config = CONFIG.get().write().unwrap();
-            if config.bookmarks.len() >= BOOKMARKS_SIZE {
-                config.bookmarks.truncate(BOOKMARKS_SIZE - 1); // make room
+            if !config.track.exists() {
+                return;
             }
-            config.bookmarks.push(track);
-            config.bookmarks.sort();
-            changed = true;
         }
-        if changed {
+        let pos = self.engine.exact_pos();
+        dialog::message_title(&format!("Add Bookmark — {APPNAME}"));
+        let note = match dialog::input(
+            util::x() - 200,
+            util::y() - 100,
+            &format!("Note for {} (optional)", util::humanized_time(pos)),
+            "",
+        ) {
+            Some(note) => note.trim().to_string(),
+            None => return, // Cancelled
+        };
+        if self.engine.add_bookmark(pos, note) {
             self.populate_bookmarks_menu_button();
         }
     }
 
     pub(crate) fn on_delete_bookmark(&mut self) {
-        let track = {
+        let (indexes, labels) = {
             let config = CONFIG.get().read().unwrap();
-            if !config.bookmarks.contains(&config.track) {
-                None
-            } else {
-                Some(config.track.clone())
+            let mut indexes = vec![];
+            let mut labels = vec![];
+            for (i, bookmark) in config.bookmarks.iter().enumerate() {
+                if bookmark.track == config.track {
+                    indexes.push(i);
+                    labels.push(format!(
+                        "&{} {}",
+                        MENU_CHARS[labels.len()],
+                        util::menu_escape(&main_window::bookmark_label(
+                            bookmark
+                        ))
+                    ));
+                }
             }
+            (indexes, labels)
         };
-        let mut changed = false;
-        if let Some(track) = track {
-            let mut config = CONFIG.get().write().unwrap();
-            if let Ok(index) = config.bookmarks.binary_search(&track) {
-                config.bookmarks.remove(index);
-                changed = true;
-            }
+        if indexes.is_empty() {
+            return;
         }
-        if changed {
-            self.populate_bookmarks_menu_button();
+        let choices: Vec<&str> =
+            labels.iter().map(|l| l.as_str()).collect();
+        let menu = MenuItem::new(&choices);
+        let x = self.delete_bookmark_button.x();
+        let y = self.delete_bookmark_button.y()
+            + self.delete_bookmark_button.height();
+        if let Some(item) = menu.popup(x, y) {
+            // The menu is flat so the chosen item's index is its bookmark's
+            if let Some(i) = (0..indexes.len())
+                .find(|i| menu.next(*i as i32).as_ref() == Some(&item))
+            {
+                {
+                    let mut config = CONFIG.get().write().unwrap();
+                    config.bookmarks.remove(indexes[i]);
+                }
+                self.populate_bookmarks_menu_button();
+            }
         }
     }
 }
+
+// Returns the chosen tracks and playlists, if any
+fn choose_tracks(title: &str) -> Vec<PathBuf> {
+    let mut form = FileDialog::new(FileDialogType::BrowseMultiFile);
+    form.set_title(&format!("{title} — {APPNAME}"));
+    let _ = form.set_directory(&util::get_track_dir()); // Ignore error
+    let audio = AUDIO_SUFFIXES.join(",");
+    let playlists = PLAYLIST_SUFFIXES.join(",");
+    form.set_filter(&format!(
+        "Audio Files and Playlists\t*.{{{audio},{playlists}}}\n\
+         Audio Files\t*.{{{audio}}}\n\
+         Playlists\t*.{{{playlists}}}"
+    ));
+    form.show();
+    form.filenames()
+        .into_iter()
+        .filter(|filename| filename.exists())
+        .collect()
+}
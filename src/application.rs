@@ -2,6 +2,7 @@
 // License: GPLv3
 
 use super::CONFIG;
+use crate::engine::Engine;
 use crate::fixed::Action;
 use crate::html_form;
 use crate::main_window;
@@ -16,15 +17,19 @@ use fltk::{
     valuator::HorFillSlider,
     window::Window,
 };
-use soloud::{audio::Wav, prelude::*, Soloud};
+use std::{path::PathBuf, time::Instant};
 
 pub struct Application {
     pub(crate) app: App,
     pub(crate) main_window: Window,
+    pub(crate) load_button: Button,
     pub(crate) prev_button: Button,
     pub(crate) replay_button: Button,
+    pub(crate) rewind_button: Button,
     pub(crate) play_pause_button: Button,
     pub(crate) next_button: Button,
+    pub(crate) repeat_menu_button: MenuButton,
+    pub(crate) chapters_menu_button: MenuButton,
     pub(crate) history_menu_button: MenuButton,
     pub(crate) bookmarks_menu_button: MenuButton,
     pub(crate) add_bookmark_button: Button,
@@ -33,38 +38,52 @@ pub struct Application {
     pub(crate) info_view: HelpView,
     pub(crate) volume_slider: HorFillSlider,
     pub(crate) volume_label: Frame,
+    pub(crate) speed_slider: HorFillSlider,
+    pub(crate) speed_label: Frame,
     pub(crate) time_slider: HorFillSlider,
     pub(crate) time_label: Frame,
     pub(crate) helpform: Option<html_form::Form>,
-    pub(crate) player: Soloud,
-    pub(crate) wav: Wav,
-    pub(crate) handle: soloud::Handle,
-    pub(crate) playing: bool,
-    pub(crate) first_to_play: bool,
+    pub(crate) engine: Engine,
+    pub(crate) playing_shown: bool, // the play/pause button's state
+    pub(crate) ticking: bool,
+    pub(crate) play_on_startup: bool,
+    pub(crate) scripts: Vec<PathBuf>, // run on startup
+    pub(crate) last_command: String,
+    pub(crate) sleep_at: Option<Instant>,
+    pub(crate) sleep_secs: f64,
+    pub(crate) sleep_at_end: bool,
+    pub(crate) saved: Instant, // when the config was last saved
     pub(crate) sender: Sender<Action>,
     pub(crate) receiver: Receiver<Action>,
 }
 
 impl Application {
-    pub fn new() -> Self {
+    pub fn new(play_on_startup: bool, scripts: Vec<PathBuf>) -> Self {
         let app = App::default().with_scheme(Scheme::Gleam);
         let (sender, receiver) = channel::<Action>();
         let mut widgets = main_window::make(sender);
         main_window::add_event_handlers(&mut widgets.main_window, sender);
         widgets.main_window.show();
-        let mut player =
-            Soloud::default().expect("Cannot access audio backend");
-        player.set_pause_all(true);
-        let load = main_window::update_widgets_from_config(&mut widgets);
+        let engine = Engine::new();
+        main_window::update_widgets_from_config(&mut widgets);
         let mut volume_slider = widgets.volume_slider.clone();
+        let mut speed_slider = widgets.speed_slider.clone();
         let mut time_slider = widgets.time_slider.clone();
+        main_window::add_chapter_marks(
+            &mut time_slider,
+            engine.chapters.clone(),
+        );
         let mut app = Self {
             app,
             main_window: widgets.main_window,
+            load_button: widgets.load_button,
             prev_button: widgets.prev_button,
             replay_button: widgets.replay_button,
+            rewind_button: widgets.rewind_button,
             play_pause_button: widgets.play_pause_button,
             next_button: widgets.next_button,
+            repeat_menu_button: widgets.repeat_menu_button,
+            chapters_menu_button: widgets.chapters_menu_button,
             history_menu_button: widgets.history_menu_button,
             bookmarks_menu_button: widgets.bookmarks_menu_button,
             add_bookmark_button: widgets.add_bookmark_button,
@@ -73,14 +92,21 @@ impl Application {
             info_view: widgets.info_view,
             volume_slider: widgets.volume_slider,
             volume_label: widgets.volume_label,
+            speed_slider: widgets.speed_slider,
+            speed_label: widgets.speed_label,
             time_slider: widgets.time_slider,
             time_label: widgets.time_label,
             helpform: None,
-            player,
-            wav: Wav::default(),
-            handle: unsafe { soloud::Handle::from_raw(0) },
-            playing: false,
-            first_to_play: true,
+            engine,
+            playing_shown: false,
+            ticking: false,
+            play_on_startup,
+            scripts,
+            last_command: String::new(),
+            sleep_at: None,
+            saved: Instant::now(),
+            sleep_secs: 30.0 * 60.0,
+            sleep_at_end: false,
             sender,
             receiver,
         };
@@ -91,61 +117,104 @@ impl Application {
         });
         #[allow(clippy::clone_on_copy)]
         let sender = sender.clone();
+        speed_slider.set_callback(move |_| {
+            sender.send(Action::SpeedUpdate);
+        });
+        #[allow(clippy::clone_on_copy)]
+        let sender = sender.clone();
         time_slider.set_callback(move |_| {
             sender.send(Action::TimeUpdate);
         });
-        if load {
-            #[allow(clippy::clone_on_copy)]
-            let sender = sender.clone();
-            app::add_timeout3(0.01, move |_| {
-                sender.send(Action::OnStartup);
-            });
-        } else {
-            app.update_ui();
-        }
+        #[allow(clippy::clone_on_copy)]
+        let sender = sender.clone();
+        app::add_timeout3(0.01, move |_| {
+            sender.send(Action::OnStartup);
+        });
+        app.populate_repeat_menu_button();
         app
     }
 
     pub fn run(&mut self) {
         while self.app.wait() {
             if let Some(action) = self.receiver.recv() {
-                match action {
-                    Action::OnStartup => self.on_startup(),
-                    Action::OnBookmarkMenu => self.on_bookmark_menu(),
-                    Action::OnHistoryMenu => self.on_history_menu(),
-                    Action::OnMenuMenu => self.on_menu_menu(),
-                    Action::Load => self.on_open(),
-                    Action::Previous => self.on_previous(),
-                    Action::Replay => self.on_replay(),
-                    Action::PlayOrPause => self.on_play_or_pause(),
-                    Action::LoadHistoryTrack => {
-                        self.on_load_history_track()
-                    }
-                    Action::LoadBookmarkedTrack => {
-                        self.on_load_bookmarked_track()
-                    }
-                    Action::SpacePressed => self.on_space_pressed(),
-                    Action::Tick => self.on_tick(),
-                    Action::MainMenu => {
-                        self.menu_button.popup();
-                    }
-                    Action::Next => self.on_next(),
-                    Action::VolumeDown => self.on_volume_down(),
-                    Action::VolumeUp => self.on_volume_up(),
-                    Action::VolumeUpdate => self.on_volume_update(),
-                    Action::TimeUpdate => self.on_time_update(),
-                    Action::AddToHistory => self.on_add_to_history(),
-                    Action::AddBookmark => self.on_add_bookmark(),
-                    Action::DeleteBookmark => self.on_delete_bookmark(),
-                    Action::Options => self.on_options(),
-                    Action::About => self.on_about(),
-                    Action::Help => self.on_help(),
-                    Action::Quit => self.on_quit(),
-                }
+                self.dispatch(action);
             }
         }
     }
 
+    pub(crate) fn dispatch(&mut self, action: Action) {
+        match action {
+            Action::OnStartup => self.on_startup(),
+            Action::HandleCommands => self.on_handle_commands(),
+            Action::OnBookmarkMenu => self.on_bookmark_menu(),
+            Action::OnChapterMenu => self.on_chapter_menu(),
+            Action::OnHistoryMenu => self.on_history_menu(),
+            Action::OnMenuMenu => self.on_menu_menu(),
+            Action::OnRepeatMenu => self.on_repeat_menu(),
+            Action::ClearLoop
+            | Action::Repeat
+            | Action::RepeatAll
+            | Action::RepeatOff
+            | Action::RepeatTrack
+            | Action::SetLoopA
+            | Action::SetLoopB
+            | Action::Shuffle
+            | Action::ShuffleAlbums
+            | Action::ShuffleOff
+            | Action::ShuffleTracks
+            | Action::ToggleLoop => self.on_repeat_action(action),
+            Action::Load => self.on_open(),
+            Action::OpenFolder => self.on_open_folder(),
+            Action::Enqueue => self.on_enqueue(),
+            Action::ShowQueue => self.on_queue(),
+            Action::ClearQueue => self.engine.clear_queue(),
+            Action::Previous => self.on_previous(),
+            Action::Replay => self.on_replay(),
+            Action::RunCommand => self.on_run_command(),
+            Action::Rewind
+            | Action::SkipBack
+            | Action::SkipBackLong
+            | Action::SkipBackMedium
+            | Action::SkipForward
+            | Action::SkipForwardLong
+            | Action::SkipForwardMedium => self.on_skip(action),
+            Action::PlayOrPause => self.on_play_or_pause(),
+            Action::LoadHistoryTrack => self.on_load_history_track(),
+            Action::LoadBookmarkedTrack => self.on_load_bookmarked_track(),
+            Action::LoadChapter => self.on_load_chapter(),
+            Action::SpacePressed => self.on_space_pressed(),
+            Action::Tick => self.on_tick(),
+            Action::MainMenu => {
+                self.menu_button.popup();
+            }
+            Action::Next => self.on_next(),
+            Action::NextTrack => self.on_next_track(),
+            Action::VolumeDown => self.on_volume_down(),
+            Action::VolumeUp => self.on_volume_up(),
+            Action::VolumeUpdate => self.on_volume_update(),
+            Action::SpeedDown => self.on_speed_down(),
+            Action::SpeedNormal => self.on_speed_normal(),
+            Action::SpeedUp => self.on_speed_up(),
+            Action::SpeedUpdate => self.on_speed_update(),
+            Action::TimeUpdate => self.on_time_update(),
+            Action::AddToHistory => self.on_add_to_history(),
+            Action::AddBookmark => self.on_add_bookmark(),
+            Action::DeleteBookmark => self.on_delete_bookmark(),
+            Action::SavePlaylist => self.on_save_playlist(),
+            Action::ClosePlaylist => self.on_close_playlist(),
+            Action::SleepTimer => self.on_sleep_timer(),
+            Action::UserActivity => self.on_user_activity(),
+            Action::Options => self.on_options(),
+            Action::SearchLibrary => self.on_search_library(),
+            Action::RescanLibrary => self.on_rescan_library(),
+            Action::LibraryRescanned => self.on_library_rescanned(),
+            Action::Relocate => self.on_relocate(),
+            Action::About => self.on_about(),
+            Action::Help => self.on_help(),
+            Action::Quit => self.on_quit(),
+        }
+    }
+
     pub fn populate_history_menu_button(&mut self) {
         main_window::populate_history_menu_button(
             &mut self.history_menu_button,
@@ -163,17 +232,19 @@ impl Application {
     }
 
     pub fn update_ui(&mut self) {
-        let (has_track, has_history, has_bookmarks) = {
+        let (has_track, has_history, has_bookmarks, has_track_bookmarks) = {
             let config = CONFIG.get().read().unwrap();
             (
                 config.track.exists(),
                 !config.history.is_empty(),
                 !config.bookmarks.is_empty(),
+                config.bookmarks.iter().any(|b| b.track == config.track),
             )
         };
         if has_track {
             self.prev_button.activate();
             self.replay_button.activate();
+            self.rewind_button.activate();
             self.play_pause_button.activate();
             self.next_button.activate();
             self.time_slider.activate();
@@ -181,11 +252,17 @@ impl Application {
         } else {
             self.prev_button.deactivate();
             self.replay_button.deactivate();
+            self.rewind_button.deactivate();
             self.play_pause_button.deactivate();
             self.next_button.deactivate();
             self.time_slider.deactivate();
             self.add_bookmark_button.deactivate();
         }
+        if has_track && !self.engine.chapters.borrow().is_empty() {
+            self.chapters_menu_button.activate();
+        } else {
+            self.chapters_menu_button.deactivate();
+        }
         if has_history {
             self.history_menu_button.activate();
         } else {
@@ -193,9 +270,12 @@ impl Application {
         }
         if has_bookmarks {
             self.bookmarks_menu_button.activate();
-            self.delete_bookmark_button.activate();
         } else {
             self.bookmarks_menu_button.deactivate();
+        }
+        if has_track_bookmarks {
+            self.delete_bookmark_button.activate();
+        } else {
             self.delete_bookmark_button.deactivate();
         }
     }
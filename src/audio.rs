@@ -0,0 +1,83 @@
+// Copyright © 2021-22 Mark Summerfield. All rights reserved.
+// License: GPLv3
+
+use soloud::{
+    audio::{Wav, WavStream},
+    prelude::*,
+    Handle, Soloud, SoloudError,
+};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver},
+    thread,
+};
+
+// Small tracks are decoded into memory; large ones (e.g., long audio
+// books) are decoded on the fly as they play
+pub enum Audio {
+    Wav(Wav),
+    Stream(WavStream),
+}
+
+impl Audio {
+    // Streams the track if it is at least stream_size MB (so always
+    // streams if stream_size is 0)
+    pub fn load(
+        track: &Path,
+        stream_size: u64,
+    ) -> Result<Self, SoloudError> {
+        let size = match fs::metadata(track) {
+            Ok(metadata) => metadata.len(),
+            Err(_) => 0,
+        };
+        if size >= stream_size * 1024 * 1024 {
+            let mut stream = WavStream::default();
+            stream.load(track)?;
+            Ok(Audio::Stream(stream))
+        } else {
+            let mut wav = Wav::default();
+            wav.load(track)?;
+            Ok(Audio::Wav(wav))
+        }
+    }
+
+    pub fn length(&self) -> f64 {
+        match self {
+            Audio::Wav(wav) => wav.length(),
+            Audio::Stream(stream) => stream.length(),
+        }
+    }
+
+    pub fn play(&self, player: &Soloud) -> Handle {
+        match self {
+            Audio::Wav(wav) => player.play(wav),
+            Audio::Stream(stream) => player.play(stream),
+        }
+    }
+}
+
+// An unplayed source is only touched by whichever thread owns it, so it
+// can be loaded in one thread and played in another
+unsafe impl Send for Audio {}
+
+// Loads the track in a background thread (so that decoding a large track
+// doesn't hold up playback); the result arrives on the returned receiver
+pub fn load_in_background(
+    track: PathBuf,
+    stream_size: u64,
+) -> Receiver<Option<(PathBuf, Audio)>> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let audio = Audio::load(&track, stream_size).ok();
+        // Ignore error: the preload was dropped while loading
+        let _ = sender.send(audio.map(|audio| (track, audio)));
+    });
+    receiver
+}
+
+impl Default for Audio {
+    fn default() -> Self {
+        Audio::Wav(Wav::default())
+    }
+}
@@ -0,0 +1,379 @@
+// Copyright © 2021-22 Mark Summerfield. All rights reserved.
+// License: GPLv3
+
+use crate::util;
+use lofty::{self, ItemKey, ItemValue, Probe};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, Read},
+    path::Path,
+};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Chapter {
+    pub title: String,
+    pub pos: f64, // secs
+}
+
+// Returns the track's chapters in order (or an empty Vec if it has none);
+// supports ID3v2 CHAP frames and Vorbis CHAPTERxxx comments
+pub fn get_track_chapters(track: &Path) -> Vec<Chapter> {
+    let chapters = match File::open(track)
+        .and_then(|mut file| get_id3_chapters(&mut file))
+    {
+        Ok(chapters) if !chapters.is_empty() => chapters,
+        _ => get_vorbis_chapters(track),
+    };
+    tidied(chapters)
+}
+
+// Returns the chapters in order without duplicates and all with titles
+fn tidied(mut chapters: Vec<Chapter>) -> Vec<Chapter> {
+    chapters.retain(|chapter| chapter.pos >= 0.0);
+    chapters.sort_by(|a, b| a.pos.total_cmp(&b.pos));
+    chapters.dedup_by(|a, b| (a.pos - b.pos).abs() < 0.001);
+    for (i, chapter) in chapters.iter_mut().enumerate() {
+        if chapter.title.is_empty() {
+            chapter.title = format!("Chapter {}", i + 1);
+        }
+    }
+    chapters
+}
+
+// Reads the chapters from the ID3v2 tag at the start of the data (if
+// there is one); CTOC frames only give the order of the CHAP frames, which
+// is the same as their time order in practice, so they are ignored
+fn get_id3_chapters(reader: &mut impl Read) -> io::Result<Vec<Chapter>> {
+    let mut header = [0u8; 10];
+    reader.read_exact(&mut header)?;
+    let version = header[3];
+    if &header[..3] != b"ID3" || version < 3 {
+        return Ok(vec![]); // ID3v2.2 has no chapters
+    }
+    let flags = header[5];
+    // A truncated tag is read as far as it goes; and a corrupt size can't
+    // make us allocate more than there is
+    let mut data = vec![];
+    reader.take(syncsafe(&header[6..10]) as u64).read_to_end(&mut data)?;
+    if flags & 0x80 != 0 {
+        data = unsynchronize(&data);
+    }
+    let mut i = 0;
+    if flags & 0x40 != 0 && data.len() >= 4 {
+        // Skip the extended header
+        i = if version == 3 {
+            (be_u32(&data[..4]) as usize).saturating_add(4)
+        } else {
+            syncsafe(&data[..4])
+        };
+    }
+    let mut chapters = vec![];
+    for (id, body) in id3_frames(&data[i.min(data.len())..], version) {
+        if id == b"CHAP" {
+            if let Some(chapter) = parse_chap_frame(body, version) {
+                chapters.push(chapter);
+            }
+        }
+    }
+    Ok(chapters)
+}
+
+fn id3_frames(data: &[u8], version: u8) -> Vec<(&[u8], &[u8])> {
+    let mut frames = vec![];
+    let mut i = 0;
+    while i + 10 <= data.len() && data[i] != 0 {
+        let size = if version == 4 {
+            syncsafe(&data[i + 4..i + 8])
+        } else {
+            be_u32(&data[i + 4..i + 8]) as usize
+        };
+        let end = size.saturating_add(i + 10).min(data.len());
+        frames.push((&data[i..i + 4], &data[i + 10..end]));
+        i = end;
+    }
+    frames
+}
+
+// CHAP: element ID\0, start ms, end ms, start offset, end offset, then
+// subframes, of which only the title (TIT2) is used
+fn parse_chap_frame(body: &[u8], version: u8) -> Option<Chapter> {
+    let start = body.iter().position(|&b| b == 0)? + 1;
+    if body.len() < start + 16 {
+        return None;
+    }
+    let pos = be_u32(&body[start..start + 4]) as f64 / 1000.0;
+    let mut title = String::new();
+    for (id, subbody) in id3_frames(&body[start + 16..], version) {
+        if id == b"TIT2" {
+            title = decode_id3_text(subbody);
+        }
+    }
+    Some(Chapter { title, pos })
+}
+
+fn decode_id3_text(body: &[u8]) -> String {
+    if body.is_empty() {
+        return String::new();
+    }
+    let text = &body[1..];
+    let text = match body[0] {
+        0 => text.iter().map(|&b| b as char).collect(), // Latin-1
+        1 | 2 => {
+            let big_endian =
+                body[0] == 2 || text.starts_with(&[0xFE, 0xFF]);
+            let text = if text.starts_with(&[0xFE, 0xFF])
+                || text.starts_with(&[0xFF, 0xFE])
+            {
+                &text[2..]
+            } else {
+                text
+            };
+            let units: Vec<u16> = text
+                .chunks_exact(2)
+                .map(|pair| {
+                    if big_endian {
+                        u16::from_be_bytes([pair[0], pair[1]])
+                    } else {
+                        u16::from_le_bytes([pair[0], pair[1]])
+                    }
+                })
+                .collect();
+            String::from_utf16_lossy(&units)
+        }
+        _ => String::from_utf8_lossy(text).to_string(),
+    };
+    text.trim_end_matches('\0').trim().to_string()
+}
+
+fn syncsafe(bytes: &[u8]) -> usize {
+    bytes.iter().fold(0, |size, &b| (size << 7) | (b & 0x7F) as usize)
+}
+
+fn unsynchronize(data: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(data.len());
+    for (i, &b) in data.iter().enumerate() {
+        if !(b == 0 && i > 0 && data[i - 1] == 0xFF) {
+            result.push(b);
+        }
+    }
+    result
+}
+
+fn be_u32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn get_vorbis_chapters(track: &Path) -> Vec<Chapter> {
+    let tagged_file = match Probe::open(track)
+        .and_then(|probe| probe.guess_file_type())
+        .and_then(|probe| probe.read(false))
+    {
+        Ok(tagged_file) => tagged_file,
+        Err(_) => return vec![],
+    };
+    let mut comments = vec![];
+    for tag in tagged_file.tags() {
+        for item in tag.items() {
+            if let (ItemKey::Unknown(key), ItemValue::Text(value)) =
+                (item.key(), item.value())
+            {
+                comments.push((key.as_str(), value.as_str()));
+            }
+        }
+    }
+    parse_vorbis_chapters(&comments)
+}
+
+// Vorbis comments: CHAPTER001=00:00:00.000 and CHAPTER001NAME=Title
+fn parse_vorbis_chapters(comments: &[(&str, &str)]) -> Vec<Chapter> {
+    let mut times = HashMap::new();
+    let mut titles = HashMap::new();
+    for (key, value) in comments {
+        let key = key.to_uppercase();
+        if let Some(number) = key.strip_prefix("CHAPTER") {
+            if let Some(number) = number.strip_suffix("NAME") {
+                titles.insert(number.to_string(), value.to_string());
+            } else if let Some(pos) = util::parse_time(value) {
+                times.insert(number.to_string(), pos);
+            }
+        }
+    }
+    times
+        .into_iter()
+        .map(|(number, pos)| Chapter {
+            title: titles.remove(&number).unwrap_or_default(),
+            pos,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn syncsafe_bytes(size: u32) -> [u8; 4] {
+        [
+            (size >> 21) as u8 & 0x7F,
+            (size >> 14) as u8 & 0x7F,
+            (size >> 7) as u8 & 0x7F,
+            size as u8 & 0x7F,
+        ]
+    }
+
+    fn frame(id: &[u8; 4], body: &[u8], version: u8) -> Vec<u8> {
+        let size = body.len() as u32;
+        let mut data = id.to_vec();
+        if version == 4 {
+            data.extend(syncsafe_bytes(size));
+        } else {
+            data.extend(size.to_be_bytes());
+        }
+        data.extend([0, 0]); // flags
+        data.extend(body);
+        data
+    }
+
+    fn chap_frame(start_ms: u32, title: &str, version: u8) -> Vec<u8> {
+        let mut body = b"chp\0".to_vec();
+        body.extend(start_ms.to_be_bytes());
+        body.extend((start_ms + 1000).to_be_bytes());
+        body.extend([0xFF; 8]); // no byte offsets
+        let mut text = vec![3]; // UTF-8
+        text.extend(title.as_bytes());
+        body.extend(frame(b"TIT2", &text, version));
+        frame(b"CHAP", &body, version)
+    }
+
+    fn tag(version: u8, frames: &[Vec<u8>]) -> Vec<u8> {
+        let mut body = frames.concat();
+        body.extend([0; 16]); // padding
+        let mut data = b"ID3".to_vec();
+        data.extend([version, 0, 0]);
+        data.extend(syncsafe_bytes(body.len() as u32));
+        data.extend(body);
+        data
+    }
+
+    fn id3_chapters(data: &[u8]) -> io::Result<Vec<Chapter>> {
+        get_id3_chapters(&mut Cursor::new(data))
+    }
+
+    fn chapter(title: &str, pos: f64) -> Chapter {
+        Chapter { title: title.to_string(), pos }
+    }
+
+    #[test]
+    fn id3v3_chapters() {
+        let data = tag(
+            3,
+            &[chap_frame(0, "Intro", 3), chap_frame(90_500, "Part One", 3)],
+        );
+        assert_eq!(
+            id3_chapters(&data).unwrap(),
+            vec![chapter("Intro", 0.0), chapter("Part One", 90.5)]
+        );
+    }
+
+    #[test]
+    fn id3v4_chapters() {
+        // Long enough for the syncsafe sizes to differ from plain ones
+        let title = "Chapter".repeat(30);
+        let data = tag(4, &[chap_frame(2_000, &title, 4)]);
+        assert_eq!(
+            id3_chapters(&data).unwrap(),
+            vec![chapter(&title, 2.0)]
+        );
+    }
+
+    #[test]
+    fn id3_without_chapters() {
+        let data = tag(3, &[frame(b"TIT2", b"\x03Title", 3)]);
+        assert!(id3_chapters(&data).unwrap().is_empty());
+        assert!(id3_chapters(b"fLaC\0\0\0\0\0\0\0\0").unwrap().is_empty());
+        assert!(id3_chapters(b"ID3\x02\0\0\0\0\0\0").unwrap().is_empty());
+        assert!(id3_chapters(b"ID3").is_err());
+    }
+
+    #[test]
+    fn id3_truncated() {
+        let first = chap_frame(0, "Intro", 3);
+        let data = tag(3, &[first.clone(), chap_frame(5_000, "Two", 3)]);
+        // Cut off in the second frame's header
+        let chapters = id3_chapters(&data[..10 + first.len() + 5]).unwrap();
+        assert_eq!(chapters, vec![chapter("Intro", 0.0)]);
+        // Cut off before the first frame's times
+        assert!(id3_chapters(&data[..10 + 10 + 6]).unwrap().is_empty());
+        // Cut off in the middle of the first frame's title
+        let chapters = id3_chapters(&data[..10 + first.len() - 2]).unwrap();
+        assert_eq!(chapters, vec![chapter("Int", 0.0)]);
+    }
+
+    #[test]
+    fn id3_corrupt_sizes() {
+        let mut data = tag(3, &[chap_frame(1_000, "One", 3)]);
+        data[14..18].copy_from_slice(&[0xFF; 4]); // CHAP frame size
+        assert_eq!(id3_chapters(&data).unwrap(), vec![chapter("One", 1.0)]);
+        data[6..10].copy_from_slice(&[0x7F; 4]); // tag size
+        assert!(id3_chapters(&data).is_ok());
+        let mut data = tag(3, &[chap_frame(1_000, "One", 3)]);
+        data[5] = 0x40; // extended header
+        data[10..14].copy_from_slice(&[0xFF; 4]); // its size
+        assert!(id3_chapters(&data).unwrap().is_empty());
+    }
+
+    #[test]
+    fn id3_text() {
+        assert_eq!(decode_id3_text(b"\x00Caf\xE9\0"), "Café");
+        assert_eq!(decode_id3_text(b"\x01\xFF\xFEH\0i\0\0\0"), "Hi");
+        assert_eq!(decode_id3_text(b"\x02\0H\0i"), "Hi");
+        assert_eq!(decode_id3_text(b"\x03Caf\xC3\xA9"), "Café");
+        assert_eq!(decode_id3_text(b"\x01\xFF"), "");
+        assert_eq!(decode_id3_text(b""), "");
+    }
+
+    #[test]
+    fn id3_unsynchronize() {
+        assert_eq!(
+            unsynchronize(&[0xFF, 0x00, 0xE0, 0x00, 0xFF]),
+            vec![0xFF, 0xE0, 0x00, 0xFF]
+        );
+    }
+
+    #[test]
+    fn vorbis_chapters() {
+        let chapters = tidied(parse_vorbis_chapters(&[
+            ("CHAPTER002", "00:01:30.500"),
+            ("CHAPTER001", "00:00:00.000"),
+            ("CHAPTER001NAME", "Intro"),
+            ("chapter002name", "Part One"),
+            ("CHAPTER003", "not a time"),
+            ("CHAPTER003NAME", "Ignored"),
+            ("CHAPTER004", "00:02:00"),
+            ("TITLE", "Not a chapter"),
+        ]));
+        assert_eq!(
+            chapters,
+            vec![
+                chapter("Intro", 0.0),
+                chapter("Part One", 90.5),
+                chapter("Chapter 3", 120.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn tidied_chapters() {
+        let chapters = tidied(vec![
+            chapter("Later", 10.0),
+            chapter("", 0.0),
+            chapter("Duplicate", 10.0),
+            chapter("Negative", -1.0),
+        ]);
+        assert_eq!(
+            chapters,
+            vec![chapter("Chapter 1", 0.0), chapter("Later", 10.0)]
+        );
+    }
+}
@@ -0,0 +1,380 @@
+// Copyright © 2021-22 Mark Summerfield. All rights reserved.
+// License: GPLv3
+
+use super::CONFIG;
+use crate::config::Config;
+use crate::fixed::{about_text, APPNAME};
+use crate::playlist::{self, Playlist};
+use crate::util;
+use std::{
+    env,
+    ffi::OsString,
+    path::{Path, PathBuf},
+    process,
+};
+
+#[derive(Clone, Debug, Default)]
+pub struct Options {
+    pub paths: Vec<PathBuf>, // tracks, folders, and playlists
+    pub position: Option<f64>,
+    pub volume: Option<f64>,
+    pub paused: bool,
+    pub config: Option<PathBuf>,
+    pub script: Option<PathBuf>,
+    pub headless: bool,
+    pub play_pause: bool, // These four are only for a running instance
+    pub next: bool,
+    pub enqueue: bool,
+    pub quit: bool,
+    pub help: bool, // These two are only for this instance
+    pub version: bool,
+}
+
+impl Options {
+    // Parses the command line; for --help, --version, or an invalid
+    // argument, prints a message and exits
+    pub fn new() -> Self {
+        match Self::try_parse(env::args_os().skip(1)) {
+            Ok(options) if options.help => {
+                print!("{}", usage());
+                process::exit(0);
+            }
+            Ok(options) if options.version => {
+                // No audio backend is started just for this
+                println!("{}", about_text(None));
+                process::exit(0);
+            }
+            Ok(options) => options,
+            Err(message) => fail(&message),
+        }
+    }
+
+    // Parses the arguments (never exits, so is safe for those forwarded
+    // by later instances); returns an error message for an invalid one
+    pub fn try_parse(
+        args: impl Iterator<Item = OsString>,
+    ) -> Result<Self, String> {
+        let mut options = Options::default();
+        let mut args = args;
+        let mut only_paths = false;
+        while let Some(arg) = args.next() {
+            let text = arg.to_string_lossy().to_string();
+            if only_paths || !text.starts_with('-') || text == "-" {
+                options.paths.push(PathBuf::from(arg));
+                continue;
+            }
+            let (name, value) = match text.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_string())),
+                None => (text.as_str(), None),
+            };
+            match name {
+                "--" => only_paths = true,
+                "-h" | "--help" => options.help = true,
+                "-V" | "--version" => options.version = true,
+                "--paused" => options.paused = true,
+                "--headless" => options.headless = true,
+                "--play-pause" => options.play_pause = true,
+                "--next" => options.next = true,
+                "--enqueue" => options.enqueue = true,
+                "--quit" => options.quit = true,
+                "--position" => {
+                    let value = get_value(name, value, &mut args)?;
+                    match util::parse_time(&value) {
+                        Some(pos) => options.position = Some(pos),
+                        None => {
+                            return Err(format!(
+                                "invalid position {value:?}"
+                            ))
+                        }
+                    }
+                }
+                "--volume" => {
+                    let value = get_value(name, value, &mut args)?;
+                    match value.parse::<f64>() {
+                        Ok(volume) if (0.0..=1.0).contains(&volume) => {
+                            options.volume = Some(volume)
+                        }
+                        _ => {
+                            return Err(format!(
+                                "invalid volume {value:?} (use 0.0 to 1.0)"
+                            ))
+                        }
+                    }
+                }
+                "--config" => {
+                    let value = get_value(name, value, &mut args)?;
+                    options.config = Some(PathBuf::from(value));
+                }
+                "--script" => {
+                    let value = get_value(name, value, &mut args)?;
+                    options.script = Some(PathBuf::from(value));
+                }
+                _ => return Err(format!("unrecognized option {name}")),
+            }
+        }
+        Ok(options)
+    }
+
+    // Returns the config's startup script (if any) and then this one
+    pub fn get_scripts(&self) -> Vec<PathBuf> {
+        let mut scripts = vec![];
+        {
+            let config = CONFIG.get().read().unwrap();
+            if !config.startup_script.as_os_str().is_empty() {
+                scripts.push(config.startup_script.clone());
+            }
+        }
+        if let Some(script) = &self.script {
+            scripts.push(script.clone());
+        }
+        scripts
+    }
+
+    // Returns the options as arguments (with absolute paths) for
+    // forwarding to a running instance
+    pub fn to_args(&self) -> Vec<String> {
+        let mut args = vec![];
+        for (flag, name) in [
+            (self.paused, "--paused"),
+            (self.play_pause, "--play-pause"),
+            (self.next, "--next"),
+            (self.enqueue, "--enqueue"),
+            (self.quit, "--quit"),
+        ] {
+            if flag {
+                args.push(name.to_string());
+            }
+        }
+        if let Some(pos) = self.position {
+            args.push(format!("--position={pos}"));
+        }
+        if let Some(volume) = self.volume {
+            args.push(format!("--volume={volume}"));
+        }
+        if let Some(script) = &self.script {
+            args.push(format!(
+                "--script={}",
+                absolute_path(script).to_string_lossy()
+            ));
+        }
+        if !self.paths.is_empty() {
+            args.push("--".to_string());
+            for path in &self.paths {
+                args.push(
+                    absolute_path(path).to_string_lossy().to_string(),
+                );
+            }
+        }
+        args
+    }
+
+    // Puts the command line's tracks (and any volume or position) into
+    // the config; returns true if play should start at once
+    pub fn apply(&self) -> bool {
+        let mut config = CONFIG.get().write().unwrap();
+        if let Some(volume) = self.volume {
+            config.volume = volume;
+        }
+        if let Some(track) = self.apply_playlist(&mut config) {
+            if config.track != track {
+                config.pos = config.position_for(&track);
+                config.track = track;
+            }
+        }
+        if let Some(pos) = self.position {
+            config.pos = pos;
+        }
+        !self.paused && (!self.paths.is_empty() || self.position.is_some())
+    }
+
+    // Makes the tracks the playlist if there are several of them (or if a
+    // playlist was given); returns the first track
+    pub fn apply_playlist(&self, config: &mut Config) -> Option<PathBuf> {
+        let (tracks, loaded_playlist) = self.get_tracks(config.recursive);
+        let track = tracks.first().cloned()?;
+        if let Some(playlist) = loaded_playlist {
+            config.playlist = Some(playlist);
+        } else if tracks.len() > 1 {
+            config.playlist = Some(Playlist::new("", tracks));
+        }
+        if let Some(playlist) = &mut config.playlist {
+            playlist.set_current(&track);
+        }
+        Some(track)
+    }
+
+    // Returns the tracks to play (folders are expanded to their audio
+    // files, and if recursive their subfolders', and playlists to their
+    // tracks) and, if the only path given is a playlist, that playlist
+    pub fn get_tracks(
+        &self,
+        recursive: bool,
+    ) -> (Vec<PathBuf>, Option<Playlist>) {
+        let mut tracks = vec![];
+        let mut loaded_playlist = None;
+        for path in &self.paths {
+            let path = absolute_path(path);
+            if path.is_dir() {
+                tracks.extend(util::get_dir_tracks(&path, recursive));
+            } else if playlist::is_playlist(&path) {
+                match Playlist::load(&path) {
+                    Ok(playlist) => {
+                        tracks.extend(
+                            playlist.tracks.iter().map(|t| t.track.clone()),
+                        );
+                        if self.paths.len() == 1 {
+                            loaded_playlist = Some(playlist);
+                        }
+                    }
+                    Err(err) => eprintln!(
+                        "{APPNAME}: failed to read playlist {path:?}: {err}"
+                    ),
+                }
+            } else if path.is_file() {
+                tracks.push(path);
+            } else {
+                eprintln!("{APPNAME}: cannot open {path:?}");
+            }
+        }
+        (tracks, loaded_playlist)
+    }
+}
+
+fn get_value(
+    name: &str,
+    value: Option<String>,
+    args: &mut impl Iterator<Item = OsString>,
+) -> Result<String, String> {
+    match value {
+        Some(value) => Ok(value),
+        None => match args.next() {
+            Some(value) => Ok(value.to_string_lossy().to_string()),
+            None => Err(format!("{name} requires a value")),
+        },
+    }
+}
+
+fn absolute_path(path: &Path) -> PathBuf {
+    match path.canonicalize() {
+        Ok(path) => path,
+        Err(_) => path.to_path_buf(),
+    }
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("{APPNAME}: {message}\nTry --help for more information.");
+    process::exit(2);
+}
+
+fn usage() -> String {
+    format!(
+        "usage: {} [OPTIONS] [FILE|DIR|PLAYLIST …]
+
+Plays the given tracks, the audio files in the given folders, and the
+tracks in the given playlists (.m3u, .m3u8, or .pls). With no arguments
+the last track played is loaded ready to resume. If AMP is already
+running the arguments are passed to it.
+
+options:
+  --position TIME  start from TIME (e.g., 90, 1:30, or 1h2m3s)
+  --volume N       set the volume from 0.0 to 1.0
+  --paused         load the first track but don't start playing
+  --config PATH    use PATH as the config file
+  --script PATH    run the commands in PATH once the track is loaded
+  --headless       play without a window; control playback with the
+                   options below (or MPRIS on Linux)
+  --play-pause     play or pause the running instance
+  --next           go to the running instance's next chapter or track
+  --enqueue        add the tracks to the end of the running instance's
+                   playlist rather than playing them
+  --quit           save the running instance's state and quit it
+  -h, --help       show this help and exit
+  -V, --version    show the version and exit
+",
+        env!("CARGO_PKG_NAME")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        Options::try_parse(args.iter().map(OsString::from))
+    }
+
+    #[test]
+    fn values_inline_or_separate() {
+        let options =
+            parse(&["--position=1:30", "--volume", "0.5", "a.mp3"])
+                .unwrap();
+        assert_eq!(options.position, Some(90.0));
+        assert_eq!(options.volume, Some(0.5));
+        assert_eq!(options.paths, [PathBuf::from("a.mp3")]);
+        let options = parse(&[
+            "--position",
+            "90",
+            "--volume=0.5",
+            "--config=a b.ini",
+        ])
+        .unwrap();
+        assert_eq!(options.position, Some(90.0));
+        assert_eq!(options.volume, Some(0.5));
+        assert_eq!(options.config, Some(PathBuf::from("a b.ini")));
+    }
+
+    #[test]
+    fn flags() {
+        let options = parse(&["--paused", "-h", "--next", "-V"]).unwrap();
+        assert!(options.paused && options.help && options.next);
+        assert!(options.version && !options.quit && !options.headless);
+    }
+
+    #[test]
+    fn paths() {
+        let options =
+            parse(&["a.mp3", "-", "--paused", "--", "--quit", "-b"])
+                .unwrap();
+        assert!(options.paused && !options.quit);
+        let paths: Vec<PathBuf> = ["a.mp3", "-", "--quit", "-b"]
+            .iter()
+            .map(PathBuf::from)
+            .collect();
+        assert_eq!(options.paths, paths);
+    }
+
+    #[test]
+    fn errors() {
+        for args in [
+            &["--volume"][..],
+            &["--position"],
+            &["--config"],
+            &["--script"],
+            &["--volume", "1.5"],
+            &["--volume=-0.1"],
+            &["--volume", "loud"],
+            &["--position", "soon"],
+            &["--position=inf"],
+            &["--bogus"],
+            &["-x"],
+        ] {
+            assert!(parse(args).is_err(), "{args:?}");
+        }
+    }
+
+    #[test]
+    fn forwarded_round_trip() {
+        let options =
+            parse(&["--paused", "--volume", "0.25", "--", "-a.mp3"])
+                .unwrap();
+        let args = options.to_args();
+        let forwarded = parse(
+            &args.iter().map(|arg| arg.as_str()).collect::<Vec<&str>>(),
+        )
+        .unwrap();
+        assert!(forwarded.paused);
+        assert_eq!(forwarded.volume, Some(0.25));
+        assert_eq!(forwarded.paths.len(), 1);
+        assert!(forwarded.paths[0].ends_with("-a.mp3"));
+    }
+}
@@ -0,0 +1,385 @@
+// Copyright © 2021-22 Mark Summerfield. All rights reserved.
+// License: GPLv3
+
+// Commands are Actions that may carry arguments. They come from later
+// instances, MPRIS, and the remote control, and can be typed into the
+// command palette or read from a script file: one or more per line,
+// separated by ';', with '#' starting a comment. Arguments containing
+// spaces must be quoted, e.g., load "~/Music/Some Album"
+
+use crate::cli::Options;
+use crate::fixed::Action;
+use crate::util;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+#[derive(Clone, Debug)]
+pub enum Command {
+    Args(Options), // Default options just raise the window
+    Do(Action),    // An action that takes no arguments
+    Play,
+    Pause,
+    Seek(f64),   // secs
+    Skip(f64),   // secs; negative to go back
+    Volume(f64), // 0.0 to 1.0
+    Speed(f64),
+    Bookmark(String),           // note
+    Queue(Vec<PathBuf>), // tracks, folders, or playlists to play next
+    Relocate(PathBuf, PathBuf), // old and new folders for missing tracks
+}
+
+pub static COMMAND_HELP: &str = "play • pause • play-pause • next • \
+previous • replay • seek TIME • seek +TIME • seek -TIME • skip-back • \
+skip-forward • skip-back-medium • skip-forward-medium • skip-back-long • \
+skip-forward-long • rewind • volume N (0.0 to 1.0 or N%) • speed N • \
+load PATH… • enqueue PATH… • queue PATH… • show-queue • clear-queue • \
+bookmark [NOTE] • add-bookmark • relocate [OLD NEW] • volume-up • \
+volume-down • speed-up • speed-down • speed-normal • loop-a • loop-b • \
+loop • clear-loop • repeat • repeat-off • repeat-track • repeat-all • \
+shuffle • shuffle-off • shuffle-tracks • shuffle-albums • open • \
+open-folder • save-playlist • close-playlist • search-library • \
+rescan-library • options • help • about • quit";
+
+// The commands that are just an Action
+static ACTIONS: [(&str, Action); 41] = [
+    ("about", Action::About),
+    ("add-bookmark", Action::AddBookmark),
+    ("clear-loop", Action::ClearLoop),
+    ("clear-queue", Action::ClearQueue),
+    ("close-playlist", Action::ClosePlaylist),
+    ("help", Action::Help),
+    ("loop", Action::ToggleLoop),
+    ("loop-a", Action::SetLoopA),
+    ("loop-b", Action::SetLoopB),
+    ("next", Action::Next),
+    ("open", Action::Load),
+    ("open-folder", Action::OpenFolder),
+    ("options", Action::Options),
+    ("play-pause", Action::PlayOrPause),
+    ("previous", Action::Previous),
+    ("quit", Action::Quit),
+    ("repeat", Action::Repeat),
+    ("repeat-all", Action::RepeatAll),
+    ("repeat-off", Action::RepeatOff),
+    ("repeat-track", Action::RepeatTrack),
+    ("replay", Action::Replay),
+    ("rescan-library", Action::RescanLibrary),
+    ("rewind", Action::Rewind),
+    ("save-playlist", Action::SavePlaylist),
+    ("search-library", Action::SearchLibrary),
+    ("show-queue", Action::ShowQueue),
+    ("shuffle", Action::Shuffle),
+    ("shuffle-albums", Action::ShuffleAlbums),
+    ("shuffle-off", Action::ShuffleOff),
+    ("shuffle-tracks", Action::ShuffleTracks),
+    ("skip-back", Action::SkipBack),
+    ("skip-back-long", Action::SkipBackLong),
+    ("skip-back-medium", Action::SkipBackMedium),
+    ("skip-forward", Action::SkipForward),
+    ("skip-forward-long", Action::SkipForwardLong),
+    ("skip-forward-medium", Action::SkipForwardMedium),
+    ("speed-down", Action::SpeedDown),
+    ("speed-normal", Action::SpeedNormal),
+    ("speed-up", Action::SpeedUp),
+    ("volume-down", Action::VolumeDown),
+    ("volume-up", Action::VolumeUp),
+];
+
+impl Command {
+    // Parses one or more commands; relative paths are relative to dir
+    pub fn parse(text: &str, dir: &Path) -> Result<Vec<Command>, String> {
+        let mut commands = vec![];
+        for line in text.lines() {
+            for words in split_line(line)? {
+                commands.push(parse_command(&words, dir)?);
+            }
+        }
+        Ok(commands)
+    }
+}
+
+// Reads the commands in the given script file; relative paths are
+// relative to the script's folder
+pub fn read_script(filename: &Path) -> Result<Vec<Command>, String> {
+    let text = fs::read_to_string(filename).map_err(|err| {
+        format!("failed to read script {filename:?}: {err}")
+    })?;
+    let dir = filename.parent().unwrap_or_else(|| Path::new("."));
+    let mut commands = vec![];
+    for (i, line) in text.lines().enumerate() {
+        match Command::parse(line, dir) {
+            Ok(line_commands) => commands.extend(line_commands),
+            Err(err) => {
+                return Err(format!("{filename:?} line {}: {err}", i + 1))
+            }
+        }
+    }
+    Ok(commands)
+}
+
+fn parse_command(words: &[String], dir: &Path) -> Result<Command, String> {
+    let name = words[0].to_lowercase();
+    let args = &words[1..];
+    let command = match name.as_str() {
+        "play" => Command::Play,
+        "pause" => Command::Pause,
+        "seek" => {
+            let arg = one_arg(&name, args)?;
+            let invalid = || format!("invalid time {arg:?}");
+            if let Some(time) = arg.strip_prefix('+') {
+                Command::Skip(util::parse_time(time).ok_or_else(invalid)?)
+            } else if let Some(time) = arg.strip_prefix('-') {
+                Command::Skip(-util::parse_time(time).ok_or_else(invalid)?)
+            } else {
+                Command::Seek(util::parse_time(arg).ok_or_else(invalid)?)
+            }
+        }
+        "volume" => {
+            let arg = one_arg(&name, args)?;
+            let volume = match arg.strip_suffix('%') {
+                Some(percent) => percent.parse::<f64>().map(|n| n / 100.0),
+                None => arg.parse::<f64>(),
+            };
+            match volume {
+                Ok(volume) if (0.0..=1.0).contains(&volume) => {
+                    Command::Volume(volume)
+                }
+                _ => return Err(format!("invalid volume {arg:?}")),
+            }
+        }
+        "speed" => {
+            let arg = one_arg(&name, args)?;
+            match arg.parse::<f64>() {
+                Ok(speed) if speed > 0.0 => Command::Speed(speed),
+                _ => return Err(format!("invalid speed {arg:?}")),
+            }
+        }
+        "load" | "enqueue" => {
+            if args.is_empty() {
+                return Err(format!("{name} requires at least one path"));
+            }
+            Command::Args(Options {
+                paths: args.iter().map(|arg| get_path(arg, dir)).collect(),
+                enqueue: name == "enqueue",
+                ..Default::default()
+            })
+        }
+        "queue" => {
+            if args.is_empty() {
+                return Err(format!("{name} requires at least one path"));
+            }
+            Command::Queue(
+                args.iter().map(|arg| get_path(arg, dir)).collect(),
+            )
+        }
+        "bookmark" => Command::Bookmark(args.join(" ")),
+        // Without arguments the folders are asked for
+        "relocate" => match args {
+            [] => Command::Do(Action::Relocate),
+            [old, new] => {
+                Command::Relocate(get_path(old, dir), get_path(new, dir))
+            }
+            _ => {
+                return Err(format!("{name} requires two folders or none"))
+            }
+        },
+        _ => match ACTIONS.iter().find(|(action, _)| *action == name) {
+            Some((_, action)) => {
+                if !args.is_empty() {
+                    return Err(format!("{name} takes no arguments"));
+                }
+                Command::Do(*action)
+            }
+            None => return Err(format!("unrecognized command {name:?}")),
+        },
+    };
+    Ok(command)
+}
+
+fn one_arg<'a>(name: &str, args: &'a [String]) -> Result<&'a str, String> {
+    match args {
+        [arg] => Ok(arg),
+        _ => Err(format!("{name} requires one argument")),
+    }
+}
+
+fn get_path(arg: &str, dir: &Path) -> PathBuf {
+    let path = match arg.strip_prefix("~/") {
+        Some(rest) => match dirs::home_dir() {
+            Some(home) => home.join(rest),
+            None => PathBuf::from(arg),
+        },
+        None => PathBuf::from(arg),
+    };
+    if path.is_absolute() {
+        path
+    } else {
+        dir.join(path)
+    }
+}
+
+// Splits a line into commands (separated by ';') each of which is a list
+// of words; words may be quoted with " or '
+fn split_line(line: &str) -> Result<Vec<Vec<String>>, String> {
+    let mut commands = vec![];
+    let mut words = vec![];
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut quote = None;
+    for c in line.chars() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => word.push(c),
+            None => match c {
+                '"' | '\'' => {
+                    quote = Some(c);
+                    in_word = true;
+                }
+                '#' if !in_word => break,
+                ';' => {
+                    if in_word {
+                        words.push(std::mem::take(&mut word));
+                        in_word = false;
+                    }
+                    if !words.is_empty() {
+                        commands.push(std::mem::take(&mut words));
+                    }
+                }
+                c if c.is_whitespace() => {
+                    if in_word {
+                        words.push(std::mem::take(&mut word));
+                        in_word = false;
+                    }
+                }
+                c => {
+                    word.push(c);
+                    in_word = true;
+                }
+            },
+        }
+    }
+    if let Some(q) = quote {
+        return Err(format!("missing closing {q}"));
+    }
+    if in_word {
+        words.push(word);
+    }
+    if !words.is_empty() {
+        commands.push(words);
+    }
+    Ok(commands)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(line: &str) -> Vec<Vec<String>> {
+        split_line(line).unwrap()
+    }
+
+    fn parse(text: &str) -> Result<Vec<Command>, String> {
+        Command::parse(text, Path::new("/music"))
+    }
+
+    fn parse_one(text: &str) -> Command {
+        let mut commands = parse(text).unwrap();
+        assert_eq!(commands.len(), 1, "{text:?}");
+        commands.remove(0)
+    }
+
+    #[test]
+    fn split_quotes() {
+        assert_eq!(
+            split(r#"load "a b" 'c "d"' e"f g"h"#),
+            [["load", "a b", "c \"d\"", "ef gh"]]
+        );
+        assert_eq!(split(r#"bookmark """#), [["bookmark", ""]]);
+        assert!(split_line(r#"load "a b"#).is_err());
+        assert!(split_line("load 'a").is_err());
+    }
+
+    #[test]
+    fn split_separators() {
+        assert_eq!(
+            split("play;seek 10 ; ;volume-up;"),
+            [vec!["play"], vec!["seek", "10"], vec!["volume-up"]]
+        );
+        assert_eq!(split("load 'a;b'"), [["load", "a;b"]]);
+        assert!(split("  ;  ").is_empty());
+    }
+
+    #[test]
+    fn split_comments() {
+        assert!(split("# a comment; play").is_empty());
+        assert_eq!(split("play # a comment; pause"), [["play"]]);
+        assert_eq!(split("play;# a comment"), [["play"]]);
+        assert_eq!(split("load track#1.mp3"), [["load", "track#1.mp3"]]);
+        assert_eq!(split("load 'a #b'"), [["load", "a #b"]]);
+    }
+
+    #[test]
+    fn parse_seek() {
+        assert!(matches!(
+            parse_one("seek +1:30"),
+            Command::Skip(secs) if secs == 90.0
+        ));
+        assert!(matches!(
+            parse_one("seek -10"),
+            Command::Skip(secs) if secs == -10.0
+        ));
+        assert!(matches!(
+            parse_one("SEEK 1:30"),
+            Command::Seek(secs) if secs == 90.0
+        ));
+        assert!(parse("seek").is_err());
+        assert!(parse("seek soon").is_err());
+        assert!(parse("seek 1 2").is_err());
+    }
+
+    #[test]
+    fn parse_volume() {
+        assert!(matches!(
+            parse_one("volume 50%"),
+            Command::Volume(volume) if volume == 0.5
+        ));
+        assert!(matches!(
+            parse_one("volume 0.25"),
+            Command::Volume(volume) if volume == 0.25
+        ));
+        assert!(parse("volume 150%").is_err());
+        assert!(parse("volume 2").is_err());
+        assert!(parse("volume loud").is_err());
+    }
+
+    #[test]
+    fn parse_relocate() {
+        assert!(matches!(
+            parse_one("relocate"),
+            Command::Do(Action::Relocate)
+        ));
+        match parse_one("relocate old '/mnt/new music'") {
+            Command::Relocate(old, new) => {
+                assert_eq!(old, Path::new("/music/old"));
+                assert_eq!(new, Path::new("/mnt/new music"));
+            }
+            command => panic!("{command:?}"),
+        }
+        assert!(parse("relocate old").is_err());
+        assert!(parse("relocate a b c").is_err());
+    }
+
+    #[test]
+    fn parse_others() {
+        let commands = parse("play; next # skip\npause").unwrap();
+        assert!(matches!(
+            commands[..],
+            [Command::Play, Command::Do(Action::Next), Command::Pause]
+        ));
+        assert!(parse("quit now").is_err());
+        assert!(parse("dance").is_err());
+        assert!(parse("load").is_err());
+    }
+}
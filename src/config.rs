@@ -2,17 +2,84 @@
 // License: GPLv3
 
 use crate::fixed::{
-    APPNAME, BOOKMARKS_SIZE, DEF_HISTORY_SIZE, MAX_HISTORY_SIZE,
-    MIN_HISTORY_SIZE, SCALE_MAX, SCALE_MIN, WINDOW_HEIGHT_MIN,
-    WINDOW_WIDTH_MIN,
+    APPNAME, BOOKMARKS_SIZE, CONFIG_VERSION, DEF_AUTOSAVE,
+    DEF_HISTORY_SIZE, DEF_POSITIONS_SIZE, DEF_REMOTE_ADDRESS, DEF_REWIND,
+    DEF_SKIP_LONG, DEF_SKIP_MEDIUM, DEF_SKIP_SHORT, DEF_SLEEP_FADE,
+    DEF_STREAM_SIZE, MAX_AUTOSAVE, MAX_CROSSFADE, MAX_HISTORY_SIZE,
+    MAX_POSITIONS_SIZE, MAX_RESUME_REWIND, MAX_SKIP, MAX_SLEEP_FADE,
+    MAX_SPEED, MAX_STREAM_SIZE, MIN_HISTORY_SIZE, MIN_POSITIONS_SIZE,
+    MIN_SPEED, SCALE_MAX, SCALE_MIN, WINDOW_HEIGHT_MIN, WINDOW_WIDTH_MIN,
 };
+use crate::keys::Keymap;
+use crate::playlist::{Playlist, PlaylistTrack};
+use crate::shuffle::ShuffleMode;
 use crate::util;
+use chrono::prelude::*;
 use fltk::{app, dialog};
 use ini::Ini;
-use std::{collections::VecDeque, env, path::PathBuf};
+use std::{
+    collections::{HashSet, VecDeque},
+    env, fs, io, iter, mem,
+    path::{Path, PathBuf},
+};
 
 type History = VecDeque<PathBuf>;
-type Bookmarks = Vec<PathBuf>;
+type Queue = VecDeque<PathBuf>;
+type Bookmarks = Vec<Bookmark>;
+type Positions = VecDeque<TrackPosition>;
+
+// The most recently played track's position (and speed) is at the front
+#[derive(Clone, Debug)]
+pub struct TrackPosition {
+    pub track: PathBuf,
+    pub pos: f64,
+    pub speed: Option<f64>, // None unless the user set it for this track
+}
+
+// Bookmarks are kept in track then position order
+#[derive(Clone, Debug)]
+pub struct Bookmark {
+    pub track: PathBuf,
+    pub pos: f64,
+    pub note: String,
+    pub created: DateTime<Local>,
+}
+
+// What happens when a track ends
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RepeatMode {
+    Off,   // play the next track (if any)
+    Track, // play the same track again
+    All,   // play the next track, wrapping round the folder or playlist
+}
+
+impl RepeatMode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            RepeatMode::Off => "off",
+            RepeatMode::Track => "track",
+            RepeatMode::All => "all",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "off" => Some(RepeatMode::Off),
+            "track" => Some(RepeatMode::Track),
+            "all" => Some(RepeatMode::All),
+            _ => None,
+        }
+    }
+
+    // Off → Track → All → Off
+    pub fn cycled(&self) -> Self {
+        match self {
+            RepeatMode::Off => RepeatMode::Track,
+            RepeatMode::Track => RepeatMode::All,
+            RepeatMode::All => RepeatMode::Off,
+        }
+    }
+}
 
 #[derive(Clone, Debug)]
 pub struct Config {
@@ -24,59 +91,365 @@ pub struct Config {
     pub volume: f64,
     pub pos: f64,
     pub track: PathBuf,
+    pub stopped: Option<DateTime<Local>>, // when the track last played
     pub filename: PathBuf,
     pub history_size: usize,
     pub history: History,
     pub bookmarks: Bookmarks,
+    pub positions_size: usize,
+    pub positions: Positions,
+    pub playlist: Option<Playlist>,
+    pub stream_size: u64, // MB
+    pub crossfade: f64,   // secs; 0.0 means gapless
+    pub sleep_fade: f64,  // secs
+    pub repeat: RepeatMode,
+    pub shuffle: ShuffleMode,
+    pub shuffled: Vec<PathBuf>, // the shuffled play order
+    pub recursive: bool,        // folders include their subfolders' tracks
+    pub queue: Queue,           // tracks to play next, before any others
+    pub keep_queue: bool, // the queue is saved (otherwise it is transient)
+    pub skip_short: f64,  // secs; skip back or forward
+    pub skip_medium: f64, // secs
+    pub skip_long: f64,   // secs
+    pub rewind: f64,      // secs; for the rewind button
+    pub resume_rewind: f64, // secs; the most; 0.0 means never
+    pub autosave: f64, // secs; between saves while playing; 0.0 means never
+    pub remote: bool,  // the HTTP remote control server is on
+    pub remote_address: String, // host:port
+    pub remote_token: String, // if not empty, requests must give it
+    pub startup_script: PathBuf, // empty for none
+    pub library_roots: Vec<PathBuf>, // paths under these are saved relative
+    pub keys: Keymap,
+    pub headless: bool, // not saved; if true FLTK isn't used
 }
 
 impl Config {
-    pub fn new() -> Self {
-        let mut config = Config {
-            filename: get_config_filename(),
-            ..Default::default()
-        };
-        if let Ok(ini) = Ini::load_from_file(&config.filename) {
+    // Uses the given config file if there is one, otherwise the default
+    pub fn new(filename: Option<PathBuf>, headless: bool) -> Self {
+        let filename = filename.unwrap_or_else(get_config_filename);
+        let mut config =
+            Config { filename, headless, ..Default::default() };
+        if let Some(ini) = load_ini(&config.filename) {
+            // The library roots are needed to resolve the other paths
+            if let Some(properties) = ini.section(Some(LIBRARY_SECTION)) {
+                read_library(properties, &mut config);
+            }
             if let Some(properties) = ini.section(Some(WINDOW_SECTION)) {
                 read_window_properties(properties, &mut config);
             }
             if let Some(properties) = ini.section(Some(TRACK_SECTION)) {
                 read_track_properties(properties, &mut config);
             }
+            if let Some(properties) = ini.section(Some(PLAYBACK_SECTION)) {
+                read_playback_properties(properties, &mut config);
+            }
+            if let Some(properties) = ini.section(Some(STARTUP_SECTION)) {
+                if let Some(value) = properties.get(STARTUP_SCRIPT_KEY) {
+                    config.startup_script = PathBuf::from(value.trim());
+                }
+            }
+            if let Some(properties) = ini.section(Some(REMOTE_SECTION)) {
+                read_remote_properties(properties, &mut config);
+            }
+            if let Some(properties) = ini.section(Some(KEYS_SECTION)) {
+                read_keys(properties, &mut config);
+            }
+            if let Some(properties) = ini.section(Some(SHUFFLE_SECTION)) {
+                read_shuffle(properties, &mut config);
+            }
+            if let Some(properties) = ini.section(Some(QUEUE_SECTION)) {
+                read_queue(properties, &mut config);
+            }
             if let Some(properties) = ini.section(Some(HISTORY_SECTION)) {
                 read_history(properties, &mut config);
             }
             if let Some(properties) = ini.section(Some(BOOKMARK_SECTION)) {
                 read_bookmarks(properties, &mut config);
             }
+            if let Some(properties) = ini.section(Some(POSITIONS_SECTION)) {
+                read_positions(properties, &mut config);
+            }
+            if let Some(properties) = ini.section(Some(PLAYLIST_SECTION)) {
+                read_playlist(properties, &mut config);
+            }
         }
         config
     }
 
+    // Returns the position last reached in the given track or 0.0
+    pub fn position_for(&self, track: &Path) -> f64 {
+        for position in &self.positions {
+            if position.track == track {
+                return position.pos;
+            }
+        }
+        0.0
+    }
+
+    // Returns the speed last used for the given track if there was one
+    pub fn speed_for(&self, track: &Path) -> Option<f64> {
+        for position in &self.positions {
+            if position.track == track {
+                return position.speed;
+            }
+        }
+        None
+    }
+
+    // Remembers the given track's position (moving it to the front)
+    pub fn set_position(&mut self, track: &Path, pos: f64) {
+        let speed = self.take_position(track).and_then(|p| p.speed);
+        self.push_position(TrackPosition {
+            track: track.to_path_buf(),
+            pos,
+            speed,
+        });
+    }
+
+    // Remembers the given track's speed (moving it to the front)
+    pub fn set_speed(&mut self, track: &Path, speed: f64) {
+        let pos = match self.take_position(track) {
+            Some(position) => position.pos,
+            None => 0.0,
+        };
+        self.push_position(TrackPosition {
+            track: track.to_path_buf(),
+            pos,
+            speed: Some(speed),
+        });
+    }
+
+    fn take_position(&mut self, track: &Path) -> Option<TrackPosition> {
+        if let Some(index) =
+            self.positions.iter().position(|p| p.track == track)
+        {
+            return self.positions.remove(index);
+        }
+        None
+    }
+
+    // Positions at the start without a speed aren't worth remembering
+    fn push_position(&mut self, position: TrackPosition) {
+        if position.pos > 0.0 || position.speed.is_some() {
+            self.positions.push_front(position);
+            self.positions.truncate(self.positions_size);
+        }
+    }
+
+    // Removes the track from the queue if it is queued (missing tracks
+    // ahead of it are skipped, so it may not be at the front)
+    pub fn unqueue(&mut self, track: &Path) {
+        if let Some(index) =
+            self.queue.iter().position(|queued| queued == track)
+        {
+            self.queue.remove(index);
+        }
+    }
+
+    // Adds the bookmark, making room by dropping the oldest if necessary
+    pub fn add_bookmark(&mut self, bookmark: Bookmark) {
+        if self.bookmarks.len() >= BOOKMARKS_SIZE {
+            if let Some(index) = self
+                .bookmarks
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, b)| b.created)
+                .map(|(i, _)| i)
+            {
+                self.bookmarks.remove(index);
+            }
+        }
+        self.bookmarks.push(bookmark);
+        sort_bookmarks(&mut self.bookmarks);
+    }
+
+    // The library's database is beside the config file
+    pub fn library_filename(&self) -> PathBuf {
+        self.filename.with_extension("db")
+    }
+
+    // Returns the remembered tracks that no longer exist (e.g., because
+    // the library has moved or a drive is mounted somewhere else)
+    pub fn missing_tracks(&self) -> Vec<PathBuf> {
+        let mut missing: Vec<PathBuf> = vec![];
+        let tracks = iter::once(&self.track)
+            .chain(self.history.iter())
+            .chain(self.bookmarks.iter().map(|bookmark| &bookmark.track))
+            .chain(self.positions.iter().map(|position| &position.track))
+            .chain(self.queue.iter())
+            .chain(self.playlist.iter().flat_map(|playlist| {
+                playlist.tracks.iter().map(|track| &track.track)
+            }));
+        for track in tracks {
+            if !track.as_os_str().is_empty()
+                && !track.exists()
+                && !missing.contains(track)
+            {
+                missing.push(track.clone());
+            }
+        }
+        missing
+    }
+
+    // Rebases every missing track (and library root) whose path starts
+    // with old onto new, providing the rebased path exists; returns how
+    // many distinct tracks were changed
+    pub fn relocate(&mut self, old: &Path, new: &Path) -> usize {
+        let mut changed = HashSet::new(); // a track may be in several lists
+        let mut rebase = |path: &mut PathBuf| {
+            if let Some(rebased) = rebased(path, old, new) {
+                changed.insert(mem::replace(path, rebased));
+            }
+        };
+        rebase(&mut self.track);
+        self.history.iter_mut().for_each(&mut rebase);
+        for bookmark in self.bookmarks.iter_mut() {
+            rebase(&mut bookmark.track);
+        }
+        for position in self.positions.iter_mut() {
+            rebase(&mut position.track);
+        }
+        self.queue.iter_mut().for_each(&mut rebase);
+        self.shuffled.iter_mut().for_each(&mut rebase);
+        if let Some(playlist) = &mut self.playlist {
+            for playlist_track in playlist.tracks.iter_mut() {
+                rebase(&mut playlist_track.track);
+            }
+        }
+        for root in self.library_roots.iter_mut() {
+            if let Some(rebased) = rebased(root, old, new) {
+                *root = rebased;
+            }
+        }
+        sort_bookmarks(&mut self.bookmarks);
+        changed.len()
+    }
+
+    // A path in a library root is saved as "@rootN/rest" (using the
+    // innermost root) so that moving the library only means changing the
+    // root; other paths are saved as they are
+    fn portable(&self, path: &Path) -> String {
+        if let Some((i, rest)) = self
+            .library_roots
+            .iter()
+            .enumerate()
+            .filter_map(|(i, root)| {
+                path.strip_prefix(root).ok().map(|rest| (i, root, rest))
+            })
+            .max_by_key(|(_, root, _)| root.components().count())
+            .map(|(i, _, rest)| (i, rest))
+        {
+            format!("{ROOT_PREFIX}{}/{}", i + 1, rest.to_string_lossy())
+        } else {
+            path.to_string_lossy().to_string()
+        }
+    }
+
+    // The inverse of portable(); an unknown root is left as it is
+    fn resolve(&self, value: &str) -> PathBuf {
+        if let Some((i, rest)) = value
+            .strip_prefix(ROOT_PREFIX)
+            .and_then(|value| value.split_once('/'))
+        {
+            if let Some(root) = i
+                .parse::<usize>()
+                .ok()
+                .and_then(|i| i.checked_sub(1))
+                .and_then(|i| self.library_roots.get(i))
+            {
+                return root.join(rest);
+            }
+        }
+        PathBuf::from(value)
+    }
+
     pub fn save(&self) {
         if self.filename.to_string_lossy() == "" {
             self.warning("failed to save configuration: no filename");
         } else {
             let mut ini = Ini::new();
+            ini.with_section(Some(CONFIG_SECTION))
+                .set(VERSION_KEY, CONFIG_VERSION.to_string());
             ini.with_section(Some(WINDOW_SECTION))
                 .set(X_KEY, self.window_x.to_string())
                 .set(Y_KEY, self.window_y.to_string())
                 .set(WIDTH_KEY, self.window_width.to_string())
                 .set(HEIGHT_KEY, self.window_height.to_string())
-                .set(SCALE_KEY, app::screen_scale(0).to_string());
+                .set(SCALE_KEY, self.window_scale.to_string());
             ini.with_section(Some(TRACK_SECTION))
                 .set(VOLUME_KEY, self.volume.to_string())
                 .set(POS_KEY, self.pos.to_string())
-                .set(TRACK_KEY, self.track.to_string_lossy());
+                .set(TRACK_KEY, self.portable(&self.track));
+            if let Some(stopped) = self.stopped {
+                ini.with_section(Some(TRACK_SECTION))
+                    .set(STOPPED_KEY, stopped.to_rfc3339());
+            }
+            ini.with_section(Some(PLAYBACK_SECTION))
+                .set(STREAM_SIZE_KEY, self.stream_size.to_string())
+                .set(CROSSFADE_KEY, self.crossfade.to_string())
+                .set(SLEEP_FADE_KEY, self.sleep_fade.to_string())
+                .set(REPEAT_KEY, self.repeat.name())
+                .set(RECURSIVE_KEY, self.recursive.to_string())
+                .set(SKIP_SHORT_KEY, self.skip_short.to_string())
+                .set(SKIP_MEDIUM_KEY, self.skip_medium.to_string())
+                .set(SKIP_LONG_KEY, self.skip_long.to_string())
+                .set(REWIND_KEY, self.rewind.to_string())
+                .set(RESUME_REWIND_KEY, self.resume_rewind.to_string())
+                .set(AUTOSAVE_KEY, self.autosave.to_string());
+            ini.with_section(Some(STARTUP_SECTION)).set(
+                STARTUP_SCRIPT_KEY,
+                self.startup_script.to_string_lossy(),
+            );
+            ini.with_section(Some(REMOTE_SECTION))
+                .set(REMOTE_ENABLED_KEY, self.remote.to_string())
+                .set(REMOTE_ADDRESS_KEY, &self.remote_address)
+                .set(REMOTE_TOKEN_KEY, &self.remote_token);
+            for (i, root) in self.library_roots.iter().enumerate() {
+                ini.with_section(Some(LIBRARY_SECTION)).set(
+                    format!("{LIBRARY_ROOT_KEY}{}", i + 1),
+                    root.to_string_lossy(),
+                );
+            }
+            for binding in self.keys.bindings() {
+                ini.with_section(Some(KEYS_SECTION))
+                    .set(binding.name, binding.chords_text(" "));
+            }
+            self.save_shuffle(&mut ini);
+            self.save_queue(&mut ini);
             ini.with_section(Some(HISTORY_SECTION))
                 .set(HISTORY_SIZE_KEY, self.history_size.to_string());
             self.save_history(&mut ini);
             self.save_bookmarks(&mut ini);
-            match ini.write_to_file(&self.filename) {
-                Ok(_) => {}
-                Err(err) => self.warning(&format!(
+            ini.with_section(Some(POSITIONS_SECTION))
+                .set(POSITIONS_SIZE_KEY, self.positions_size.to_string());
+            self.save_positions(&mut ini);
+            self.save_playlist(&mut ini);
+            if let Err(err) = write_ini(&ini, &self.filename) {
+                self.warning(&format!(
                     "failed to save configuration: {err}"
-                )),
+                ))
+            }
+        }
+    }
+
+    fn save_shuffle(&self, ini: &mut Ini) {
+        ini.with_section(Some(SHUFFLE_SECTION))
+            .set(SHUFFLE_MODE_KEY, self.shuffle.name());
+        for (i, track) in self.shuffled.iter().enumerate() {
+            let key = format!("{SHUFFLE_TRACK_KEY}{}", i + 1);
+            ini.with_section(Some(SHUFFLE_SECTION))
+                .set(key, self.portable(track));
+        }
+    }
+
+    fn save_queue(&self, ini: &mut Ini) {
+        ini.with_section(Some(QUEUE_SECTION))
+            .set(QUEUE_KEEP_KEY, self.keep_queue.to_string());
+        if self.keep_queue {
+            for (i, track) in self.queue.iter().enumerate() {
+                let key = format!("{QUEUE_TRACK_KEY}{}", i + 1);
+                ini.with_section(Some(QUEUE_SECTION))
+                    .set(key, self.portable(track));
             }
         }
     }
@@ -85,19 +458,88 @@ impl Config {
         for (i, track) in self.history.iter().enumerate() {
             let key = format!("{HISTORY_KEY}{}", i + 1);
             ini.with_section(Some(HISTORY_SECTION))
-                .set(key, track.to_string_lossy());
+                .set(key, self.portable(track));
         }
     }
 
     fn save_bookmarks(&self, ini: &mut Ini) {
-        for (i, track) in self.bookmarks.iter().enumerate() {
-            let key = format!("{BOOKMARK_KEY}{}", i + 1);
+        for (i, bookmark) in self.bookmarks.iter().enumerate() {
             ini.with_section(Some(BOOKMARK_SECTION))
-                .set(key, track.to_string_lossy());
+                .set(
+                    format!("{BOOKMARK_KEY}{}", i + 1),
+                    self.portable(&bookmark.track),
+                )
+                .set(
+                    format!("{BOOKMARK_POS_KEY}{}", i + 1),
+                    bookmark.pos.to_string(),
+                )
+                .set(
+                    format!("{BOOKMARK_NOTE_KEY}{}", i + 1),
+                    bookmark.note.clone(),
+                )
+                .set(
+                    format!("{BOOKMARK_CREATED_KEY}{}", i + 1),
+                    bookmark.created.to_rfc3339(),
+                );
+        }
+    }
+
+    fn save_positions(&self, ini: &mut Ini) {
+        for (i, position) in self.positions.iter().enumerate() {
+            ini.with_section(Some(POSITIONS_SECTION))
+                .set(
+                    format!("{POSITION_TRACK_KEY}{}", i + 1),
+                    self.portable(&position.track),
+                )
+                .set(
+                    format!("{POSITION_POS_KEY}{}", i + 1),
+                    position.pos.to_string(),
+                );
+            if let Some(speed) = position.speed {
+                ini.with_section(Some(POSITIONS_SECTION)).set(
+                    format!("{POSITION_SPEED_KEY}{}", i + 1),
+                    speed.to_string(),
+                );
+            }
+        }
+    }
+
+    fn save_playlist(&self, ini: &mut Ini) {
+        if let Some(playlist) = &self.playlist {
+            ini.with_section(Some(PLAYLIST_SECTION))
+                .set(PLAYLIST_NAME_KEY, playlist.name.clone())
+                .set(
+                    PLAYLIST_FILENAME_KEY,
+                    self.portable(&playlist.filename),
+                )
+                .set(PLAYLIST_INDEX_KEY, playlist.index.to_string());
+            for (i, track) in playlist.tracks.iter().enumerate() {
+                let i = i + 1;
+                ini.with_section(Some(PLAYLIST_SECTION)).set(
+                    format!("{PLAYLIST_TRACK_KEY}{i}"),
+                    self.portable(&track.track),
+                );
+                if !track.title.is_empty() {
+                    ini.with_section(Some(PLAYLIST_SECTION)).set(
+                        format!("{PLAYLIST_TITLE_KEY}{i}"),
+                        track.title.clone(),
+                    );
+                }
+                if let Some(secs) = track.secs {
+                    ini.with_section(Some(PLAYLIST_SECTION)).set(
+                        format!("{PLAYLIST_SECS_KEY}{i}"),
+                        secs.to_string(),
+                    );
+                }
+            }
         }
     }
 
     fn warning(&self, message: &str) {
+        if self.headless {
+            eprintln!("{APPNAME}: {message}");
+            return;
+        }
         dialog::message_title(&format!("Warning — {APPNAME}",));
         dialog::message(util::x() - 200, util::y() - 100, message);
     }
@@ -114,14 +556,119 @@ impl Default for Config {
             volume: 0.5,
             pos: 0.0,
             track: PathBuf::new(),
+            stopped: None,
             filename: PathBuf::new(),
             history_size: DEF_HISTORY_SIZE,
             history: History::new(),
             bookmarks: Bookmarks::new(),
+            positions_size: DEF_POSITIONS_SIZE,
+            positions: Positions::new(),
+            playlist: None,
+            stream_size: DEF_STREAM_SIZE,
+            crossfade: 0.0,
+            sleep_fade: DEF_SLEEP_FADE,
+            repeat: RepeatMode::Off,
+            shuffle: ShuffleMode::Off,
+            shuffled: Vec::new(),
+            recursive: false,
+            queue: Queue::new(),
+            keep_queue: false,
+            skip_short: DEF_SKIP_SHORT,
+            skip_medium: DEF_SKIP_MEDIUM,
+            skip_long: DEF_SKIP_LONG,
+            rewind: DEF_REWIND,
+            resume_rewind: 0.0,
+            autosave: DEF_AUTOSAVE,
+            remote: false,
+            remote_address: DEF_REMOTE_ADDRESS.to_string(),
+            remote_token: String::new(),
+            startup_script: PathBuf::new(),
+            library_roots: Vec::new(),
+            keys: Keymap::default(),
+            headless: false,
+        }
+    }
+}
+
+// Falls back to the backup if the config file can't be read; older
+// formats are brought up to date
+fn load_ini(filename: &Path) -> Option<Ini> {
+    let mut ini = match Ini::load_from_file(filename) {
+        Ok(ini) => ini,
+        Err(err) => {
+            if !filename.exists() {
+                return None;
+            }
+            let backup = with_suffix(filename, BACKUP_SUFFIX);
+            eprintln!(
+                "{APPNAME}: failed to read {filename:?} ({err}); trying \
+                 {backup:?}"
+            );
+            Ini::load_from_file(&backup).ok()?
+        }
+    };
+    migrate(&mut ini);
+    Some(ini)
+}
+
+fn migrate(ini: &mut Ini) {
+    let version = ini
+        .get_from(Some(CONFIG_SECTION), VERSION_KEY)
+        .and_then(|value| value.trim().parse::<u32>().ok())
+        .unwrap_or(1);
+    if version < 2 {
+        // Version 1 misspelled the bookmarks section and keys
+        if let Some(properties) = ini.delete(Some(OLD_BOOKMARK_SECTION)) {
+            for (key, value) in properties.iter() {
+                let key = match key.strip_prefix(OLD_BOOKMARK_KEY) {
+                    Some(i) => format!("{BOOKMARK_KEY}{i}"),
+                    None => key.to_string(),
+                };
+                ini.with_section(Some(BOOKMARK_SECTION)).set(key, value);
+            }
         }
     }
 }
 
+// Writes to a temporary file that then replaces the config file so that a
+// crash can't leave it half-written; the replaced file (if it could be
+// read) is kept as a backup if possible. On failure the temporary file
+// is removed
+fn write_ini(ini: &Ini, filename: &Path) -> io::Result<()> {
+    let temp = with_suffix(filename, TEMP_SUFFIX);
+    let result = replace_with_temp(ini, filename, &temp);
+    if result.is_err() {
+        let _ = fs::remove_file(&temp); // Ignore error; may not exist
+    }
+    result
+}
+
+fn replace_with_temp(
+    ini: &Ini,
+    filename: &Path,
+    temp: &Path,
+) -> io::Result<()> {
+    {
+        let mut file = fs::File::create(temp)?;
+        ini.write_to(&mut file)?;
+        file.sync_all()?;
+    }
+    if Ini::load_from_file(filename).is_ok() {
+        let backup = with_suffix(filename, BACKUP_SUFFIX);
+        if let Err(err) = fs::copy(filename, &backup) {
+            // The backup is only a nicety so the save goes ahead
+            eprintln!("{APPNAME}: failed to back up to {backup:?}: {err}");
+        }
+    }
+    fs::rename(temp, filename)
+}
+
+fn with_suffix(filename: &Path, suffix: &str) -> PathBuf {
+    let mut name = filename.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
 fn get_config_filename() -> PathBuf {
     let mut dir = dirs::config_dir();
     let mut dot = "";
@@ -138,12 +685,31 @@ fn get_config_filename() -> PathBuf {
     }
 }
 
+fn read_library(properties: &ini::Properties, config: &mut Config) {
+    config.library_roots.clear();
+    let mut i = 1;
+    while let Some(value) =
+        properties.get(&format!("{LIBRARY_ROOT_KEY}{i}"))
+    {
+        let root = PathBuf::from(value.trim());
+        if !root.as_os_str().is_empty() {
+            config.library_roots.push(root);
+        }
+        i += 1;
+    }
+}
+
 fn read_window_properties(
     properties: &ini::Properties,
     config: &mut Config,
 ) {
-    let max_x = (app::screen_size().0 - 100.0) as i32;
-    let max_y = (app::screen_size().1 - 100.0) as i32;
+    // Without a window the geometry is only read so that it is saved
+    let (max_x, max_y) = if config.headless {
+        (i32::MAX, i32::MAX)
+    } else {
+        let (width, height) = app::screen_size();
+        ((width - 100.0) as i32, (height - 100.0) as i32)
+    };
     if let Some(value) = properties.get(X_KEY) {
         config.window_x = util::get_num(value, 0, max_x, config.window_x)
     }
@@ -169,7 +735,7 @@ fn read_window_properties(
     if let Some(value) = properties.get(SCALE_KEY) {
         config.window_scale =
             util::get_num(value, SCALE_MIN, SCALE_MAX, config.window_scale);
-        if !util::isone32(config.window_scale) {
+        if !config.headless && !util::isone32(config.window_scale) {
             app::set_screen_scale(0, config.window_scale);
         }
     }
@@ -186,7 +752,144 @@ fn read_track_properties(
         config.pos = util::get_num(value, 0.0, f64::MAX, config.pos)
     }
     if let Some(value) = properties.get(TRACK_KEY) {
-        config.track = PathBuf::from(value);
+        config.track = config.resolve(value);
+    }
+    if let Some(value) = properties.get(STOPPED_KEY) {
+        if let Ok(stopped) = DateTime::parse_from_rfc3339(value) {
+            config.stopped = Some(stopped.with_timezone(&Local));
+        }
+    }
+}
+
+fn read_playback_properties(
+    properties: &ini::Properties,
+    config: &mut Config,
+) {
+    if let Some(value) = properties.get(STREAM_SIZE_KEY) {
+        config.stream_size =
+            util::get_num(value, 0, MAX_STREAM_SIZE, config.stream_size)
+    }
+    if let Some(value) = properties.get(CROSSFADE_KEY) {
+        config.crossfade =
+            util::get_num(value, 0.0, MAX_CROSSFADE, config.crossfade)
+    }
+    if let Some(value) = properties.get(SLEEP_FADE_KEY) {
+        config.sleep_fade =
+            util::get_num(value, 1.0, MAX_SLEEP_FADE, config.sleep_fade)
+    }
+    if let Some(value) = properties.get(REPEAT_KEY) {
+        if let Some(repeat) = RepeatMode::from_name(value) {
+            config.repeat = repeat;
+        }
+    }
+    if let Some(value) = properties.get(RECURSIVE_KEY) {
+        config.recursive = matches!(
+            value.trim().to_lowercase().as_str(),
+            "true" | "yes" | "on" | "1"
+        );
+    }
+    if let Some(value) = properties.get(SKIP_SHORT_KEY) {
+        config.skip_short =
+            util::get_num(value, 1.0, MAX_SKIP, config.skip_short)
+    }
+    if let Some(value) = properties.get(SKIP_MEDIUM_KEY) {
+        config.skip_medium =
+            util::get_num(value, 1.0, MAX_SKIP, config.skip_medium)
+    }
+    if let Some(value) = properties.get(SKIP_LONG_KEY) {
+        config.skip_long =
+            util::get_num(value, 1.0, MAX_SKIP, config.skip_long)
+    }
+    if let Some(value) = properties.get(REWIND_KEY) {
+        config.rewind = util::get_num(value, 1.0, MAX_SKIP, config.rewind)
+    }
+    if let Some(value) = properties.get(RESUME_REWIND_KEY) {
+        config.resume_rewind = util::get_num(
+            value,
+            0.0,
+            MAX_RESUME_REWIND,
+            config.resume_rewind,
+        )
+    }
+    if let Some(value) = properties.get(AUTOSAVE_KEY) {
+        config.autosave =
+            util::get_num(value, 0.0, MAX_AUTOSAVE, config.autosave)
+    }
+}
+
+fn read_remote_properties(
+    properties: &ini::Properties,
+    config: &mut Config,
+) {
+    if let Some(value) = properties.get(REMOTE_ENABLED_KEY) {
+        config.remote = matches!(
+            value.trim().to_lowercase().as_str(),
+            "true" | "yes" | "on" | "1"
+        );
+    }
+    if let Some(value) = properties.get(REMOTE_ADDRESS_KEY) {
+        let value = value.trim();
+        if !value.is_empty() {
+            config.remote_address = value.to_string();
+        }
+    }
+    if let Some(value) = properties.get(REMOTE_TOKEN_KEY) {
+        config.remote_token = value.trim().to_string();
+    }
+}
+
+// The [Keys] section is applied as a whole (see Keymap::set_all());
+// actions that aren't given keep their default keys unless given away
+fn read_keys(properties: &ini::Properties, config: &mut Config) {
+    let entries: Vec<(&str, &str)> = config
+        .keys
+        .bindings()
+        .iter()
+        .filter_map(|binding| {
+            properties.get(binding.name).map(|value| (binding.name, value))
+        })
+        .collect();
+    for err in config.keys.set_all(&entries) {
+        eprintln!("{APPNAME}: {err}");
+    }
+}
+
+fn read_shuffle(properties: &ini::Properties, config: &mut Config) {
+    if let Some(value) = properties.get(SHUFFLE_MODE_KEY) {
+        if let Some(shuffle) = ShuffleMode::from_name(value) {
+            config.shuffle = shuffle;
+        }
+    }
+    config.shuffled.clear();
+    if config.shuffle != ShuffleMode::Off {
+        let mut i = 1;
+        while let Some(value) =
+            properties.get(&format!("{SHUFFLE_TRACK_KEY}{i}"))
+        {
+            let track = config.resolve(value);
+            config.shuffled.push(track);
+            i += 1;
+        }
+    }
+}
+
+fn read_queue(properties: &ini::Properties, config: &mut Config) {
+    if let Some(value) = properties.get(QUEUE_KEEP_KEY) {
+        config.keep_queue = matches!(
+            value.trim().to_lowercase().as_str(),
+            "true" | "yes" | "on" | "1"
+        );
+    }
+    config.queue.clear();
+    if config.keep_queue {
+        let mut i = 1;
+        while let Some(value) =
+            properties.get(&format!("{QUEUE_TRACK_KEY}{i}"))
+        {
+            let track = config.resolve(value);
+            config.queue.push_back(track);
+            i += 1;
+        }
     }
 }
 
@@ -203,8 +906,9 @@ fn read_history(properties: &ini::Properties, config: &mut Config) {
     for i in 1..=config.history_size {
         let key = format!("{HISTORY_KEY}{i}");
         if let Some(value) = properties.get(&key) {
-            let value = PathBuf::from(value);
-            if !config.history.contains(&value) && value.exists() {
+            // Missing tracks are kept so that they can be relocated
+            let value = config.resolve(value);
+            if !config.history.contains(&value) {
                 config.history.push_back(value);
             }
         }
@@ -216,14 +920,137 @@ fn read_bookmarks(properties: &ini::Properties, config: &mut Config) {
     for i in 1..=BOOKMARKS_SIZE {
         let key = format!("{BOOKMARK_KEY}{i}");
         if let Some(value) = properties.get(&key) {
-            let value = PathBuf::from(value);
-            if !config.bookmarks.contains(&value) && value.exists() {
-                config.bookmarks.push(value);
+            let track = config.resolve(value);
+            // Older configs only have the track, so pos defaults to 0.0
+            let pos =
+                match properties.get(&format!("{BOOKMARK_POS_KEY}{i}")) {
+                    Some(value) => util::get_num(value, 0.0, f64::MAX, 0.0),
+                    None => 0.0,
+                };
+            let note =
+                match properties.get(&format!("{BOOKMARK_NOTE_KEY}{i}")) {
+                    Some(value) => value.to_string(),
+                    None => String::new(),
+                };
+            let created = match properties
+                .get(&format!("{BOOKMARK_CREATED_KEY}{i}"))
+                .and_then(|value| DateTime::parse_from_rfc3339(value).ok())
+            {
+                Some(created) => created.with_timezone(&Local),
+                None => Local::now(),
+            };
+            if !config
+                .bookmarks
+                .iter()
+                .any(|b| b.track == track && util::isclose64(b.pos, pos))
+            {
+                config.bookmarks.push(Bookmark {
+                    track,
+                    pos,
+                    note,
+                    created,
+                });
+            }
+        }
+    }
+    sort_bookmarks(&mut config.bookmarks);
+}
+
+// Returns the missing path rebased from old to new if it then exists
+fn rebased(path: &Path, old: &Path, new: &Path) -> Option<PathBuf> {
+    if path.exists() {
+        return None;
+    }
+    let path = new.join(path.strip_prefix(old).ok()?);
+    if path.exists() {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+fn sort_bookmarks(bookmarks: &mut Bookmarks) {
+    bookmarks.sort_by(|a, b| {
+        a.track.cmp(&b.track).then_with(|| a.pos.total_cmp(&b.pos))
+    });
+}
+
+fn read_positions(properties: &ini::Properties, config: &mut Config) {
+    if let Some(value) = properties.get(POSITIONS_SIZE_KEY) {
+        config.positions_size = util::get_num(
+            value,
+            MIN_POSITIONS_SIZE,
+            MAX_POSITIONS_SIZE,
+            config.positions_size,
+        )
+    }
+    config.positions.clear();
+    for i in 1..=config.positions_size {
+        let track_key = format!("{POSITION_TRACK_KEY}{i}");
+        let pos_key = format!("{POSITION_POS_KEY}{i}");
+        if let (Some(track), Some(pos)) =
+            (properties.get(&track_key), properties.get(&pos_key))
+        {
+            let track = config.resolve(track);
+            let pos = util::get_num(pos, 0.0, f64::MAX, 0.0);
+            let speed =
+                properties.get(&format!("{POSITION_SPEED_KEY}{i}")).map(
+                    |value| util::get_num(value, MIN_SPEED, MAX_SPEED, 1.0),
+                );
+            if (pos > 0.0 || speed.is_some())
+                && !config.positions.iter().any(|p| p.track == track)
+            {
+                config.positions.push_back(TrackPosition {
+                    track,
+                    pos,
+                    speed,
+                });
+            }
+        }
+    }
+}
+
+fn read_playlist(properties: &ini::Properties, config: &mut Config) {
+    let mut playlist = Playlist::default();
+    if let Some(value) = properties.get(PLAYLIST_NAME_KEY) {
+        playlist.name = value.to_string();
+    }
+    if let Some(value) = properties.get(PLAYLIST_FILENAME_KEY) {
+        playlist.filename = config.resolve(value);
+    }
+    let mut i = 1;
+    while let Some(value) =
+        properties.get(&format!("{PLAYLIST_TRACK_KEY}{i}"))
+    {
+        let mut track = PlaylistTrack::new(config.resolve(value));
+        if let Some(value) =
+            properties.get(&format!("{PLAYLIST_TITLE_KEY}{i}"))
+        {
+            track.title = value.to_string();
+        }
+        if let Some(value) =
+            properties.get(&format!("{PLAYLIST_SECS_KEY}{i}"))
+        {
+            track.secs = value.parse::<f64>().ok();
+        }
+        playlist.tracks.push(track);
+        i += 1;
+    }
+    if !playlist.tracks.is_empty() {
+        if !playlist.set_current(&config.track) {
+            if let Some(value) = properties.get(PLAYLIST_INDEX_KEY) {
+                playlist.index =
+                    util::get_num(value, 0, playlist.tracks.len() - 1, 0);
             }
         }
+        config.playlist = Some(playlist);
     }
 }
 
+static CONFIG_SECTION: &str = "Config";
+static VERSION_KEY: &str = "version";
+static TEMP_SUFFIX: &str = ".tmp";
+static BACKUP_SUFFIX: &str = ".bak";
 static WINDOW_SECTION: &str = "Window";
 static X_KEY: &str = "x";
 static Y_KEY: &str = "y";
@@ -234,8 +1061,54 @@ static TRACK_SECTION: &str = "Track";
 static VOLUME_KEY: &str = "volume";
 static POS_KEY: &str = "pos";
 static TRACK_KEY: &str = "track";
+static STOPPED_KEY: &str = "stopped";
+static PLAYBACK_SECTION: &str = "Playback";
+static STREAM_SIZE_KEY: &str = "stream_size";
+static CROSSFADE_KEY: &str = "crossfade";
+static SLEEP_FADE_KEY: &str = "sleep_fade";
+static REPEAT_KEY: &str = "repeat";
+static RECURSIVE_KEY: &str = "recursive";
+static SKIP_SHORT_KEY: &str = "skip_short";
+static SKIP_MEDIUM_KEY: &str = "skip_medium";
+static SKIP_LONG_KEY: &str = "skip_long";
+static REWIND_KEY: &str = "rewind";
+static RESUME_REWIND_KEY: &str = "resume_rewind";
+static AUTOSAVE_KEY: &str = "autosave";
+static STARTUP_SECTION: &str = "Startup";
+static STARTUP_SCRIPT_KEY: &str = "script";
+static REMOTE_SECTION: &str = "Remote";
+static REMOTE_ENABLED_KEY: &str = "enabled";
+static REMOTE_ADDRESS_KEY: &str = "address";
+static REMOTE_TOKEN_KEY: &str = "token";
+static KEYS_SECTION: &str = "Keys";
+static LIBRARY_SECTION: &str = "Library";
+static LIBRARY_ROOT_KEY: &str = "root";
+static ROOT_PREFIX: &str = "@root";
+static SHUFFLE_SECTION: &str = "Shuffle";
+static SHUFFLE_MODE_KEY: &str = "mode";
+static SHUFFLE_TRACK_KEY: &str = "track";
+static QUEUE_SECTION: &str = "Queue";
+static QUEUE_KEEP_KEY: &str = "keep";
+static QUEUE_TRACK_KEY: &str = "track";
 static HISTORY_SECTION: &str = "History";
 static HISTORY_SIZE_KEY: &str = "size";
 static HISTORY_KEY: &str = "history";
-static BOOKMARK_SECTION: &str = "Boomarks";
-static BOOKMARK_KEY: &str = "boomark";
+static BOOKMARK_SECTION: &str = "Bookmarks";
+static BOOKMARK_KEY: &str = "bookmark";
+static OLD_BOOKMARK_SECTION: &str = "Boomarks"; // version 1
+static OLD_BOOKMARK_KEY: &str = "boomark"; // version 1
+static BOOKMARK_POS_KEY: &str = "pos";
+static BOOKMARK_NOTE_KEY: &str = "note";
+static BOOKMARK_CREATED_KEY: &str = "created";
+static POSITIONS_SECTION: &str = "Positions";
+static POSITIONS_SIZE_KEY: &str = "size";
+static POSITION_TRACK_KEY: &str = "track";
+static POSITION_POS_KEY: &str = "pos";
+static POSITION_SPEED_KEY: &str = "speed";
+static PLAYLIST_SECTION: &str = "Playlist";
+static PLAYLIST_NAME_KEY: &str = "name";
+static PLAYLIST_FILENAME_KEY: &str = "filename";
+static PLAYLIST_INDEX_KEY: &str = "index";
+static PLAYLIST_TRACK_KEY: &str = "track";
+static PLAYLIST_TITLE_KEY: &str = "title";
+static PLAYLIST_SECS_KEY: &str = "secs";
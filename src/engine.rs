@@ -0,0 +1,957 @@
+// Copyright © 2021-22 Mark Summerfield. All rights reserved.
+// License: GPLv3
+
+// The playback state machine: loads tracks, plays and pauses them, moves
+// between chapters and tracks, and keeps the config's track, position,
+// and history up to date. Both the window (Application) and headless
+// mode drive an Engine; neither it nor anything it calls uses FLTK
+
+use super::CONFIG;
+use crate::audio::{self, Audio};
+use crate::chapters::{self, Chapter};
+use crate::cli::Options;
+use crate::config::{Bookmark, RepeatMode};
+use crate::fixed::{
+    Action, MAX_SPEED, MIN_SPEED, PRELOAD_SECS, RESUME_REWIND_FULL_PAUSE,
+    RESUME_REWIND_MIN_PAUSE, SEEK_WAIT_LIMIT, TICK_TIMEOUT,
+};
+use crate::playlist::{Playlist, PlaylistTrack};
+use crate::shuffle::{self, ShuffleMode};
+use crate::status::STATUS;
+use crate::util::{self, WhichTrack};
+use chrono::prelude::*;
+use soloud::{prelude::*, Handle, Soloud};
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::mpsc::Receiver,
+    thread,
+    time::Duration,
+};
+
+pub struct Engine {
+    pub player: Soloud,
+    pub audio: Audio,
+    next_audio: Option<(PathBuf, Audio)>,
+    loading: Option<Receiver<Option<(PathBuf, Audio)>>>, // next_audio to be
+    old_audio: Option<Audio>,
+    old_handle: Option<Handle>, // the track being crossfaded out
+    preloaded: bool,
+    pub handle: Handle,
+    pub playing: bool,
+    pub first_to_play: bool,
+    pub loaded: bool, // false if there's no track or it failed to load
+    pub pos: f64,     // secs; updated by seek() and tick()
+    pub volume: f64,  // 0.0 to 1.0
+    pub speed: f64,
+    pub chapters: Rc<RefCell<Vec<Chapter>>>,
+    pub loop_a: Option<f64>, // secs; the A/B loop's start
+    pub loop_b: Option<f64>, // secs; the A/B loop's end
+    pub looping: bool,       // between loop_a and loop_b
+    paused_at: Option<(PathBuf, DateTime<Local>)>, // for resume rewind
+}
+
+impl Engine {
+    pub fn new() -> Self {
+        let mut player =
+            Soloud::default().expect("Cannot access audio backend");
+        player.set_pause_all(true);
+        let (volume, paused_at) = {
+            let config = CONFIG.get().read().unwrap();
+            (
+                config.volume,
+                config
+                    .stopped
+                    .map(|stopped| (config.track.clone(), stopped)),
+            )
+        };
+        Self {
+            player,
+            audio: Audio::default(),
+            next_audio: None,
+            loading: None,
+            old_audio: None,
+            old_handle: None,
+            preloaded: false,
+            handle: unsafe { Handle::from_raw(0) },
+            playing: false,
+            first_to_play: true,
+            loaded: false,
+            pos: 0.0,
+            volume,
+            speed: 1.0,
+            chapters: Rc::new(RefCell::new(vec![])),
+            loop_a: None,
+            loop_b: None,
+            looping: false,
+            paused_at,
+        }
+    }
+
+    // Loads the config's track paused, ready to play from the config's
+    // pos; returns false if it couldn't be loaded
+    pub fn load(&mut self) -> bool {
+        if self.playing {
+            self.play_or_pause(); // PAUSE
+            self.player.stop_all();
+        }
+        self.next_audio = None;
+        self.loading = None;
+        self.old_audio = None;
+        self.old_handle = None;
+        self.preloaded = false;
+        self.chapters.borrow_mut().clear();
+        let (track, stream_size, pos) = {
+            let config = CONFIG.get().read().unwrap();
+            (config.track.clone(), config.stream_size, config.pos)
+        };
+        self.loaded = match Audio::load(&track, stream_size) {
+            Ok(audio) => {
+                self.audio = audio;
+                self.handle = self.audio.play(&self.player);
+                self.player.set_pause(self.handle, true);
+                self.player.set_volume(self.handle, self.volume as f32);
+                // The seek to pos happens when play starts
+                self.first_to_play = true;
+                true
+            }
+            Err(_) => false,
+        };
+        if self.loaded {
+            self.pos = pos;
+            self.track_changed(&track);
+        }
+        self.loaded
+    }
+
+    // Uses the new track's own speed if it has one (otherwise the
+    // current one, though that isn't remembered for the new track) and
+    // reads its chapters
+    fn track_changed(&mut self, track: &Path) {
+        let speed = {
+            let config = CONFIG.get().read().unwrap();
+            config.speed_for(track)
+        };
+        self.apply_speed(speed.unwrap_or(self.speed));
+        self.clear_loop();
+        self.update_shuffle();
+        *self.chapters.borrow_mut() = chapters::get_track_chapters(track);
+        STATUS.get().write().unwrap().set_track(track, self.audio.length());
+        self.update_status();
+    }
+
+    // Plays or pauses at the user's request; when resuming after a pause
+    // it first goes back a little so as not to resume mid-sentence
+    pub fn resume_or_pause(&mut self) {
+        if self.loaded && !self.playing {
+            self.rewind_for_resume();
+        }
+        self.play_or_pause();
+    }
+
+    pub fn play_or_pause(&mut self) {
+        if !self.loaded {
+            return;
+        }
+        if self.playing {
+            let track = CONFIG.get().read().unwrap().track.clone();
+            self.paused_at = Some((track, Local::now()));
+        }
+        if self.first_to_play {
+            let pos = {
+                let config = CONFIG.get().read().unwrap();
+                config.pos
+            };
+            self.seek(pos);
+            self.first_to_play = false;
+        }
+        self.player.set_pause(self.handle, self.playing);
+        // A track that is being crossfaded out pauses and resumes too
+        if let Some(old_handle) = self.old_handle {
+            if self.player.is_valid_voice_handle(old_handle) {
+                self.player.set_pause(old_handle, self.playing);
+            } else {
+                self.old_handle = None;
+            }
+        }
+        self.playing = !self.playing;
+        self.update_status();
+    }
+
+    pub fn replay(&mut self) {
+        if !self.loaded {
+            return;
+        }
+        if self.playing {
+            self.play_or_pause(); // PAUSE
+        }
+        {
+            let mut config = CONFIG.get().write().unwrap();
+            config.pos = 0.0;
+        }
+        self.seek(0.0);
+        self.play_or_pause(); // PLAY
+    }
+
+    // Plays the current track from the given position
+    pub fn play_from(&mut self, pos: f64) {
+        self.go_to(pos);
+        if !self.playing {
+            self.play_or_pause(); // PLAY
+        }
+    }
+
+    // Goes back further the longer the current track has been paused
+    // (or, at startup, since it last played), up to the configured most
+    fn rewind_for_resume(&mut self) {
+        let (track, most, pos) = {
+            let config = CONFIG.get().read().unwrap();
+            (config.track.clone(), config.resume_rewind, config.pos)
+        };
+        let paused = match &self.paused_at {
+            Some((paused_track, at)) if *paused_track == track => {
+                (Local::now() - *at).num_milliseconds() as f64 / 1000.0
+            }
+            _ => return,
+        };
+        if most <= 0.0 || paused < RESUME_REWIND_MIN_PAUSE {
+            return;
+        }
+        // Logarithmic so that short pauses only go back a second or two
+        let fraction = (paused / RESUME_REWIND_MIN_PAUSE).ln()
+            / (RESUME_REWIND_FULL_PAUSE / RESUME_REWIND_MIN_PAUSE).ln();
+        let secs = (most * fraction.min(1.0)).max(1.0);
+        let pos = if self.first_to_play { pos } else { self.exact_pos() };
+        self.go_to(pos - secs);
+    }
+
+    // Moves to the given position in the current track (even if it hasn't
+    // started playing yet)
+    pub fn go_to(&mut self, pos: f64) {
+        self.paused_at = None; // A deliberate move means don't rewind
+        let pos = pos.clamp(0.0, self.audio.length());
+        if self.first_to_play {
+            {
+                let mut config = CONFIG.get().write().unwrap();
+                config.pos = pos; // The seek happens when play starts
+            }
+            self.pos = pos;
+            self.update_status();
+        } else {
+            self.seek(pos);
+        }
+    }
+
+    // Moves back (if secs is negative) or forward from the current
+    // position, stopping at the start or end of the track
+    pub fn skip(&mut self, secs: f64) {
+        if self.loaded {
+            self.go_to(self.exact_pos() + secs);
+        }
+    }
+
+    pub fn seek(&mut self, pos: f64) {
+        self.paused_at = None;
+        if self.player.seek(self.handle, pos).is_ok() {
+            // Streamed tracks may take a little while to catch up
+            for _ in 0..SEEK_WAIT_LIMIT {
+                if self.player.stream_position(self.handle) >= pos {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(100));
+            }
+        }
+        self.pos = pos;
+        self.update_status();
+    }
+
+    // Returns true if the current track has been played (or seeked)
+    // and hasn't reached its end
+    pub fn has_started(&self) -> bool {
+        !self.first_to_play
+            && self.player.is_valid_voice_handle(self.handle)
+    }
+
+    // Returns true if the current track has played to the end
+    pub fn has_ended(&self) -> bool {
+        self.playing && !self.player.is_valid_voice_handle(self.handle)
+    }
+
+    // Returns the position reached in the current track, or 0.0 if it
+    // was played to the end
+    pub fn current_pos(&self) -> f64 {
+        if self.first_to_play
+            || self.player.is_valid_voice_handle(self.handle)
+        {
+            self.pos
+        } else {
+            0.0
+        }
+    }
+
+    // Real (rather than track) time remaining
+    pub fn remaining(&self) -> f64 {
+        (self.audio.length() - self.player.stream_position(self.handle))
+            / self.speed
+    }
+
+    pub fn set_volume(&mut self, volume: f64) {
+        self.volume = volume.clamp(0.0, 1.0);
+        self.player.set_volume(self.handle, self.volume as f32);
+        self.update_status();
+    }
+
+    // Plays at the given fraction of the volume without changing it
+    // (e.g., for the sleep timer's fade-out)
+    pub fn set_fade(&mut self, fraction: f64) {
+        let volume = self.volume * fraction.clamp(0.0, 1.0);
+        self.player.set_volume(self.handle, volume as f32);
+    }
+
+    // Sets the speed at the user's request and remembers it for the
+    // current track
+    pub fn set_speed(&mut self, speed: f64) {
+        self.apply_speed(speed);
+        let mut config = CONFIG.get().write().unwrap();
+        let track = config.track.clone();
+        if track.exists() {
+            config.set_speed(&track, self.speed);
+        }
+    }
+
+    fn apply_speed(&mut self, speed: f64) {
+        self.speed = speed.clamp(MIN_SPEED, MAX_SPEED);
+        // Ignore error
+        let _ = self
+            .player
+            .set_relative_play_speed(self.handle, self.speed as f32);
+        self.update_status();
+    }
+
+    // Goes to the previous chapter or track; returns true if the track
+    // changed
+    pub fn previous(&mut self) -> bool {
+        if let Some(pos) =
+            self.get_prev_or_next_chapter(WhichTrack::Previous)
+        {
+            self.play_from(pos);
+        } else if let Some(track) =
+            self.get_prev_or_next_track(WhichTrack::Previous)
+        {
+            self.auto_play_track(track);
+            return true;
+        }
+        false
+    }
+
+    // Goes to the next chapter or track; returns true if the track
+    // changed
+    pub fn next(&mut self) -> bool {
+        if let Some(pos) = self.get_prev_or_next_chapter(WhichTrack::Next) {
+            self.play_from(pos);
+            false
+        } else {
+            self.play_next_track()
+        }
+    }
+
+    // Returns true if there was a next track to play
+    pub fn play_next_track(&mut self) -> bool {
+        match self.take_next_track() {
+            Some(track) => {
+                self.auto_play_track(track);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Plays the next track once the current one has ended (or the same
+    // one if repeating it or looping), or stops if there isn't one;
+    // returns true if the track changed
+    pub fn end_of_track(&mut self) -> bool {
+        let (track, repeat) = {
+            let config = CONFIG.get().read().unwrap();
+            (config.track.clone(), config.repeat)
+        };
+        if self.looping || repeat == RepeatMode::Track {
+            // The track has stopped so must be reloaded to play again
+            let pos = if self.looping { self.loop_a } else { None };
+            let (loop_a, loop_b) = (self.loop_a, self.loop_b);
+            self.auto_play_track_at(track, pos.unwrap_or(0.0));
+            if pos.is_some() {
+                self.loop_a = loop_a; // Loading cleared the loop
+                self.loop_b = loop_b;
+                self.looping = true;
+            }
+            return false;
+        }
+        if self.play_next_track() {
+            return true;
+        }
+        self.playing = false;
+        self.update_status();
+        false
+    }
+
+    // Returns the start of the chapter before or after the current one
+    pub fn get_prev_or_next_chapter(
+        &self,
+        which: WhichTrack,
+    ) -> Option<f64> {
+        let chapters = self.chapters.borrow();
+        let pos = self.pos + 0.5; // Allow for ticks
+        let index =
+            match (which, chapters.iter().rposition(|c| c.pos <= pos)) {
+                (WhichTrack::Previous, Some(index)) if index > 0 => {
+                    index - 1
+                }
+                (WhichTrack::Next, Some(index)) => index + 1,
+                (WhichTrack::Next, None) => 0, // Before the first chapter
+                _ => return None,
+            };
+        chapters.get(index).map(|chapter| chapter.pos)
+    }
+
+    // Uses the shuffled order if shuffling, otherwise the playlist if the
+    // current track is in it, otherwise the current track's folder; wraps
+    // round if repeating all
+    pub fn get_prev_or_next_track(
+        &self,
+        which: WhichTrack,
+    ) -> Option<PathBuf> {
+        let config = CONFIG.get().read().unwrap();
+        let wrap = config.repeat == RepeatMode::All;
+        if config.shuffle != ShuffleMode::Off {
+            let order = &config.shuffled;
+            if let Some(index) =
+                order.iter().position(|t| *t == config.track)
+            {
+                let index = util::get_prev_or_next_index(
+                    index,
+                    order.len(),
+                    which,
+                    wrap,
+                    |i| order[i].exists(),
+                )?;
+                return order.get(index).cloned();
+            }
+        }
+        if let Some(playlist) = &config.playlist {
+            if playlist.contains(&config.track) {
+                return playlist.get_prev_or_next_track(
+                    &config.track,
+                    which,
+                    wrap,
+                );
+            }
+        }
+        util::get_prev_or_next_track(
+            &config.track,
+            which,
+            wrap,
+            config.recursive,
+        )
+    }
+
+    // Returns the track to play next: the first queued track if there is
+    // one (missing tracks are skipped but kept so that they can be
+    // relocated), otherwise the next one in order
+    pub fn peek_next_track(&self) -> Option<PathBuf> {
+        let queued = CONFIG
+            .get()
+            .read()
+            .unwrap()
+            .queue
+            .iter()
+            .find(|track| track.exists())
+            .cloned();
+        queued.or_else(|| self.get_prev_or_next_track(WhichTrack::Next))
+    }
+
+    // Returns the track to play next, taking it off the queue if queued
+    pub fn take_next_track(&mut self) -> Option<PathBuf> {
+        let track = self.peek_next_track()?;
+        CONFIG.get().write().unwrap().unqueue(&track);
+        Some(track)
+    }
+
+    // Adds the tracks (folders are expanded to their tracks, and playlists
+    // to theirs) to the end of the queue
+    pub fn add_to_queue(&mut self, paths: &[PathBuf]) {
+        let options =
+            Options { paths: paths.to_vec(), ..Default::default() };
+        let recursive = CONFIG.get().read().unwrap().recursive;
+        let tracks = options.get_tracks(recursive).0;
+        CONFIG.get().write().unwrap().queue.extend(tracks);
+        self.drop_preload();
+    }
+
+    pub fn set_queue(&mut self, queue: VecDeque<PathBuf>) {
+        CONFIG.get().write().unwrap().queue = queue;
+        self.drop_preload();
+    }
+
+    pub fn clear_queue(&mut self) {
+        self.set_queue(VecDeque::new());
+    }
+
+    // Handles the repeat mode, shuffle mode, and A/B loop actions
+    pub fn apply_repeat_action(&mut self, action: Action) {
+        match action {
+            Action::ClearLoop => self.clear_loop(),
+            Action::Repeat => {
+                let repeat = CONFIG.get().read().unwrap().repeat.cycled();
+                self.set_repeat(repeat);
+            }
+            Action::RepeatAll => self.set_repeat(RepeatMode::All),
+            Action::RepeatOff => self.set_repeat(RepeatMode::Off),
+            Action::RepeatTrack => self.set_repeat(RepeatMode::Track),
+            Action::SetLoopA => self.set_loop_a(),
+            Action::SetLoopB => self.set_loop_b(),
+            Action::Shuffle => {
+                let shuffle = CONFIG.get().read().unwrap().shuffle.cycled();
+                self.set_shuffle(shuffle);
+            }
+            Action::ShuffleAlbums => self.set_shuffle(ShuffleMode::Albums),
+            Action::ShuffleOff => self.set_shuffle(ShuffleMode::Off),
+            Action::ShuffleTracks => self.set_shuffle(ShuffleMode::Tracks),
+            Action::ToggleLoop => self.toggle_loop(),
+            _ => {}
+        }
+    }
+
+    // Skips back or forward by the skip or rewind action's interval
+    pub fn apply_skip_action(&mut self, action: Action) {
+        let secs = {
+            let config = CONFIG.get().read().unwrap();
+            match action {
+                Action::Rewind => -config.rewind,
+                Action::SkipBack => -config.skip_short,
+                Action::SkipBackLong => -config.skip_long,
+                Action::SkipBackMedium => -config.skip_medium,
+                Action::SkipForward => config.skip_short,
+                Action::SkipForwardLong => config.skip_long,
+                Action::SkipForwardMedium => config.skip_medium,
+                _ => return,
+            }
+        };
+        self.skip(secs);
+    }
+
+    pub fn set_repeat(&mut self, repeat: RepeatMode) {
+        CONFIG.get().write().unwrap().repeat = repeat;
+        self.drop_preload();
+    }
+
+    // The preloaded track may no longer be the right one
+    pub fn drop_preload(&mut self) {
+        self.next_audio = None;
+        self.loading = None;
+        self.preloaded = false;
+    }
+
+    // A new mode means a new order (starting from the current track)
+    pub fn set_shuffle(&mut self, shuffle: ShuffleMode) {
+        {
+            let mut config = CONFIG.get().write().unwrap();
+            if config.shuffle == shuffle {
+                return;
+            }
+            config.shuffle = shuffle;
+            config.shuffled.clear();
+        }
+        self.update_shuffle();
+        self.drop_preload();
+    }
+
+    // If shuffling and the current track isn't in the shuffled order
+    // (e.g., it is from another folder or playlist), makes a new order
+    // that starts from it; the order is kept so that each track is
+    // played once even across restarts
+    fn update_shuffle(&self) {
+        let mut config = CONFIG.get().write().unwrap();
+        if config.shuffle == ShuffleMode::Off {
+            config.shuffled.clear();
+            return;
+        }
+        let track = config.track.clone();
+        if !track.exists() || config.shuffled.contains(&track) {
+            return;
+        }
+        let tracks: Vec<PathBuf> = match &config.playlist {
+            Some(playlist) if playlist.contains(&track) => playlist
+                .tracks
+                .iter()
+                .map(|playlist_track| playlist_track.track.clone())
+                .collect(),
+            _ => util::get_sorted_tracks(&track, config.recursive),
+        };
+        config.shuffled =
+            shuffle::shuffled(&tracks, config.shuffle, &track);
+    }
+
+    // Sets the A/B loop's start (or end) to the current position; if the
+    // other end is on the wrong side of it, it is cleared
+    pub fn set_loop_a(&mut self) {
+        let pos = self.exact_pos();
+        self.loop_a = Some(pos);
+        if matches!(self.loop_b, Some(b) if b <= pos) {
+            self.loop_b = None;
+            self.looping = false;
+        }
+    }
+
+    pub fn set_loop_b(&mut self) {
+        let pos = self.exact_pos();
+        self.loop_b = Some(pos);
+        if matches!(self.loop_a, Some(a) if a >= pos) {
+            self.loop_a = None;
+            self.looping = false;
+        }
+    }
+
+    // Turns the A/B loop on (if both ends are set) or off
+    pub fn toggle_loop(&mut self) {
+        self.looping =
+            !self.looping && self.loop_a.is_some() && self.loop_b.is_some();
+        if self.looping {
+            self.drop_preload();
+        }
+    }
+
+    pub fn clear_loop(&mut self) {
+        self.loop_a = None;
+        self.loop_b = None;
+        self.looping = false;
+    }
+
+    pub fn auto_play_track(&mut self, track: PathBuf) {
+        let pos = {
+            let config = CONFIG.get().read().unwrap();
+            config.position_for(&track)
+        };
+        self.auto_play_track_at(track, pos);
+    }
+
+    pub fn auto_play_track_at(&mut self, track: PathBuf, pos: f64) {
+        self.switch_track(track, pos);
+        self.play_or_pause(); // PLAY
+    }
+
+    // Remembers the current track's position and loads the given track
+    // ready to play from pos
+    pub fn switch_track(&mut self, track: PathBuf, pos: f64) {
+        if self.playing {
+            self.play_or_pause(); // PAUSE
+        }
+        let old_pos = self.current_pos();
+        {
+            let mut config = CONFIG.get().write().unwrap();
+            let old_track = config.track.clone();
+            if old_track.exists() {
+                config.set_position(&old_track, old_pos);
+            }
+            config.pos = pos;
+            if let Some(playlist) = &mut config.playlist {
+                playlist.set_current(&track);
+            }
+            config.track = track;
+        }
+        self.load();
+    }
+
+    // Called regularly while playing to update the position and, near the
+    // end of the track, to preload and then start the next one (unless
+    // preload is false); returns true if the next track was started
+    pub fn tick(&mut self, preload: bool) -> bool {
+        let remaining = self.remaining();
+        let (crossfade, repeat) = {
+            let config = CONFIG.get().read().unwrap();
+            (config.crossfade, config.repeat)
+        };
+        // Looping or repeating the track replays the current one instead
+        let preload =
+            preload && !self.looping && repeat != RepeatMode::Track;
+        if preload
+            && !self.preloaded
+            && remaining <= PRELOAD_SECS + crossfade
+        {
+            self.preload_next_track();
+        }
+        if let Some(loading) = &self.loading {
+            if let Ok(next_audio) = loading.try_recv() {
+                self.next_audio = next_audio;
+                self.loading = None;
+            }
+        }
+        let started = preload
+            && self.next_audio.is_some()
+            && remaining <= crossfade.max(TICK_TIMEOUT * 2.0)
+            && self.start_next_track(crossfade);
+        self.pos = self.player.stream_position(self.handle);
+        if let (true, Some(a), Some(b)) =
+            (self.looping, self.loop_a, self.loop_b)
+        {
+            if self.pos >= b {
+                self.seek(a);
+            }
+        }
+        self.update_status();
+        started
+    }
+
+    // Starts loading the track that will follow the current one in the
+    // background so that it can be started without a gap
+    fn preload_next_track(&mut self) {
+        self.preloaded = true;
+        if let Some(track) = self.peek_next_track() {
+            let stream_size = {
+                let config = CONFIG.get().read().unwrap();
+                config.stream_size
+            };
+            self.loading =
+                Some(audio::load_in_background(track, stream_size));
+        }
+    }
+
+    // Starts the preloaded track (from its remembered position) either
+    // sample-accurately at the end of the current track (gapless) or
+    // overlapping it (crossfade)
+    fn start_next_track(&mut self, crossfade: f64) -> bool {
+        let (track, audio) = match self.next_audio.take() {
+            Some(next) => next,
+            None => return false,
+        };
+        let pos = {
+            let config = CONFIG.get().read().unwrap();
+            config.position_for(&track)
+        };
+        let volume = self.volume as f32;
+        let handle = audio.play(&self.player);
+        self.player.set_pause(handle, true);
+        // Ignore errors
+        let _ =
+            self.player.set_relative_play_speed(handle, self.speed as f32);
+        if pos > 0.0 {
+            let _ = self.player.seek(handle, pos);
+        }
+        // The remaining time is read from the mixer's position for the
+        // current track immediately before the new one is unpaused so that
+        // the delay (which the mixer counts down) ends where it does
+        if crossfade > 0.0 {
+            let remaining = self.remaining().max(TICK_TIMEOUT);
+            self.player.set_volume(handle, 0.0);
+            self.player.fade_volume(handle, volume, crossfade);
+            self.player.fade_volume(self.handle, 0.0, remaining);
+            self.player.schedule_stop(self.handle, remaining);
+            self.old_handle = Some(self.handle);
+        } else {
+            self.player.set_volume(handle, volume);
+            let samples = self.remaining().max(0.0)
+                * self.player.backend_samplerate() as f64;
+            self.player.set_delay_samples(handle, samples as u32);
+            self.old_handle = None;
+        }
+        self.player.set_pause(handle, false);
+        // The old audio must live until it has finished playing
+        self.old_audio = Some(std::mem::replace(&mut self.audio, audio));
+        self.handle = handle;
+        self.pos = pos;
+        self.preloaded = false;
+        {
+            let mut config = CONFIG.get().write().unwrap();
+            let old_track = config.track.clone();
+            config.set_position(&old_track, 0.0); // Played to the end
+            config.pos = pos;
+            if let Some(playlist) = &mut config.playlist {
+                playlist.set_current(&track);
+            }
+            config.unqueue(&track);
+            config.track = track.clone();
+        }
+        self.track_changed(&track);
+        true
+    }
+
+    // Handles the options forwarded by a later instance; returns true if
+    // the track changed
+    pub fn handle_args(&mut self, options: &Options) -> bool {
+        if let Some(volume) = options.volume {
+            self.set_volume(volume);
+        }
+        let mut changed = false;
+        if options.enqueue {
+            let recursive = CONFIG.get().read().unwrap().recursive;
+            changed = self.enqueue(&options.get_tracks(recursive).0);
+        } else {
+            let track = {
+                let mut config = CONFIG.get().write().unwrap();
+                options.apply_playlist(&mut config)
+            };
+            if let Some(track) = track {
+                let pos = match options.position {
+                    Some(pos) => pos,
+                    None => {
+                        let config = CONFIG.get().read().unwrap();
+                        config.position_for(&track)
+                    }
+                };
+                self.auto_play_track_at(track, pos);
+                if options.paused {
+                    self.play_or_pause(); // PAUSE
+                }
+                changed = true;
+            } else if let Some(pos) = options.position {
+                self.play_from(pos);
+            }
+        }
+        if options.play_pause {
+            self.play_or_pause();
+        }
+        if options.next && self.next() {
+            changed = true;
+        }
+        changed
+    }
+
+    // Adds the tracks to the end of the playlist (which is started with
+    // the current track if it isn't in the playlist); returns true if
+    // there was no track so the first one was loaded
+    pub fn enqueue(&mut self, tracks: &[PathBuf]) -> bool {
+        if tracks.is_empty() {
+            return false;
+        }
+        let first = {
+            let mut config = CONFIG.get().write().unwrap();
+            let track = config.track.clone();
+            let has_track = track.exists();
+            let in_playlist = match &config.playlist {
+                Some(playlist) => playlist.contains(&track),
+                None => false,
+            };
+            if !in_playlist {
+                let current = if has_track { vec![track] } else { vec![] };
+                config.playlist = Some(Playlist::new("", current));
+            }
+            if let Some(playlist) = &mut config.playlist {
+                playlist
+                    .tracks
+                    .extend(tracks.iter().cloned().map(PlaylistTrack::new));
+            }
+            if config.shuffle != ShuffleMode::Off {
+                if in_playlist {
+                    // Enqueued tracks are shuffled in after the rest
+                    let more = shuffle::shuffled(
+                        tracks,
+                        config.shuffle,
+                        Path::new(""),
+                    );
+                    config.shuffled.extend(more);
+                } else {
+                    config.shuffled.clear(); // Reshuffle the new playlist
+                }
+            }
+            if has_track {
+                None
+            } else {
+                Some(tracks[0].clone())
+            }
+        };
+        match first {
+            Some(track) => {
+                self.auto_play_track(track); // Nothing was loaded
+                true
+            }
+            None => {
+                self.update_shuffle();
+                false
+            }
+        }
+    }
+
+    // Returns the position right now (rather than as of the last tick)
+    pub fn exact_pos(&self) -> f64 {
+        if self.first_to_play {
+            self.pos
+        } else {
+            self.player.stream_position(self.handle)
+        }
+    }
+
+    // Bookmarks the current track at the given position; returns false if
+    // there's no track
+    pub fn add_bookmark(&self, pos: f64, note: String) -> bool {
+        let mut config = CONFIG.get().write().unwrap();
+        if !config.track.exists() {
+            return false;
+        }
+        let track = config.track.clone();
+        config.add_bookmark(Bookmark {
+            track,
+            pos,
+            note,
+            created: Local::now(),
+        });
+        true
+    }
+
+    // Returns true if the current track was added to the history
+    pub fn add_to_history(&self) -> bool {
+        let mut config = CONFIG.get().write().unwrap();
+        let track = config.track.clone();
+        let size = config.history_size;
+        util::maybe_add_to_deque(&mut config.history, track, size)
+    }
+
+    // Rebases the missing remembered tracks from old to new; if nothing
+    // is loaded and the current track has been found it is loaded;
+    // returns how many paths were changed
+    pub fn relocate(&mut self, old: &Path, new: &Path) -> usize {
+        let (count, has_track) = {
+            let mut config = CONFIG.get().write().unwrap();
+            let count = config.relocate(old, new);
+            (count, config.track.exists())
+        };
+        if count > 0 {
+            self.drop_preload();
+            if !self.loaded && has_track {
+                self.load();
+            }
+        }
+        count
+    }
+
+    // Saves the volume and position along with the rest of the config
+    pub fn save(&self) {
+        let mut config = CONFIG.get().write().unwrap();
+        config.volume = self.volume;
+        config.pos = self.pos;
+        config.stopped = match &self.paused_at {
+            Some((_, at)) if !self.playing => Some(*at),
+            _ => Some(Local::now()),
+        };
+        let (track, pos) = (config.track.clone(), config.pos);
+        config.set_position(&track, pos);
+        // We already have the track, history, and bookmarks
+        config.save();
+    }
+
+    // Keeps the status snapshot that other threads read up to date
+    pub fn update_status(&self) {
+        let mut status = STATUS.get().write().unwrap();
+        status.playing = self.playing;
+        status.pos = self.pos;
+        status.length = self.audio.length();
+        status.volume = self.volume;
+        status.speed = self.speed;
+    }
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
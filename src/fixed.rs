@@ -10,11 +10,14 @@ use std::env;
 pub static APPNAME: &str = "AMP";
 pub static VERSION: &str = "1.2.5";
 pub static HELP_HTML: &str = include_str!("../data/help.html");
+pub static HELP_KEYS_MARKER: &str = "<!-- KEYS -->"; // Replaced by the keys
 pub const ICON: &str = include_str!("../images/amp.svg");
 pub const LOAD_ICON: &str = include_str!("../images/document-open.svg");
 pub const PREV_ICON: &str =
     include_str!("../images/media-seek-backward.svg");
 pub const REPLAY_ICON: &str = include_str!("../images/replay.svg");
+pub const REWIND_ICON: &str = include_str!("../images/rewind.svg");
+pub const REPEAT_ICON: &str = include_str!("../images/repeat.svg");
 pub const PLAY_ICON: &str =
     include_str!("../images/media-playback-start.svg");
 pub const PAUSE_ICON: &str =
@@ -24,8 +27,10 @@ pub const NEXT_ICON: &str =
 pub const VOLUME_ICON: &str =
     include_str!("../images/audio-volume-high.svg");
 pub const TIME_ICON: &str = include_str!("../images/time.svg");
+pub const SPEED_ICON: &str = include_str!("../images/speed.svg");
 pub const HISTORY_ICON: &str = include_str!("../images/history.svg");
 pub const BOOKMARKS_ICON: &str = include_str!("../images/bookmarks.svg");
+pub const CHAPTERS_ICON: &str = include_str!("../images/chapters.svg");
 pub const ADD_BOOKMARK_ICON: &str =
     include_str!("../images/addbookmark.svg");
 pub const DELETE_BOOKMARK_ICON: &str =
@@ -35,9 +40,38 @@ pub const DEF_HISTORY_SIZE: usize = 26;
 pub const MIN_HISTORY_SIZE: usize = 2;
 pub const MAX_HISTORY_SIZE: usize = 35;
 pub const BOOKMARKS_SIZE: usize = 35;
+pub const DEF_POSITIONS_SIZE: usize = 100;
+pub const MIN_POSITIONS_SIZE: usize = 10;
+pub const MAX_POSITIONS_SIZE: usize = 1000;
+pub const DEF_STREAM_SIZE: u64 = 50; // MB
+pub const MAX_STREAM_SIZE: u64 = 4096; // MB
+pub const SEEK_WAIT_LIMIT: usize = 50; // × 100ms
+pub const INSTANCE_WAIT_LIMIT: usize = 30; // × 100ms
+pub const PRELOAD_SECS: f64 = 10.0;
+pub const MAX_CROSSFADE: f64 = 10.0;
+pub const MIN_SPEED: f64 = 0.5;
+pub const MAX_SPEED: f64 = 3.0;
+pub const SPEED_STEP: f64 = 0.05;
+pub const DEF_SLEEP_FADE: f64 = 30.0;
+pub const MAX_SLEEP_FADE: f64 = 300.0;
+pub const SLEEP_EXTEND_SECS: f64 = 60.0;
+pub const USER_ACTIVITY_INTERVAL: f64 = 1.0; // secs between reports
+pub const DEF_SKIP_SHORT: f64 = 10.0; // secs
+pub const DEF_SKIP_MEDIUM: f64 = 60.0; // secs
+pub const DEF_SKIP_LONG: f64 = 300.0; // secs
+pub const DEF_REWIND: f64 = 30.0; // secs; for the rewind button
+pub const MAX_SKIP: f64 = 3600.0; // secs
+pub const MAX_RESUME_REWIND: f64 = 300.0; // secs
+pub const RESUME_REWIND_MIN_PAUSE: f64 = 5.0; // secs; shorter: no rewind
+pub const RESUME_REWIND_FULL_PAUSE: f64 = 86400.0; // secs; longer: all
+pub const MAX_FOLDER_TRACKS: usize = 10000; // recursive walks stop here
+pub static SLEEP_MINUTES: [u32; 4] = [15, 30, 45, 60];
+pub static SLEEP_CUSTOM: &str = "Custom…";
+pub static SLEEP_END_OF_TRACK: &str = "End of Track";
+pub static SLEEP_OFF: &str = "Off";
 pub const PAD: i32 = 6;
-pub const WINDOW_WIDTH_MIN: i32 = 480;
-pub const WINDOW_HEIGHT_MIN: i32 = 240;
+pub const WINDOW_WIDTH_MIN: i32 = 600;
+pub const WINDOW_HEIGHT_MIN: i32 = 270;
 pub const TOOLBUTTON_SIZE: i32 = 28;
 pub const TOOLBAR_HEIGHT: i32 = ((TOOLBUTTON_SIZE * 3) / 2) + (2 * PAD);
 pub const BUTTON_HEIGHT: i32 = 30;
@@ -46,49 +80,98 @@ pub const SCALE_MIN: f32 = 0.5;
 pub const SCALE_MAX: f32 = 3.5;
 pub const TINY_TIMEOUT: f64 = 0.075;
 pub const TICK_TIMEOUT: f64 = 0.1;
+pub const DEF_AUTOSAVE: f64 = 60.0; // secs
+pub const MAX_AUTOSAVE: f64 = 3600.0; // secs
+pub const CONFIG_VERSION: u32 = 2; // of the config file's format
+pub static DEF_REMOTE_ADDRESS: &str = "127.0.0.1:8765";
+pub const REMOTE_TIMEOUT_SECS: u64 = 5;
+pub const REMOTE_MAX_CONNECTIONS: usize = 8;
+pub const REMOTE_MAX_REQUEST: u64 = 8192; // bytes of request line and headers
+pub const LIBRARY_BUSY_TIMEOUT: u64 = 10; // secs
+pub const MAX_LIBRARY_RESULTS: usize = 500; // shown in the search form
+pub static NO_LIBRARY_ROOTS: &str =
+    "Set Library Roots in the Options dialog and then use Rescan Library";
 pub static MENU_CHARS: [char; 35] = [
     '1', '2', '3', '4', '5', '6', '7', '8', '9', 'A', 'B', 'C', 'D', 'E',
     'F', 'G', 'H', 'I', 'J', 'K', 'L', 'M', 'N', 'O', 'P', 'Q', 'R', 'S',
     'T', 'U', 'V', 'W', 'X', 'Y', 'Z',
 ];
 pub const PATH_SEP: char = '→';
+pub static AUDIO_SUFFIXES: [&str; 6] =
+    ["flac", "mogg", "mp3", "oga", "ogg", "wav"];
 
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum Action {
     About,
     AddBookmark,
     AddToHistory,
+    ClearLoop,
+    ClearQueue,
+    ClosePlaylist,
     DeleteBookmark,
+    Enqueue,
+    HandleCommands,
     Help,
+    LibraryRescanned,
     Load,
     LoadBookmarkedTrack,
+    LoadChapter,
     LoadHistoryTrack,
     MainMenu,
     Next,
+    NextTrack,
     OnBookmarkMenu,
+    OnChapterMenu,
     OnHistoryMenu,
     OnMenuMenu,
+    OnRepeatMenu,
     OnStartup,
+    OpenFolder,
     Options,
     PlayOrPause,
     Previous,
     Quit,
+    Relocate,
+    Repeat,
+    RepeatAll,
+    RepeatOff,
+    RepeatTrack,
     Replay,
+    RescanLibrary,
+    Rewind,
+    RunCommand,
+    SavePlaylist,
+    SearchLibrary,
+    SetLoopA,
+    SetLoopB,
+    ShowQueue,
+    Shuffle,
+    ShuffleAlbums,
+    ShuffleOff,
+    ShuffleTracks,
+    SkipBack,
+    SkipBackLong,
+    SkipBackMedium,
+    SkipForward,
+    SkipForwardLong,
+    SkipForwardMedium,
+    SleepTimer,
     SpacePressed,
+    SpeedDown,
+    SpeedNormal,
+    SpeedUp,
+    SpeedUpdate,
     Tick,
+    ToggleLoop,
     TimeUpdate,
+    UserActivity,
     VolumeDown,
     VolumeUp,
     VolumeUpdate,
 }
 
 pub fn about_html(player: &Soloud) -> String {
-    let year = Local::today().year();
-    let year = if year == 2021 {
-        year.to_string()
-    } else {
-        format!("2021-{}", year - 2000)
-    };
+    let (libraries, platform) = version_details(Some(player));
     format!(
         "<p><center><font size=6 color=navy><b>{}</b> v{}</font>
 </center></p>
@@ -104,22 +187,71 @@ All rights reserved.<br>
 License: GPLv3.</font>
 </center></p>
 <p><center><font size=4 color=#555>
-Rust {} • fltk-rs {} • FLTK {}<br>Soloud {}/{} • {}/{}
+{}<br>{}
 </font></center></p>",
         APPNAME,
         VERSION,
-        year,
+        copyright_years(),
+        libraries,
+        platform
+    )
+}
+
+// The plain text equivalent of about_html() for --version
+pub fn about_text(player: Option<&Soloud>) -> String {
+    let (libraries, platform) = version_details(player);
+    format!(
+        "{APPNAME} v{VERSION} “Another Music Player‟
+https://github.com/mark-summerfield/amp-rs
+Copyright © {} Mark Summerfield. All rights reserved.
+License: GPLv3.
+{libraries}
+{platform}",
+        copyright_years()
+    )
+}
+
+fn copyright_years() -> String {
+    let year = Local::today().year();
+    if year == 2021 {
+        year.to_string()
+    } else {
+        format!("2021-{}", year - 2000)
+    }
+}
+
+// Returns the library versions and the platform (with the audio backend
+// if there is one)
+fn version_details(player: Option<&Soloud>) -> (String, String) {
+    let libraries = format!(
+        "Rust {} • fltk-rs {} • FLTK {} • SQLite {}",
         rustc_version_runtime::version(),
         app::crate_version(),
         app::version_str(),
-        player.version(),
-        player.backend_string(),
+        rusqlite::version()
+    );
+    let platform = format!(
+        "{}/{}",
         capitalize_first(env::consts::OS),
         env::consts::ARCH
-    )
+    );
+    let platform = match player {
+        Some(player) => format!(
+            "Soloud {}/{} • {platform}",
+            player.version(),
+            player.backend_string()
+        ),
+        None => platform,
+    };
+    (libraries, platform)
 }
 
 pub static LOAD_ERROR: &str = "
 <font color=red><b>Error</b><br>Failed to open</font>
 <font color=magenta>\"FILE\".</font>
 </body>";
+
+pub static NO_TRACKS_ERROR: &str = "
+<font color=red><b>Error</b><br>No tracks in</font>
+<font color=magenta>\"FOLDER\".</font>
+</body>";
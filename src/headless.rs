@@ -0,0 +1,275 @@
+// Copyright © 2021-22 Mark Summerfield. All rights reserved.
+// License: GPLv3
+
+// Plays without a window (and without using FLTK at all): the engine is
+// controlled by later instances (e.g., amp --play-pause) and MPRIS, and
+// the position and history are saved to the config just as for the GUI
+
+use super::CONFIG;
+use crate::command::{self, Command};
+use crate::engine::Engine;
+use crate::fixed::{Action, APPNAME, SPEED_STEP, TICK_TIMEOUT};
+use crate::ipc::{self, ActionSender, Listener};
+use crate::library;
+#[cfg(target_os = "linux")]
+use crate::mpris;
+use crate::remote;
+use std::{
+    path::{Path, PathBuf},
+    sync::mpsc::{self, RecvTimeoutError},
+    time::{Duration, Instant},
+};
+
+pub fn run(listener: Listener, play: bool, scripts: Vec<PathBuf>) {
+    let (sender, receiver) = mpsc::channel();
+    let sender = ActionSender::Channel(sender);
+    ipc::serve(listener, sender.clone());
+    remote::serve(sender.clone());
+    #[cfg(target_os = "linux")]
+    mpris::serve(sender.clone());
+    // Ctrl+C and SIGTERM save and quit
+    let _ = ctrlc::set_handler(move || sender.send(Action::Quit));
+    let mut engine = Engine::new();
+    let has_track = {
+        let config = CONFIG.get().read().unwrap();
+        config.track.exists()
+    };
+    if has_track {
+        engine.load();
+        track_changed(&engine);
+        if play {
+            engine.resume_or_pause(); // PLAY
+        }
+    }
+    // A script may quit
+    if scripts.iter().all(|script| run_script(&mut engine, script)) {
+        run_loop(&mut engine, &receiver);
+    }
+    engine.save();
+    ipc::cleanup();
+}
+
+// Handles actions and ticks until told to quit
+fn run_loop(engine: &mut Engine, receiver: &mpsc::Receiver<Action>) {
+    let timeout = Duration::from_secs_f64(TICK_TIMEOUT);
+    let mut saved = Instant::now();
+    loop {
+        match receiver.recv_timeout(timeout) {
+            Ok(action) => {
+                if !handle_action(engine, action) {
+                    break;
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+        if let Some(report) = library::take_scan_report() {
+            eprintln!("{APPNAME}: {report}");
+        }
+        if engine.playing {
+            let changed = if engine.has_ended() {
+                engine.end_of_track()
+            } else {
+                engine.tick(true)
+            };
+            if changed {
+                track_changed(engine);
+            }
+            let autosave = CONFIG.get().read().unwrap().autosave;
+            if autosave > 0.0 && saved.elapsed().as_secs_f64() >= autosave {
+                engine.save();
+                saved = Instant::now();
+            }
+        }
+    }
+}
+
+// Returns false if the action means quit
+fn handle_action(engine: &mut Engine, action: Action) -> bool {
+    let was_playing = engine.playing;
+    let changed = match action {
+        Action::PlayOrPause | Action::SpacePressed => {
+            engine.resume_or_pause();
+            false
+        }
+        Action::Previous => engine.previous(),
+        Action::Next => engine.next(),
+        Action::NextTrack => engine.play_next_track(),
+        Action::Replay => {
+            engine.replay();
+            false
+        }
+        Action::VolumeDown => {
+            engine.set_volume(engine.volume - 0.05);
+            false
+        }
+        Action::VolumeUp => {
+            engine.set_volume(engine.volume + 0.05);
+            false
+        }
+        Action::SpeedDown => {
+            engine.set_speed(engine.speed - SPEED_STEP * 2.0);
+            false
+        }
+        Action::SpeedNormal => {
+            engine.set_speed(1.0);
+            false
+        }
+        Action::SpeedUp => {
+            engine.set_speed(engine.speed + SPEED_STEP * 2.0);
+            false
+        }
+        Action::HandleCommands => {
+            while let Some(command) = ipc::take_command() {
+                if !run_command(engine, command) {
+                    return false;
+                }
+            }
+            false
+        }
+        Action::ClearQueue => {
+            engine.clear_queue();
+            false
+        }
+        Action::ClearLoop
+        | Action::Repeat
+        | Action::RepeatAll
+        | Action::RepeatOff
+        | Action::RepeatTrack
+        | Action::SetLoopA
+        | Action::SetLoopB
+        | Action::Shuffle
+        | Action::ShuffleAlbums
+        | Action::ShuffleOff
+        | Action::ShuffleTracks
+        | Action::ToggleLoop => {
+            engine.apply_repeat_action(action);
+            false
+        }
+        Action::Rewind
+        | Action::SkipBack
+        | Action::SkipBackLong
+        | Action::SkipBackMedium
+        | Action::SkipForward
+        | Action::SkipForwardLong
+        | Action::SkipForwardMedium => {
+            engine.apply_skip_action(action);
+            false
+        }
+        Action::AddBookmark => {
+            // There's no window to ask for a note
+            engine.add_bookmark(engine.exact_pos(), String::new());
+            false
+        }
+        Action::RescanLibrary => {
+            rescan_library();
+            false
+        }
+        Action::Quit => return false,
+        _ => false, // The other actions are only for the window
+    };
+    if changed {
+        track_changed(engine);
+    }
+    if was_playing && !engine.playing {
+        engine.save(); // Paused
+    }
+    true
+}
+
+// Without a window the rescan's report is picked up by run_loop()
+fn rescan_library() {
+    let (filename, roots) = {
+        let config = CONFIG.get().read().unwrap();
+        (config.library_filename(), config.library_roots.clone())
+    };
+    if roots.is_empty() {
+        eprintln!("{APPNAME}: no library roots to rescan");
+    } else if !library::rescan_in_background(filename, roots, None) {
+        eprintln!("{APPNAME}: the library is already being rescanned");
+    }
+}
+
+// Returns false if the command means quit
+fn run_command(engine: &mut Engine, command: Command) -> bool {
+    let changed = match command {
+        Command::Args(options) => {
+            if options.quit {
+                return false;
+            }
+            let changed = engine.handle_args(&options);
+            if let Some(script) = &options.script {
+                if !run_script(engine, script) {
+                    return false;
+                }
+            }
+            changed
+        }
+        Command::Do(action) => return handle_action(engine, action),
+        Command::Play | Command::Pause => {
+            let play = matches!(command, Command::Play);
+            if engine.playing != play {
+                engine.resume_or_pause();
+            }
+            false
+        }
+        Command::Seek(pos) => {
+            engine.go_to(pos);
+            false
+        }
+        Command::Skip(secs) => {
+            engine.skip(secs);
+            false
+        }
+        Command::Volume(volume) => {
+            engine.set_volume(volume);
+            false
+        }
+        Command::Speed(speed) => {
+            engine.set_speed(speed);
+            false
+        }
+        Command::Bookmark(note) => {
+            engine.add_bookmark(engine.exact_pos(), note);
+            false
+        }
+        Command::Queue(paths) => {
+            engine.add_to_queue(&paths);
+            !engine.loaded && engine.play_next_track()
+        }
+        Command::Relocate(old, new) => {
+            let loaded = engine.loaded;
+            let count = engine.relocate(&old, &new);
+            eprintln!("{APPNAME}: relocated {count} missing tracks");
+            !loaded && engine.loaded
+        }
+    };
+    if changed {
+        track_changed(engine);
+    }
+    true
+}
+
+// Returns false if the script quits
+fn run_script(engine: &mut Engine, filename: &Path) -> bool {
+    match command::read_script(filename) {
+        Ok(commands) => {
+            for command in commands {
+                if !run_command(engine, command) {
+                    return false;
+                }
+            }
+        }
+        Err(err) => eprintln!("{APPNAME}: {err}"),
+    }
+    true
+}
+
+fn track_changed(engine: &Engine) {
+    if engine.loaded {
+        engine.add_to_history();
+    } else {
+        let config = CONFIG.get().read().unwrap();
+        eprintln!("{APPNAME}: failed to load {:?}", config.track);
+    }
+}
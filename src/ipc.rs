@@ -0,0 +1,185 @@
+// Copyright © 2021-22 Mark Summerfield. All rights reserved.
+// License: GPLv3
+
+// Only one instance runs per config file: later instances forward their
+// (parsed) arguments one per line over a Unix domain socket and then quit.
+// The instance that listens holds an advisory lock on a file beside the
+// socket so that two instances started at once can't both take it over
+
+use crate::cli::Options;
+use crate::command::Command;
+use crate::fixed::Action;
+use fltk::app;
+use state::Storage;
+use std::{
+    collections::VecDeque,
+    path::Path,
+    sync::{mpsc, Mutex},
+};
+#[cfg(unix)]
+use {
+    crate::fixed::{APPNAME, INSTANCE_WAIT_LIMIT},
+    std::{
+        collections::hash_map::DefaultHasher,
+        env,
+        ffi::OsString,
+        fs::{self, File, OpenOptions},
+        hash::{Hash, Hasher},
+        io::{BufRead, BufReader, Write},
+        os::unix::{
+            io::AsRawFd,
+            net::{UnixListener, UnixStream},
+        },
+        path::PathBuf,
+        thread,
+        time::Duration,
+    },
+};
+
+static COMMANDS: Storage<Mutex<VecDeque<Command>>> = Storage::new();
+#[cfg(unix)]
+static SOCKET_PATH: Storage<PathBuf> = Storage::new();
+#[cfg(unix)]
+static SOCKET_LOCK: Storage<File> = Storage::new(); // held until exit
+
+pub struct Listener {
+    #[cfg(unix)]
+    listener: Option<UnixListener>, // None if the socket isn't available
+}
+
+// Actions go to the FLTK event loop or, in headless mode, to a channel
+#[derive(Clone)]
+pub enum ActionSender {
+    Fltk(app::Sender<Action>),
+    Channel(mpsc::Sender<Action>),
+}
+
+impl ActionSender {
+    pub fn send(&self, action: Action) {
+        match self {
+            ActionSender::Fltk(sender) => sender.send(action),
+            ActionSender::Channel(sender) => {
+                let _ = sender.send(action); // Ignore error: quitting
+            }
+        }
+    }
+}
+
+// Queues the command and tells the application to handle it
+pub fn send_command(command: Command, sender: &ActionSender) {
+    if let Some(commands) = COMMANDS.try_get() {
+        commands.lock().unwrap().push_back(command);
+        sender.send(Action::HandleCommands);
+    }
+}
+
+// Returns the next command, if any
+pub fn take_command() -> Option<Command> {
+    COMMANDS.try_get()?.lock().unwrap().pop_front()
+}
+
+// Returns None if another instance is running and has been sent the
+// options; otherwise returns a listener for later instances' requests
+// (or, if the socket can't be had, a listener that listens for nothing)
+#[cfg(unix)]
+pub fn forward_or_listen(
+    options: &Options,
+    config_filename: &Path,
+) -> Option<Listener> {
+    let path = get_socket_path(config_filename);
+    let mut text = options.to_args().join("\n");
+    text.push('\n');
+    // If another instance is starting up it holds the lock, so wait for
+    // it to listen and then forward to it
+    for _ in 0..INSTANCE_WAIT_LIMIT {
+        if let Ok(mut stream) = UnixStream::connect(&path) {
+            if stream.write_all(text.as_bytes()).is_ok() {
+                return None;
+            }
+        }
+        if let Some(lock) = try_lock(&path.with_extension("lock")) {
+            let _ = fs::remove_file(&path); // Ignore error; may not exist
+            let listener = UnixListener::bind(&path).ok();
+            if listener.is_some() {
+                SOCKET_PATH.set(path);
+                SOCKET_LOCK.set(lock);
+            }
+            return Some(Listener { listener });
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    Some(Listener { listener: None })
+}
+
+// Returns the lock file locked, or None if another instance has it
+#[cfg(unix)]
+fn try_lock(path: &Path) -> Option<File> {
+    let file =
+        OpenOptions::new().create(true).write(true).open(path).ok()?;
+    // Safe: the descriptor is open for as long as the file is
+    let locked = unsafe {
+        libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB)
+    };
+    if locked == 0 {
+        Some(file)
+    } else {
+        None
+    }
+}
+
+#[cfg(not(unix))]
+pub fn forward_or_listen(
+    _options: &Options,
+    _config_filename: &Path,
+) -> Option<Listener> {
+    Some(Listener {})
+}
+
+// Accepts requests in a background thread; each is queued and the
+// application told to handle it
+pub fn serve(listener: Listener, sender: ActionSender) {
+    COMMANDS.set(Mutex::new(VecDeque::new()));
+    #[cfg(unix)]
+    if let Some(listener) = listener.listener {
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let args: Vec<OsString> = BufReader::new(stream)
+                    .lines()
+                    .flatten()
+                    .map(OsString::from)
+                    .collect();
+                match Options::try_parse(args.into_iter()) {
+                    Ok(options) => {
+                        send_command(Command::Args(options), &sender)
+                    }
+                    Err(err) => eprintln!(
+                        "{APPNAME}: ignored forwarded arguments: {err}"
+                    ),
+                }
+            }
+        });
+    }
+    #[cfg(not(unix))]
+    let _ = (listener, sender);
+}
+
+// Removes the socket so that the next instance starts cleanly
+pub fn cleanup() {
+    #[cfg(unix)]
+    if let Some(path) = SOCKET_PATH.try_get() {
+        let _ = fs::remove_file(path); // Ignore error
+    }
+}
+
+// The socket is per user (runtime folder) and per config file
+#[cfg(unix)]
+fn get_socket_path(config_filename: &Path) -> PathBuf {
+    let mut hasher = DefaultHasher::new();
+    config_filename.hash(&mut hasher);
+    let dir = dirs::runtime_dir().unwrap_or_else(env::temp_dir);
+    dir.join(format!(
+        "{}-{:x}.sock",
+        APPNAME.to_lowercase(),
+        hasher.finish()
+    ))
+}
@@ -0,0 +1,747 @@
+// Copyright © 2021-22 Mark Summerfield. All rights reserved.
+// License: GPLv3
+
+// Key bindings: each bindable action has zero or more key chords, e.g.,
+// "p", "Space", "F5", or "Ctrl+Right". In the config file's [Keys]
+// section each action's chords are separated by spaces, e.g.,
+// volume-up=+ =. A chord may only be bound to one action.
+
+use crate::fixed::Action;
+use fltk::{app, enums::Key};
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Keymap {
+    bindings: Vec<Binding>, // in BINDABLE order
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Binding {
+    pub name: &'static str,
+    pub action: Action,
+    pub description: &'static str,
+    pub chords: Vec<Chord>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Chord {
+    key: ChordKey,
+    ctrl: bool,
+    alt: bool,
+    shift: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ChordKey {
+    Char(char), // letters are held in lowercase
+    Named(Key),
+}
+
+// name, action, description, default chords
+static BINDABLE: [(&str, Action, &str, &str); 50] = [
+    (
+        "play-pause",
+        Action::PlayOrPause,
+        "Play or Pause the current track",
+        "Space p",
+    ),
+    ("quit", Action::Quit, "Quit", "Escape"),
+    ("volume-down", Action::VolumeDown, "Reduce the volume", "-"),
+    ("volume-up", Action::VolumeUp, "Increase the volume", "+ ="),
+    ("speed-down", Action::SpeedDown, "Play more slowly", "["),
+    ("speed-up", Action::SpeedUp, "Play faster", "]"),
+    ("speed-normal", Action::SpeedNormal, "Play at normal speed", "\\"),
+    (
+        "previous",
+        Action::Previous,
+        "Go to the previous chapter (if the track has chapters), or else \
+         start playing the previous track (if any)",
+        "F4",
+    ),
+    (
+        "replay",
+        Action::Replay,
+        "Replay the current track from the beginning",
+        "F5 r",
+    ),
+    (
+        "next",
+        Action::Next,
+        "Go to the next chapter (if the track has chapters), or else \
+         start playing the next track (if any)",
+        "F6",
+    ),
+    (
+        "skip-back",
+        Action::SkipBack,
+        "Skip back by the short skip interval (10″ by default)",
+        "Left",
+    ),
+    (
+        "skip-forward",
+        Action::SkipForward,
+        "Skip forward by the short skip interval (10″ by default)",
+        "Right",
+    ),
+    (
+        "skip-back-medium",
+        Action::SkipBackMedium,
+        "Skip back by the medium skip interval (1′ by default)",
+        "Shift+Left",
+    ),
+    (
+        "skip-forward-medium",
+        Action::SkipForwardMedium,
+        "Skip forward by the medium skip interval (1′ by default)",
+        "Shift+Right",
+    ),
+    (
+        "skip-back-long",
+        Action::SkipBackLong,
+        "Skip back by the long skip interval (5′ by default)",
+        "Ctrl+Left",
+    ),
+    (
+        "skip-forward-long",
+        Action::SkipForwardLong,
+        "Skip forward by the long skip interval (5′ by default)",
+        "Ctrl+Right",
+    ),
+    (
+        "rewind",
+        Action::Rewind,
+        "Go back by the rewind button's interval (30″ by default)",
+        "BackSpace",
+    ),
+    (
+        "loop-a",
+        Action::SetLoopA,
+        "Set the start of the A/B loop to the current position",
+        "Shift+a",
+    ),
+    (
+        "loop-b",
+        Action::SetLoopB,
+        "Set the end of the A/B loop to the current position",
+        "Shift+b",
+    ),
+    ("loop", Action::ToggleLoop, "Turn the A/B loop on or off", "l"),
+    ("clear-loop", Action::ClearLoop, "Clear the A/B loop", "Shift+l"),
+    (
+        "repeat",
+        Action::Repeat,
+        "Change the repeat mode (off → track → all → off)",
+        "Shift+r",
+    ),
+    ("repeat-off", Action::RepeatOff, "Don't repeat", ""),
+    ("repeat-track", Action::RepeatTrack, "Repeat the current track", ""),
+    (
+        "repeat-all",
+        Action::RepeatAll,
+        "Repeat the whole folder or playlist",
+        "",
+    ),
+    (
+        "shuffle",
+        Action::Shuffle,
+        "Change the shuffle mode (off → tracks → albums → off)",
+        "Shift+s",
+    ),
+    (
+        "shuffle-off",
+        Action::ShuffleOff,
+        "Play in folder or playlist order",
+        "",
+    ),
+    (
+        "shuffle-tracks",
+        Action::ShuffleTracks,
+        "Play the tracks in a random order",
+        "",
+    ),
+    (
+        "shuffle-albums",
+        Action::ShuffleAlbums,
+        "Play the albums in a random order",
+        "",
+    ),
+    (
+        "repeat-menu",
+        Action::OnRepeatMenu,
+        "Pop up the repeat, shuffle, and A/B loop menu",
+        "",
+    ),
+    (
+        "add-bookmark",
+        Action::AddBookmark,
+        "Bookmark the current position in the current track (with an \
+         optional note)",
+        "a",
+    ),
+    (
+        "bookmarks-menu",
+        Action::OnBookmarkMenu,
+        "Pop up the bookmarks menu (initially empty); choose a bookmark \
+         to play from its position",
+        "b",
+    ),
+    (
+        "chapters-menu",
+        Action::OnChapterMenu,
+        "Pop up the current track's chapters menu (if it has chapters); \
+         choose a chapter to play from its start",
+        "c",
+    ),
+    (
+        "delete-bookmark",
+        Action::DeleteBookmark,
+        "Choose one of the current track's bookmarks to delete",
+        "d",
+    ),
+    (
+        "history-menu",
+        Action::OnHistoryMenu,
+        "Pop up the history menu (initially empty)",
+        "h",
+    ),
+    ("main-menu", Action::MainMenu, "Pop up the main menu", "m Menu"),
+    (
+        "open",
+        Action::Load,
+        "Open one or more tracks (or a playlist) and start playing",
+        "o",
+    ),
+    (
+        "open-folder",
+        Action::OpenFolder,
+        "Open a folder and start playing its first track",
+        "Shift+o",
+    ),
+    (
+        "queue",
+        Action::Enqueue,
+        "Choose tracks (or playlists) to add to the queue of tracks to \
+         play next",
+        "e",
+    ),
+    (
+        "show-queue",
+        Action::ShowQueue,
+        "Show the queue to reorder or remove its tracks",
+        "u",
+    ),
+    (
+        "clear-queue",
+        Action::ClearQueue,
+        "Remove all the tracks from the queue",
+        "",
+    ),
+    (
+        "search-library",
+        Action::SearchLibrary,
+        "Search the music library (set Library Roots in the Options \
+         dialog) to play or enqueue its tracks",
+        "/",
+    ),
+    (
+        "rescan-library",
+        Action::RescanLibrary,
+        "Update the music library with the library roots' new, changed, \
+         and removed tracks",
+        "",
+    ),
+    (
+        "run-command",
+        Action::RunCommand,
+        "Type one or more commands to run",
+        ":",
+    ),
+    (
+        "save-playlist",
+        Action::SavePlaylist,
+        "Save the playlist (or the current track's folder) as a playlist",
+        "",
+    ),
+    (
+        "close-playlist",
+        Action::ClosePlaylist,
+        "Close the playlist and go back to playing by folder",
+        "",
+    ),
+    (
+        "relocate",
+        Action::Relocate,
+        "Find missing history, bookmark, and position tracks by changing \
+         the start of their paths (e.g., after moving the music library)",
+        "",
+    ),
+    ("options", Action::Options, "Configure AMP", ""),
+    ("help", Action::Help, "Show this help", "F1 Help"),
+    ("about", Action::About, "Show AMP's version and copyright", ""),
+];
+
+// Key names other than Space; case is ignored when parsing
+static NAMED_KEYS: [(&str, Key); 29] = [
+    ("Enter", Key::Enter),
+    ("Tab", Key::Tab),
+    ("BackSpace", Key::BackSpace),
+    ("Delete", Key::Delete),
+    ("Insert", Key::Insert),
+    ("Home", Key::Home),
+    ("End", Key::End),
+    ("PageUp", Key::PageUp),
+    ("PageDown", Key::PageDown),
+    ("Left", Key::Left),
+    ("Right", Key::Right),
+    ("Up", Key::Up),
+    ("Down", Key::Down),
+    ("Escape", Key::Escape),
+    ("Menu", Key::Menu),
+    ("Help", Key::Help),
+    ("Pause", Key::Pause),
+    ("F1", Key::F1),
+    ("F2", Key::F2),
+    ("F3", Key::F3),
+    ("F4", Key::F4),
+    ("F5", Key::F5),
+    ("F6", Key::F6),
+    ("F7", Key::F7),
+    ("F8", Key::F8),
+    ("F9", Key::F9),
+    ("F10", Key::F10),
+    ("F11", Key::F11),
+    ("F12", Key::F12),
+];
+
+impl Keymap {
+    pub fn bindings(&self) -> &[Binding] {
+        &self.bindings
+    }
+
+    // Replaces the named action's chords; fails if the chords can't be
+    // parsed or if any is already bound to another action
+    pub fn set(&mut self, name: &str, text: &str) -> Result<(), String> {
+        let chords = parse_chords(text)?;
+        for binding in &self.bindings {
+            if binding.name == name {
+                continue;
+            }
+            if let Some(chord) = chords.iter().find(|chord| {
+                binding.chords.iter().any(|used| used.overlaps(chord))
+            }) {
+                return Err(format!(
+                    "{chord} is already used by {}",
+                    binding.name
+                ));
+            }
+        }
+        match self.bindings.iter_mut().find(|binding| binding.name == name)
+        {
+            Some(binding) => {
+                binding.chords = chords;
+                Ok(())
+            }
+            None => Err(format!("unrecognized action {name:?}")),
+        }
+    }
+
+    // Replaces the chords of each (name, chords) entry as a whole: the
+    // entries' actions are cleared first so that chords can be moved
+    // between them, and other actions lose any chords the entries use;
+    // returns a message for each unrecognized action and for each action
+    // left unbound because its chords are invalid or used twice
+    pub fn set_all(&mut self, entries: &[(&str, &str)]) -> Vec<String> {
+        let mut errors = vec![];
+        let mut valid = vec![];
+        for (name, text) in entries {
+            match self.set(name, "") {
+                Ok(()) => valid.push((*name, *text)),
+                Err(err) => errors.push(err),
+            }
+        }
+        for (name, text) in &valid {
+            if let Ok(chords) = parse_chords(text) {
+                self.release(name, &chords);
+            }
+        }
+        for (name, text) in &valid {
+            if let Err(err) = self.set(name, text) {
+                errors.push(format!("{name} is unbound: {err}"));
+            }
+        }
+        errors
+    }
+
+    // Removes any of the chords that are bound to actions other than the
+    // named one
+    fn release(&mut self, name: &str, chords: &[Chord]) {
+        for binding in &mut self.bindings {
+            if binding.name != name {
+                binding.chords.retain(|used| {
+                    !chords.iter().any(|chord| used.overlaps(chord))
+                });
+            }
+        }
+    }
+
+    // Returns the action bound to the key event that's being handled
+    pub fn action_for_event(&self) -> Option<Action> {
+        let key = app::event_key();
+        let text = app::event_text();
+        let ctrl = app::is_event_ctrl();
+        let alt = app::is_event_alt();
+        let shift = app::is_event_shift();
+        for binding in &self.bindings {
+            if binding
+                .chords
+                .iter()
+                .any(|chord| chord.matches(key, &text, ctrl, alt, shift))
+            {
+                return Some(binding.action);
+            }
+        }
+        None
+    }
+
+    // Returns the given action's chords, e.g., "F5 or r", or "" if unbound
+    pub fn chords_for(&self, action: Action) -> String {
+        self.bindings
+            .iter()
+            .find(|binding| binding.action == action)
+            .map(|binding| binding.chords_text(" or "))
+            .unwrap_or_default()
+    }
+
+    // Returns the help text's key table rows for the bound actions
+    pub fn help_rows(&self) -> String {
+        let mut rows = String::new();
+        for binding in &self.bindings {
+            if binding.chords.is_empty() {
+                continue;
+            }
+            let keys: Vec<String> = binding
+                .chords
+                .iter()
+                .map(|chord| {
+                    format!("<b>{}</b>", html_escape(&chord.to_string()))
+                })
+                .collect();
+            rows.push_str(&format!(
+                "<tr><td>{}</td><td>{}</td></tr>\n",
+                keys.join(" or "),
+                binding.description
+            ));
+        }
+        rows
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Self {
+            bindings: BINDABLE
+                .iter()
+                .map(|(name, action, description, chords)| Binding {
+                    name,
+                    action: *action,
+                    description,
+                    chords: parse_chords(chords).unwrap(),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl Binding {
+    // Returns the chords separated by sep, e.g., "+ =" for the config file
+    pub fn chords_text(&self, sep: &str) -> String {
+        let chords: Vec<String> =
+            self.chords.iter().map(|chord| chord.to_string()).collect();
+        chords.join(sep)
+    }
+
+    pub fn default_chords_text(&self) -> &'static str {
+        BINDABLE
+            .iter()
+            .find(|(name, ..)| *name == self.name)
+            .map(|(.., chords)| *chords)
+            .unwrap_or_default()
+    }
+}
+
+impl Chord {
+    // Returns true if both chords can match the same key press: since
+    // punctuation is matched by what was typed, e.g., "+" and "Shift+="
+    // overlap (assuming a US layout for what Shift types)
+    fn overlaps(&self, other: &Chord) -> bool {
+        if self == other {
+            return true;
+        }
+        if !self.is_typed_punctuation() && !other.is_typed_punctuation() {
+            return false;
+        }
+        match (self.typed(), other.typed()) {
+            (Some(a), Some(b)) => a == b,
+            _ => false,
+        }
+    }
+
+    fn is_typed_punctuation(&self) -> bool {
+        !self.ctrl
+            && !self.alt
+            && matches!(self.key, ChordKey::Char(c) if !c.is_alphanumeric())
+    }
+
+    // Returns the character the chord types, if it types one
+    fn typed(&self) -> Option<char> {
+        if self.ctrl || self.alt {
+            return None;
+        }
+        match self.key {
+            ChordKey::Char(c) if self.shift => SHIFTED
+                .iter()
+                .find(|(plain, _)| *plain == c)
+                .map(|(_, shifted)| *shifted)
+                .or_else(|| {
+                    if c.is_alphabetic() {
+                        Some(c.to_ascii_uppercase())
+                    } else {
+                        None
+                    }
+                }),
+            ChordKey::Char(c) => Some(c),
+            ChordKey::Named(_) => None,
+        }
+    }
+
+    fn matches(
+        &self,
+        key: Key,
+        text: &str,
+        ctrl: bool,
+        alt: bool,
+        shift: bool,
+    ) -> bool {
+        if self.ctrl != ctrl || self.alt != alt {
+            return false;
+        }
+        match self.key {
+            ChordKey::Named(k) => k == key && self.shift == shift,
+            ChordKey::Char(c) if c.is_alphanumeric() => {
+                key == Key::from_char(c) && self.shift == shift
+            }
+            // Punctuation often needs Shift, so match what was typed
+            ChordKey::Char(c) => {
+                (!ctrl && !alt && text.chars().eq([c]))
+                    || (key == Key::from_char(c) && self.shift == shift)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Chord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.ctrl {
+            write!(f, "Ctrl+")?;
+        }
+        if self.alt {
+            write!(f, "Alt+")?;
+        }
+        if self.shift {
+            write!(f, "Shift+")?;
+        }
+        match self.key {
+            ChordKey::Char(' ') => write!(f, "Space"),
+            ChordKey::Char(c) => write!(f, "{c}"),
+            ChordKey::Named(key) => {
+                let name = NAMED_KEYS
+                    .iter()
+                    .find(|(_, k)| *k == key)
+                    .map(|(name, _)| *name)
+                    .unwrap_or("?");
+                write!(f, "{name}")
+            }
+        }
+    }
+}
+
+// What Shift types on a US keyboard
+static SHIFTED: [(char, char); 21] = [
+    ('`', '~'),
+    ('1', '!'),
+    ('2', '@'),
+    ('3', '#'),
+    ('4', '$'),
+    ('5', '%'),
+    ('6', '^'),
+    ('7', '&'),
+    ('8', '*'),
+    ('9', '('),
+    ('0', ')'),
+    ('-', '_'),
+    ('=', '+'),
+    ('[', '{'),
+    (']', '}'),
+    ('\\', '|'),
+    (';', ':'),
+    ('\'', '"'),
+    (',', '<'),
+    ('.', '>'),
+    ('/', '?'),
+];
+
+// Parses space-separated chords, e.g., "F5 r Ctrl+Shift+Left"
+pub fn parse_chords(text: &str) -> Result<Vec<Chord>, String> {
+    let mut chords = vec![];
+    for word in text.split_whitespace() {
+        let chord = parse_chord(word)?;
+        if !chords.contains(&chord) {
+            chords.push(chord);
+        }
+    }
+    Ok(chords)
+}
+
+fn parse_chord(text: &str) -> Result<Chord, String> {
+    let mut chord = Chord {
+        key: ChordKey::Char(' '),
+        ctrl: false,
+        alt: false,
+        shift: false,
+    };
+    let mut rest = text;
+    loop {
+        // A trailing + is the key itself, e.g., "Ctrl++"
+        let (modifier, after) = match rest.split_once('+') {
+            Some((modifier, after)) if !after.is_empty() => {
+                (modifier, after)
+            }
+            _ => break,
+        };
+        match modifier.to_lowercase().as_str() {
+            "ctrl" => chord.ctrl = true,
+            "alt" => chord.alt = true,
+            "shift" => chord.shift = true,
+            _ => return Err(format!("invalid modifier in key {text:?}")),
+        }
+        rest = after;
+    }
+    let mut chars = rest.chars();
+    chord.key = match (chars.next(), chars.next()) {
+        (Some(c), None) if !c.is_whitespace() && !c.is_control() => {
+            if c.is_uppercase() {
+                chord.shift = true; // e.g., "P" means "Shift+p"
+            }
+            ChordKey::Char(c.to_ascii_lowercase())
+        }
+        _ if rest.eq_ignore_ascii_case("space") => ChordKey::Char(' '),
+        _ => match NAMED_KEYS
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(rest))
+        {
+            Some((_, key)) => ChordKey::Named(*key),
+            None => return Err(format!("unrecognized key {text:?}")),
+        },
+    };
+    Ok(chord)
+}
+
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chord(text: &str) -> Chord {
+        parse_chord(text).unwrap()
+    }
+
+    fn chords(keymap: &Keymap, name: &str) -> String {
+        keymap
+            .bindings()
+            .iter()
+            .find(|binding| binding.name == name)
+            .map(|binding| binding.chords_text(" "))
+            .unwrap()
+    }
+
+    #[test]
+    fn parse_chords_accepts() {
+        let parsed = parse_chords("F5 r ctrl+shift+left Space r").unwrap();
+        let texts: Vec<String> =
+            parsed.iter().map(|chord| chord.to_string()).collect();
+        assert_eq!(texts, ["F5", "r", "Ctrl+Shift+Left", "Space"]);
+        assert_eq!(chord("P"), chord("Shift+p"));
+        assert_eq!(chord("Ctrl++").to_string(), "Ctrl++");
+        assert_eq!(chord("\\").to_string(), "\\");
+        assert!(parse_chords("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn parse_chords_rejects() {
+        for text in
+            ["Hyper+a", "F13", "ab", "Ctrl+", "Shift+Foo", "a Meta+b"]
+        {
+            assert!(parse_chords(text).is_err(), "{text:?}");
+        }
+    }
+
+    #[test]
+    fn overlaps() {
+        assert!(chord("Shift+=").overlaps(&chord("+")));
+        assert!(chord("+").overlaps(&chord("Shift+=")));
+        assert!(chord("Shift+1").overlaps(&chord("!")));
+        assert!(chord("P").overlaps(&chord("Shift+p")));
+        assert!(!chord("=").overlaps(&chord("+")));
+        assert!(!chord("a").overlaps(&chord("Shift+a")));
+        assert!(!chord("Ctrl+=").overlaps(&chord("+")));
+        assert!(!chord("Alt+Shift+=").overlaps(&chord("+")));
+        assert!(!chord("Left").overlaps(&chord("Shift+Left")));
+    }
+
+    #[test]
+    fn set_rejects_overlaps() {
+        let mut keymap = Keymap::default();
+        assert!(keymap.set("quit", "Shift+=").is_err());
+        assert!(keymap.set("quit", "x").is_ok());
+        assert_eq!(chords(&keymap, "quit"), "x");
+        assert!(keymap.set("no-such-action", "y").is_err());
+    }
+
+    #[test]
+    fn set_all_swaps() {
+        let mut keymap = Keymap::default();
+        let errors =
+            keymap.set_all(&[("volume-up", "-"), ("volume-down", "+ =")]);
+        assert!(errors.is_empty(), "{errors:?}");
+        assert_eq!(chords(&keymap, "volume-up"), "-");
+        assert_eq!(chords(&keymap, "volume-down"), "+ =");
+    }
+
+    #[test]
+    fn set_all_takes_default_keys() {
+        let mut keymap = Keymap::default();
+        let errors = keymap.set_all(&[("quit", "p Shift+=")]);
+        assert!(errors.is_empty(), "{errors:?}");
+        assert_eq!(chords(&keymap, "quit"), "p Shift+=");
+        assert_eq!(chords(&keymap, "play-pause"), "Space");
+        assert_eq!(chords(&keymap, "volume-up"), "=");
+    }
+
+    #[test]
+    fn set_all_reports_conflicts() {
+        let mut keymap = Keymap::default();
+        let errors = keymap.set_all(&[
+            ("quit", "x"),
+            ("help", "x"),
+            ("no-such-action", "y"),
+            ("about", "Hyper+a"),
+        ]);
+        assert_eq!(errors.len(), 3, "{errors:?}");
+        assert_eq!(chords(&keymap, "quit"), "x");
+        assert_eq!(chords(&keymap, "help"), "");
+        assert_eq!(chords(&keymap, "about"), "");
+    }
+}
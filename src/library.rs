@@ -0,0 +1,315 @@
+// Copyright © 2021-22 Mark Summerfield. All rights reserved.
+// License: GPLv3
+
+// The optional music library: the tracks in the library roots (set in the
+// Options dialog) are catalogued in an SQLite database beside the config
+// file so that they can be searched by title, artist, or album. A rescan
+// only rereads the tracks that are new or changed since the last one
+
+use crate::fixed::{Action, LIBRARY_BUSY_TIMEOUT};
+use crate::ipc::ActionSender;
+use crate::util;
+use rusqlite::{params, Connection};
+use state::Storage;
+use std::{
+    cmp,
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    thread,
+    time::{Duration, UNIX_EPOCH},
+};
+
+static SCANNING: AtomicBool = AtomicBool::new(false);
+static SCAN_REPORT: Storage<Mutex<Option<String>>> = Storage::new();
+
+#[derive(Clone, Debug)]
+pub struct LibraryTrack {
+    pub track: PathBuf,
+    pub title: String, // the filename's stem if untagged
+    pub artist: String,
+    pub album: String,
+    pub year: i32, // 0 if unknown
+    pub secs: f64,
+}
+
+impl LibraryTrack {
+    // Returns "artist — album — title" omitting whatever isn't known
+    pub fn label(&self) -> String {
+        [&self.artist, &self.album, &self.title]
+            .iter()
+            .filter(|text| !text.is_empty())
+            .map(|text| text.as_str())
+            .collect::<Vec<&str>>()
+            .join(" — ")
+    }
+
+    // Returns the lowercase text that searches are matched against
+    pub fn search_text(&self) -> String {
+        let name = self
+            .track
+            .file_stem()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let year = if self.year > 0 {
+            self.year.to_string()
+        } else {
+            String::new()
+        };
+        format!(
+            "{} {} {} {name} {year}",
+            self.artist, self.album, self.title
+        )
+        .to_lowercase()
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Scan {
+    pub total: usize,
+    pub updated: usize, // new or changed
+    pub removed: usize,
+    pub skipped: Vec<PathBuf>, // roots that aren't present
+}
+
+pub struct Library {
+    db: Connection,
+}
+
+impl Library {
+    pub fn open(filename: &Path) -> rusqlite::Result<Self> {
+        let db = Connection::open(filename)?;
+        // A search may read while a rescan is writing
+        db.busy_timeout(Duration::from_secs(LIBRARY_BUSY_TIMEOUT))?;
+        db.execute_batch(SCHEMA)?;
+        Ok(Self { db })
+    }
+
+    // Returns all the tracks in artist, album, track number order
+    pub fn tracks(&self) -> rusqlite::Result<Vec<LibraryTrack>> {
+        let mut query = self.db.prepare(
+            "SELECT path, title, artist, album, year, secs
+             FROM tracks ORDER BY artist, album, number, title",
+        )?;
+        let rows = query.query_map([], |row| {
+            Ok(LibraryTrack {
+                track: PathBuf::from(row.get::<_, String>(0)?),
+                title: row.get(1)?,
+                artist: row.get(2)?,
+                album: row.get(3)?,
+                year: row.get(4)?,
+                secs: row.get(5)?,
+            })
+        })?;
+        rows.collect()
+    }
+
+    // Adds the tracks in the roots that are new or have been modified
+    // since they were last scanned, and removes those that have gone; a
+    // root that isn't present (e.g., an unplugged drive) is skipped and
+    // its tracks are kept
+    pub fn rescan(&mut self, roots: &[PathBuf]) -> rusqlite::Result<Scan> {
+        let mut known = self.mtimes()?;
+        let mut scan = Scan::default();
+        for root in roots.iter().filter(|root| !root.is_dir()) {
+            let count = known.len();
+            known.retain(|track, _| !track.starts_with(root));
+            scan.total += count - known.len(); // kept as they are
+            scan.skipped.push(root.clone());
+        }
+        let mut seen = HashSet::new();
+        let transaction = self.db.transaction()?;
+        {
+            let mut upsert = transaction.prepare(UPSERT)?;
+            for root in roots.iter().filter(|root| root.is_dir()) {
+                for track in get_tracks(root) {
+                    if !seen.insert(track.clone()) {
+                        continue; // roots may overlap
+                    }
+                    scan.total += 1;
+                    let mtime = get_mtime(&track);
+                    if known.remove(&track) == Some(mtime) {
+                        continue; // unchanged
+                    }
+                    let (data, secs) = util::get_track_tag_and_secs(&track)
+                        .unwrap_or((None, 0.0));
+                    let mut data = data.unwrap_or_default();
+                    if data.title.is_empty() {
+                        data.title = util::get_track_name(&track);
+                    }
+                    upsert.execute(params![
+                        track.to_string_lossy().to_string(),
+                        data.title,
+                        data.artist,
+                        data.album,
+                        data.number,
+                        data.year,
+                        secs,
+                        mtime,
+                    ])?;
+                    scan.updated += 1;
+                }
+            }
+            let mut delete = transaction
+                .prepare("DELETE FROM tracks WHERE path = ?1")?;
+            for track in known.keys() {
+                delete.execute(params![track
+                    .to_string_lossy()
+                    .to_string()])?;
+                scan.removed += 1;
+            }
+        }
+        transaction.commit()?;
+        Ok(scan)
+    }
+
+    fn mtimes(&self) -> rusqlite::Result<HashMap<PathBuf, i64>> {
+        let mut query =
+            self.db.prepare("SELECT path, mtime FROM tracks")?;
+        let rows = query.query_map([], |row| {
+            Ok((PathBuf::from(row.get::<_, String>(0)?), row.get(1)?))
+        })?;
+        rows.collect()
+    }
+}
+
+// Rescans in a background thread (unless a rescan is already underway,
+// in which case returns false); when done the report is available from
+// take_scan_report() and, if there's a sender, LibraryRescanned is sent
+pub fn rescan_in_background(
+    filename: PathBuf,
+    roots: Vec<PathBuf>,
+    sender: Option<ActionSender>,
+) -> bool {
+    if SCANNING.swap(true, Ordering::SeqCst) {
+        return false;
+    }
+    SCAN_REPORT.set(Mutex::new(None)); // Does nothing if already set
+    thread::spawn(move || {
+        let report = match Library::open(&filename)
+            .and_then(|mut library| library.rescan(&roots))
+        {
+            Ok(scan) => {
+                let mut report = format!(
+                    "The library has {} tracks ({} new or changed, {} \
+                     removed)",
+                    scan.total, scan.updated, scan.removed
+                );
+                for root in &scan.skipped {
+                    report.push_str(&format!(
+                        "; skipped {} (not present)",
+                        root.display()
+                    ));
+                }
+                report
+            }
+            Err(err) => format!("Failed to rescan the library: {err}"),
+        };
+        if let Some(scan_report) = SCAN_REPORT.try_get() {
+            *scan_report.lock().unwrap() = Some(report);
+        }
+        SCANNING.store(false, Ordering::SeqCst);
+        if let Some(sender) = sender {
+            sender.send(Action::LibraryRescanned);
+        }
+    });
+    true
+}
+
+// Returns the last rescan's report if it hasn't already been taken
+pub fn take_scan_report() -> Option<String> {
+    SCAN_REPORT.try_get()?.lock().unwrap().take()
+}
+
+// Returns the indexes of the tracks that match every word in the text,
+// best matches first; a word matches if its letters appear in order
+// (though not necessarily together) in the track's search text
+pub fn search(search_texts: &[String], text: &str) -> Vec<usize> {
+    let words: Vec<String> =
+        text.split_whitespace().map(|word| word.to_lowercase()).collect();
+    let mut matches: Vec<(i32, usize)> = search_texts
+        .iter()
+        .enumerate()
+        .filter_map(|(i, search_text)| {
+            words
+                .iter()
+                .map(|word| fuzzy_score(word, search_text))
+                .sum::<Option<i32>>()
+                .map(|score| (score, i))
+        })
+        .collect();
+    // Stable so that equal matches stay in artist, album, number order
+    matches.sort_by_key(|(score, _)| cmp::Reverse(*score));
+    matches.into_iter().map(|(_, i)| i).collect()
+}
+
+// Returns None if the word's letters don't all appear in order in the
+// text; otherwise a score that is higher the more they appear together
+// and at the starts of words
+fn fuzzy_score(word: &str, text: &str) -> Option<i32> {
+    let mut score = 0;
+    let mut letters = word.chars().peekable();
+    let mut previous: Option<char> = None;
+    let mut adjacent = false; // the previous letter matched
+    for c in text.chars() {
+        match letters.peek() {
+            Some(letter) if *letter == c => {
+                score += 1;
+                if adjacent {
+                    score += 3;
+                }
+                if !previous.map_or(false, |p| p.is_alphanumeric()) {
+                    score += 2;
+                }
+                adjacent = true;
+                letters.next();
+            }
+            Some(_) => adjacent = false,
+            None => break,
+        }
+        previous = Some(c);
+    }
+    if letters.peek().is_none() {
+        Some(score)
+    } else {
+        None
+    }
+}
+
+// Returns every audio file in the root and its subfolders
+fn get_tracks(root: &Path) -> Vec<PathBuf> {
+    let mut tracks = vec![];
+    let mut folders = vec![root.to_path_buf()];
+    while let Some(folder) = folders.pop() {
+        tracks.extend(util::get_dir_tracks(&folder, false));
+        folders.extend(util::get_subfolders(&folder));
+    }
+    tracks
+}
+
+fn get_mtime(track: &Path) -> i64 {
+    fs::metadata(track)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |duration| duration.as_secs() as i64)
+}
+
+static SCHEMA: &str = "CREATE TABLE IF NOT EXISTS tracks (
+    path TEXT PRIMARY KEY NOT NULL,
+    title TEXT NOT NULL,
+    artist TEXT NOT NULL,
+    album TEXT NOT NULL,
+    number INTEGER NOT NULL,
+    year INTEGER NOT NULL,
+    secs REAL NOT NULL,
+    mtime INTEGER NOT NULL
+);";
+
+static UPSERT: &str = "INSERT OR REPLACE INTO tracks
+    (path, title, artist, album, number, year, secs, mtime)
+    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)";
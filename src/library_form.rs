@@ -0,0 +1,293 @@
+// Copyright © 2021-22 Mark Summerfield. All rights reserved.
+// License: GPLv3
+
+// Searches the music library as the search text is typed; the chosen
+// track can be played (closing the form) or added to the queue
+
+use crate::fixed::{
+    APPNAME, BUTTON_HEIGHT, BUTTON_WIDTH, ICON, MAX_LIBRARY_RESULTS, PAD,
+};
+use crate::library::{self, LibraryTrack};
+use crate::util;
+use fltk::{
+    app,
+    browser::HoldBrowser,
+    button::{Button, ReturnButton},
+    enums::{Align, CallbackTrigger, FrameType},
+    frame::Frame,
+    group::Flex,
+    image::SvgImage,
+    input::Input,
+    prelude::*,
+    window::Window,
+};
+use std::{cell::RefCell, path::PathBuf, rc::Rc};
+
+pub struct Form {
+    form: Window,
+    pub play: Rc<RefCell<Option<PathBuf>>>, // the track to play, if any
+    pub queue: Rc<RefCell<Vec<PathBuf>>>,   // the tracks to enqueue
+}
+
+impl Form {
+    pub fn new(tracks: Vec<LibraryTrack>) -> Self {
+        let play = Rc::from(RefCell::from(None));
+        let queue = Rc::from(RefCell::from(vec![]));
+        let search_texts: Vec<String> =
+            tracks.iter().map(|track| track.search_text()).collect();
+        let mut form = make_form();
+        let mut vbox = Flex::default().size_of_parent().column();
+        vbox.set_margin(PAD);
+        vbox.set_pad(PAD);
+        let (search_row, input) = make_search_row();
+        vbox.set_size(&search_row, BUTTON_HEIGHT);
+        let mut browser = HoldBrowser::default();
+        browser.set_column_char('\t');
+        browser.set_column_widths(&[WIDTH * 4 / 5, WIDTH]);
+        let message =
+            Frame::default().with_align(Align::Inside | Align::Left);
+        vbox.set_size(&message, BUTTON_HEIGHT);
+        let (button_row, play_button, queue_button, close_button) =
+            make_buttons();
+        vbox.set_size(&button_row, BUTTON_HEIGHT);
+        vbox.end();
+        form.end();
+        form.make_modal(true);
+        let mut widgets = Widgets {
+            input,
+            browser,
+            message,
+            play_button,
+            queue_button,
+            close_button,
+        };
+        let catalogue = Rc::from(Catalogue { tracks, search_texts });
+        let results = Rc::from(RefCell::from(vec![]));
+        populate_browser(
+            &mut widgets.browser,
+            &mut widgets.message,
+            &catalogue,
+            &mut results.borrow_mut(),
+            "",
+        );
+        add_event_handlers(
+            &mut form,
+            &mut widgets,
+            &catalogue,
+            &results,
+            &play,
+            &queue,
+        );
+        widgets.input.take_focus().unwrap();
+        form.show();
+        while form.shown() {
+            app::wait();
+        }
+        Self { form, play, queue }
+    }
+}
+
+impl Drop for Form {
+    fn drop(&mut self) {
+        app::delete_widget(self.form.clone());
+    }
+}
+
+// The library's tracks
+struct Catalogue {
+    tracks: Vec<LibraryTrack>,
+    search_texts: Vec<String>, // one per track
+}
+
+struct Widgets {
+    pub input: Input,
+    pub browser: HoldBrowser,
+    pub message: Frame,
+    pub play_button: ReturnButton,
+    pub queue_button: Button,
+    pub close_button: Button,
+}
+
+fn make_form() -> Window {
+    let image = SvgImage::from_data(ICON).unwrap();
+    let mut form = Window::default()
+        .with_size(WIDTH, HEIGHT)
+        .with_label(&format!("Library — {APPNAME}"));
+    if let Some(window) = app::first_window() {
+        form.set_pos(window.x() + 50, window.y() + 100);
+    }
+    form.make_resizable(true);
+    form.set_icon(Some(image));
+    form
+}
+
+fn make_search_row() -> (Flex, Input) {
+    let mut row = Flex::default().row();
+    row.set_pad(PAD);
+    let mut label = Button::default()
+        .with_label("&Search")
+        .with_align(Align::Inside | Align::Left);
+    label.set_frame(FrameType::NoBox);
+    label.clear_visible_focus();
+    let mut input = Input::default();
+    input.set_tooltip(
+        "Type words from the artist, album, or title (e.g., \"bch gldbg\" \
+finds Bach's Goldberg Variations)",
+    );
+    row.set_size(&label, BUTTON_WIDTH);
+    row.end();
+    label.set_callback({
+        let mut input = input.clone();
+        move |_| {
+            input.take_focus().unwrap();
+        }
+    });
+    (row, input)
+}
+
+fn make_buttons() -> (Flex, ReturnButton, Button, Button) {
+    let mut row = Flex::default().size_of_parent().row();
+    row.set_pad(PAD);
+    Frame::default(); // pad left of buttons
+    let play_button = ReturnButton::default().with_label("&Play");
+    let queue_button = Button::default().with_label("&Enqueue");
+    let close_button = Button::default().with_label("&Close");
+    Frame::default(); // pad right of buttons
+    row.set_size(&play_button, BUTTON_WIDTH);
+    row.set_size(&queue_button, BUTTON_WIDTH);
+    row.set_size(&close_button, BUTTON_WIDTH);
+    row.end();
+    (row, play_button, queue_button, close_button)
+}
+
+// Shows the best matches for the search text (or if it is empty, all the
+// tracks) up to MAX_LIBRARY_RESULTS with the first selected; lines are
+// "artist — album — title<tab>time" with format characters disabled
+fn populate_browser(
+    browser: &mut HoldBrowser,
+    message: &mut Frame,
+    catalogue: &Catalogue,
+    results: &mut Vec<usize>,
+    text: &str,
+) {
+    *results = if text.trim().is_empty() {
+        (0..catalogue.tracks.len()).collect()
+    } else {
+        library::search(&catalogue.search_texts, text)
+    };
+    let count = results.len();
+    results.truncate(MAX_LIBRARY_RESULTS);
+    browser.clear();
+    for i in results.iter() {
+        let track = &catalogue.tracks[*i];
+        browser.add(&format!(
+            "@.{}\t@.{}",
+            track.label(),
+            util::humanized_time(track.secs)
+        ));
+    }
+    if browser.size() > 0 {
+        browser.select(1);
+    }
+    message.set_label(&if count > results.len() {
+        format!(
+            "Showing the best {} of {count} matching tracks (of {})",
+            results.len(),
+            catalogue.tracks.len()
+        )
+    } else {
+        format!("{count} matching tracks (of {})", catalogue.tracks.len())
+    });
+}
+
+// Returns the selected track, if any
+fn selected_track(
+    browser: &HoldBrowser,
+    catalogue: &Catalogue,
+    results: &[usize],
+) -> Option<PathBuf> {
+    let index = browser.value(); // 1-based; 0 means none
+    if index < 1 {
+        return None;
+    }
+    results
+        .get(index as usize - 1)
+        .map(|i| catalogue.tracks[*i].track.clone())
+}
+
+fn add_event_handlers(
+    form: &mut Window,
+    widgets: &mut Widgets,
+    catalogue: &Rc<Catalogue>,
+    results: &Rc<RefCell<Vec<usize>>>,
+    play: &Rc<RefCell<Option<PathBuf>>>,
+    queue: &Rc<RefCell<Vec<PathBuf>>>,
+) {
+    widgets.input.set_trigger(CallbackTrigger::Changed);
+    widgets.input.set_callback({
+        let mut browser = widgets.browser.clone();
+        let mut message = widgets.message.clone();
+        let catalogue = Rc::clone(catalogue);
+        let results = Rc::clone(results);
+        move |input| {
+            populate_browser(
+                &mut browser,
+                &mut message,
+                &catalogue,
+                &mut results.borrow_mut(),
+                &input.value(),
+            );
+        }
+    });
+    widgets.play_button.set_callback({
+        let mut form = form.clone();
+        let browser = widgets.browser.clone();
+        let catalogue = Rc::clone(catalogue);
+        let results = Rc::clone(results);
+        let play = Rc::clone(play);
+        move |_| {
+            let track =
+                selected_track(&browser, &catalogue, &results.borrow());
+            if track.is_some() {
+                *play.borrow_mut() = track;
+                form.hide();
+            }
+        }
+    });
+    // Double-clicking a track plays it
+    widgets.browser.set_callback({
+        let mut play_button = widgets.play_button.clone();
+        move |_| {
+            if app::event_clicks() {
+                play_button.do_callback();
+            }
+        }
+    });
+    widgets.queue_button.set_callback({
+        let browser = widgets.browser.clone();
+        let mut message = widgets.message.clone();
+        let catalogue = Rc::clone(catalogue);
+        let results = Rc::clone(results);
+        let queue = Rc::clone(queue);
+        move |_| {
+            if let Some(track) =
+                selected_track(&browser, &catalogue, &results.borrow())
+            {
+                message.set_label(&format!(
+                    "Enqueued {}",
+                    util::get_track_title(&track)
+                ));
+                queue.borrow_mut().push(track);
+            }
+        }
+    });
+    widgets.close_button.set_callback({
+        let mut form = form.clone();
+        move |_| {
+            form.hide();
+        }
+    });
+}
+
+const WIDTH: i32 = 560;
+const HEIGHT: i32 = 420;
@@ -4,31 +4,74 @@
 
 mod actions;
 mod application;
+mod audio;
+mod chapters;
+mod cli;
+mod command;
 mod config;
+mod engine;
 mod fixed;
+mod headless;
 mod html_form;
+mod ipc;
+mod keys;
+mod library;
+mod library_form;
 mod main_window;
+#[cfg(target_os = "linux")]
+mod mpris;
 mod options_form;
+mod playlist;
+mod queue_form;
+mod remote;
+mod shuffle;
+mod status;
 mod util;
 
 use crate::application::Application;
 use crate::fixed::APPNAME;
 use config::Config;
 use fltk::dialog;
+use ipc::ActionSender;
 use state::Storage;
+use status::{Status, STATUS};
 use std::{panic, sync};
 
 pub static CONFIG: Storage<sync::RwLock<Config>> = Storage::new();
 
 fn main() {
-    panic::set_hook(Box::new(|info| {
-        let err = dbg!(&info);
-        dialog::message_title(&format!("Error — {APPNAME}"));
-        let x = util::x() - 200;
-        let y = util::y() - 100;
-        dialog::message(x, y, &err.to_string());
-    }));
-    CONFIG.set(sync::RwLock::new(Config::new()));
-    let mut app = Application::new();
+    let options = cli::Options::new();
+    if !options.headless {
+        panic::set_hook(Box::new(|info| {
+            let err = dbg!(&info);
+            dialog::message_title(&format!("Error — {APPNAME}"));
+            let x = util::x() - 200;
+            let y = util::y() - 100;
+            dialog::message(x, y, &err.to_string());
+        }));
+    }
+    let config = Config::new(options.config.clone(), options.headless);
+    let listener = match ipc::forward_or_listen(&options, &config.filename)
+    {
+        Some(listener) => listener,
+        None => return, // Passed on to the already running instance
+    };
+    if options.quit {
+        ipc::cleanup();
+        return; // There's no running instance to quit
+    }
+    CONFIG.set(sync::RwLock::new(config));
+    STATUS.set(sync::RwLock::new(Status::default()));
+    let play = options.apply();
+    let scripts = options.get_scripts();
+    if options.headless {
+        headless::run(listener, play, scripts);
+        return;
+    }
+    let mut app = Application::new(play, scripts);
+    ipc::serve(listener, ActionSender::Fltk(app.sender));
+    remote::serve(ActionSender::Fltk(app.sender));
+    #[cfg(target_os = "linux")]
+    mpris::serve(ActionSender::Fltk(app.sender));
     app.run();
 }
@@ -2,19 +2,31 @@
 // License: GPLv3
 
 use super::CONFIG;
+use crate::chapters::Chapter;
+use crate::cli::Options;
+use crate::command::Command;
+use crate::config::{Bookmark, RepeatMode};
+use crate::engine::Engine;
 use crate::fixed::{
     Action, ADD_BOOKMARK_ICON, APPNAME, BOOKMARKS_ICON, BUTTON_HEIGHT,
-    DELETE_BOOKMARK_ICON, HISTORY_ICON, ICON, LOAD_ICON, MENU_CHARS,
-    MENU_ICON, NEXT_ICON, PAD, PATH_SEP, PLAY_ICON, PREV_ICON, REPLAY_ICON,
-    TIME_ICON, TOOLBAR_HEIGHT, TOOLBUTTON_SIZE, VOLUME_ICON,
-    WINDOW_HEIGHT_MIN, WINDOW_WIDTH_MIN,
+    CHAPTERS_ICON, DELETE_BOOKMARK_ICON, HISTORY_ICON, ICON, LOAD_ICON,
+    MAX_SPEED, MENU_CHARS, MENU_ICON, MIN_SPEED, NEXT_ICON, PAD, PATH_SEP,
+    PLAY_ICON, PREV_ICON, REPEAT_ICON, REPLAY_ICON, REWIND_ICON,
+    SLEEP_CUSTOM, SLEEP_END_OF_TRACK, SLEEP_MINUTES, SLEEP_OFF, SPEED_ICON,
+    SPEED_STEP, TIME_ICON, TOOLBAR_HEIGHT, TOOLBUTTON_SIZE,
+    USER_ACTIVITY_INTERVAL, VOLUME_ICON, WINDOW_HEIGHT_MIN,
+    WINDOW_WIDTH_MIN,
 };
+use crate::ipc::{self, ActionSender};
+use crate::shuffle::ShuffleMode;
+use crate::status::STATUS;
 use crate::util;
 use fltk::{
     app,
     app::Sender,
     button::Button,
-    enums::{Event, Font, FrameType, Key, Shortcut},
+    draw,
+    enums::{Color, Event, Font, FrameType, Shortcut},
     frame::Frame,
     group::Flex,
     image::SvgImage,
@@ -24,14 +36,18 @@ use fltk::{
     valuator::HorFillSlider,
     window::Window,
 };
-use std::path::Path;
+use std::{cell::RefCell, path::Path, rc::Rc, time::Instant};
 
 pub struct Widgets {
     pub main_window: Window,
+    pub load_button: Button,
     pub prev_button: Button,
     pub replay_button: Button,
+    pub rewind_button: Button,
     pub play_pause_button: Button,
     pub next_button: Button,
+    pub repeat_menu_button: MenuButton,
+    pub chapters_menu_button: MenuButton,
     pub history_menu_button: MenuButton,
     pub bookmarks_menu_button: MenuButton,
     pub add_bookmark_button: Button,
@@ -40,6 +56,8 @@ pub struct Widgets {
     pub info_view: HelpView,
     pub volume_slider: HorFillSlider,
     pub volume_label: Frame,
+    pub speed_slider: HorFillSlider,
+    pub speed_label: Frame,
     pub time_slider: HorFillSlider,
     pub time_label: Frame,
 }
@@ -62,14 +80,20 @@ pub fn make(sender: Sender<Action>) -> Widgets {
     let info_view = add_info_view();
     let (volume_box, volume_slider, volume_label) = add_volume_row(width);
     vbox.set_size(&volume_box, BUTTON_HEIGHT);
+    let (speed_box, speed_slider, speed_label) = add_speed_row(width);
+    vbox.set_size(&speed_box, BUTTON_HEIGHT);
     let (time_box, time_slider, time_label) =
         add_slider_row(width, TIME_ICON, "0″/0″");
     vbox.set_size(&time_box, BUTTON_HEIGHT);
     let (
+        load_button,
         prev_button,
         replay_button,
+        rewind_button,
         play_pause_button,
         next_button,
+        repeat_menu_button,
+        chapters_menu_button,
         history_menu_button,
         bookmarks_menu_button,
         add_bookmark_button,
@@ -82,10 +106,14 @@ pub fn make(sender: Sender<Action>) -> Widgets {
     main_window.end();
     Widgets {
         main_window,
+        load_button,
         prev_button,
         replay_button,
+        rewind_button,
         play_pause_button,
         next_button,
+        repeat_menu_button,
+        chapters_menu_button,
         history_menu_button,
         bookmarks_menu_button,
         add_bookmark_button,
@@ -94,6 +122,8 @@ pub fn make(sender: Sender<Action>) -> Widgets {
         info_view,
         volume_slider,
         volume_label,
+        speed_slider,
+        speed_label,
         time_slider,
         time_label,
     }
@@ -116,6 +146,10 @@ fn add_toolbar(
     Button,
     Button,
     Button,
+    Button,
+    Button,
+    MenuButton,
+    MenuButton,
     MenuButton,
     MenuButton,
     Button,
@@ -127,97 +161,80 @@ fn add_toolbar(
         Flex::default().row().with_size(width, TOOLBAR_HEIGHT);
     button_box.set_frame(FrameType::UpBox);
     button_box.set_margin(PAD);
-    add_toolbutton(
-        sender,
-        Shortcut::from_char('o'),
-        "Open a track ready to play • o",
-        Action::Load,
-        LOAD_ICON,
-        &mut button_box,
-    );
+    let load_button =
+        add_toolbutton(sender, Action::Load, LOAD_ICON, &mut button_box);
     let prev_button = add_toolbutton(
         sender,
-        Shortcut::from_key(Key::F4),
-        "Previous track • F4",
         Action::Previous,
         PREV_ICON,
         &mut button_box,
     );
     let replay_button = add_toolbutton(
         sender,
-        Shortcut::from_char('r'),
-        "Replay the current track • r or F5",
         Action::Replay,
         REPLAY_ICON,
         &mut button_box,
     );
+    let rewind_button = add_toolbutton(
+        sender,
+        Action::Rewind,
+        REWIND_ICON,
+        &mut button_box,
+    );
     let play_pause_button = add_toolbutton(
         sender,
-        Shortcut::from_char('p'),
-        "Play or Pause the current track • p or Space",
         Action::PlayOrPause,
         PLAY_ICON,
         &mut button_box,
     );
-    let next_button = add_toolbutton(
-        sender,
-        Shortcut::from_key(Key::F6),
-        "Next track • F6",
-        Action::Next,
-        NEXT_ICON,
+    let next_button =
+        add_toolbutton(sender, Action::Next, NEXT_ICON, &mut button_box);
+    let repeat_menu_button =
+        add_menubutton(Action::OnRepeatMenu, REPEAT_ICON, &mut button_box);
+    let chapters_menu_button = add_menubutton(
+        Action::OnChapterMenu,
+        CHAPTERS_ICON,
         &mut button_box,
     );
     Frame::default().with_size(PAD, PAD);
     let mut history_menu_button = add_menubutton(
-        sender,
         Action::OnHistoryMenu,
-        0x68,
-        "History • h",
         HISTORY_ICON,
         &mut button_box,
     );
     populate_history_menu_button(&mut history_menu_button, sender);
     let mut bookmarks_menu_button = add_menubutton(
-        sender,
         Action::OnBookmarkMenu,
-        0x62,
-        "Bookmarks • b",
         BOOKMARKS_ICON,
         &mut button_box,
     );
     populate_bookmarks_menu_button(&mut bookmarks_menu_button, sender);
     let add_bookmark_button = add_toolbutton(
         sender,
-        Shortcut::from_char('a'),
-        "Add Track to Bookmarks • a",
         Action::AddBookmark,
         ADD_BOOKMARK_ICON,
         &mut button_box,
     );
     let delete_bookmark_button = add_toolbutton(
         sender,
-        Shortcut::from_char('d'),
-        "Delete Track from Bookmarks • d",
         Action::DeleteBookmark,
         DELETE_BOOKMARK_ICON,
         &mut button_box,
     );
     Frame::default().with_size(PAD, PAD);
-    let mut menu_button = add_menubutton(
-        sender,
-        Action::OnMenuMenu,
-        0x6D,
-        "Menu • m",
-        MENU_ICON,
-        &mut button_box,
-    );
+    let mut menu_button =
+        add_menubutton(Action::OnMenuMenu, MENU_ICON, &mut button_box);
     initialize_menu_button(&mut menu_button, sender);
     button_box.end();
     (
+        load_button,
         prev_button,
         replay_button,
+        rewind_button,
         play_pause_button,
         next_button,
+        repeat_menu_button,
+        chapters_menu_button,
         history_menu_button,
         bookmarks_menu_button,
         add_bookmark_button,
@@ -229,8 +246,6 @@ fn add_toolbar(
 
 fn add_toolbutton(
     sender: Sender<Action>,
-    shortcut: Shortcut,
-    tooltip: &str,
     action: Action,
     icon: &str,
     button_box: &mut Flex,
@@ -240,8 +255,7 @@ fn add_toolbutton(
     button.set_size(width, TOOLBUTTON_SIZE + PAD);
     button.visible_focus(false);
     button.set_label_size(0);
-    button.set_shortcut(shortcut);
-    button.set_tooltip(tooltip);
+    button.set_tooltip(&toolbar_tooltip(action));
     let mut icon = SvgImage::from_data(icon).unwrap();
     icon.scale(TOOLBUTTON_SIZE, TOOLBUTTON_SIZE, true, true);
     button.set_image(Some(icon));
@@ -251,10 +265,7 @@ fn add_toolbutton(
 }
 
 fn add_menubutton(
-    sender: Sender<Action>,
     action: Action,
-    codepoint: i32,
-    tooltip: &str,
     icon: &str,
     button_box: &mut Flex,
 ) -> MenuButton {
@@ -263,21 +274,71 @@ fn add_menubutton(
     button.set_size(width, TOOLBUTTON_SIZE + PAD);
     button.visible_focus(false);
     button.set_label_size(0);
-    button.set_tooltip(tooltip);
+    button.set_tooltip(&toolbar_tooltip(action));
     let mut icon = SvgImage::from_data(icon).unwrap();
     icon.scale(TOOLBUTTON_SIZE, TOOLBUTTON_SIZE, true, true);
     button.set_image(Some(icon));
-    button.handle(move |_, event| {
-        if event == Event::KeyUp && app::event_key().bits() == codepoint {
-            sender.send(action);
-            return true;
-        }
-        false
-    });
     button_box.set_size(&button, width);
     button
 }
 
+// Returns the toolbar button's tooltip with the action's keys (if any)
+pub(crate) fn toolbar_tooltip(action: Action) -> String {
+    let rewind = format!(
+        "Go back {}",
+        util::humanized_time(CONFIG.get().read().unwrap().rewind)
+    );
+    let (text, action) = match action {
+        Action::Load => ("Open tracks ready to play", action),
+        Action::Rewind => (rewind.as_str(), action),
+        Action::Previous => ("Previous chapter or track", action),
+        Action::Replay => ("Replay the current track", action),
+        Action::PlayOrPause => ("Play or Pause the current track", action),
+        Action::Next => ("Next chapter or track", action),
+        Action::OnRepeatMenu => ("Repeat, shuffle, and A/B loop", action),
+        Action::OnChapterMenu => ("Chapters", action),
+        Action::OnHistoryMenu => ("History", action),
+        Action::OnBookmarkMenu => ("Bookmarks", action),
+        Action::AddBookmark => ("Bookmark the current position", action),
+        Action::DeleteBookmark => {
+            ("Delete one of the current track's bookmarks", action)
+        }
+        Action::OnMenuMenu => ("Menu", Action::MainMenu),
+        _ => ("", action),
+    };
+    with_keys(text, action)
+}
+
+pub(crate) fn speed_tooltip() -> String {
+    let config = CONFIG.get().read().unwrap();
+    let hints: Vec<String> = [
+        (Action::SpeedDown, "slower"),
+        (Action::SpeedUp, "faster"),
+        (Action::SpeedNormal, "normal"),
+    ]
+    .iter()
+    .filter_map(|(action, text)| {
+        let keys = config.keys.chords_for(*action);
+        (!keys.is_empty()).then(|| format!("{keys} {text}"))
+    })
+    .collect();
+    if hints.is_empty() {
+        "Playback speed".to_string()
+    } else {
+        format!("Playback speed • {}", hints.join(", "))
+    }
+}
+
+// Returns "text • keys", or just the text if the action has no keys
+fn with_keys(text: &str, action: Action) -> String {
+    let keys = CONFIG.get().read().unwrap().keys.chords_for(action);
+    if keys.is_empty() {
+        text.to_string()
+    } else {
+        format!("{text} • {keys}")
+    }
+}
+
 pub(crate) fn populate_history_menu_button(
     menu_button: &mut MenuButton,
     sender: Sender<Action>,
@@ -293,7 +354,7 @@ pub(crate) fn populate_history_menu_button(
         menu_button.add_emit(
             &track_menu_option(MENU_CHARS[base + i], track),
             Shortcut::None,
-            MenuFlag::Normal,
+            missing_flag(track),
             sender,
             Action::LoadHistoryTrack,
         );
@@ -308,17 +369,195 @@ pub(crate) fn populate_bookmarks_menu_button(
     let config = CONFIG.get().read().unwrap();
     let base =
         if (10..=26).contains(&config.bookmarks.len()) { 9 } else { 0 };
-    for (i, track) in config.bookmarks.iter().enumerate() {
+    for (i, bookmark) in config.bookmarks.iter().enumerate() {
         menu_button.add_emit(
-            &track_menu_option(MENU_CHARS[base + i], track),
+            &format!(
+                "&{} {}",
+                MENU_CHARS[base + i],
+                util::menu_escape(&bookmark_label(bookmark))
+            ),
             Shortcut::None,
-            MenuFlag::Normal,
+            missing_flag(&bookmark.track),
             sender,
             Action::LoadBookmarkedTrack,
         );
     }
 }
 
+// Missing tracks are kept (so that they can be relocated) but greyed out
+fn missing_flag(track: &Path) -> MenuFlag {
+    if track.exists() {
+        MenuFlag::Normal
+    } else {
+        MenuFlag::Inactive
+    }
+}
+
+pub(crate) fn populate_chapters_menu_button(
+    menu_button: &mut MenuButton,
+    sender: Sender<Action>,
+    chapters: &[Chapter],
+) {
+    menu_button.clear();
+    for (i, chapter) in chapters.iter().enumerate() {
+        let label = format!(
+            "{} — {}",
+            util::menu_escape(&chapter.title),
+            util::humanized_time(chapter.pos)
+        );
+        menu_button.add_emit(
+            &if let Some(c) = MENU_CHARS.get(i) {
+                format!("&{c} {label}")
+            } else {
+                label
+            },
+            Shortcut::None,
+            MenuFlag::Normal,
+            sender,
+            Action::LoadChapter,
+        );
+    }
+}
+
+// The repeat and shuffle modes (with the current ones checked) and the
+// A/B loop
+pub(crate) fn populate_repeat_menu_button(
+    menu_button: &mut MenuButton,
+    sender: Sender<Action>,
+    engine: &Engine,
+) {
+    menu_button.clear();
+    let (repeat, shuffle) = {
+        let config = CONFIG.get().read().unwrap();
+        (config.repeat, config.shuffle)
+    };
+    for (label, mode, action) in [
+        ("Repeat &Off", RepeatMode::Off, Action::RepeatOff),
+        ("Repeat &Track", RepeatMode::Track, Action::RepeatTrack),
+        ("Repeat &All", RepeatMode::All, Action::RepeatAll),
+    ] {
+        let mut flag = MenuFlag::Radio;
+        if mode == repeat {
+            flag |= MenuFlag::Value;
+        }
+        if mode == RepeatMode::All {
+            flag |= MenuFlag::MenuDivider;
+        }
+        menu_button.add_emit(
+            &menu_label(label, action),
+            Shortcut::None,
+            flag,
+            sender,
+            action,
+        );
+    }
+    for (label, mode, action) in [
+        ("Shuffle O&ff", ShuffleMode::Off, Action::ShuffleOff),
+        ("Shuffle T&racks", ShuffleMode::Tracks, Action::ShuffleTracks),
+        ("Shuffle Al&bums", ShuffleMode::Albums, Action::ShuffleAlbums),
+    ] {
+        let mut flag = MenuFlag::Radio;
+        if mode == shuffle {
+            flag |= MenuFlag::Value;
+        }
+        if mode == ShuffleMode::Albums {
+            flag |= MenuFlag::MenuDivider;
+        }
+        menu_button.add_emit(
+            &menu_label(label, action),
+            Shortcut::None,
+            flag,
+            sender,
+            action,
+        );
+    }
+    for (label, pos, action) in [
+        ("Set Loop &Start (A)", engine.loop_a, Action::SetLoopA),
+        ("Set Loop &End (B)", engine.loop_b, Action::SetLoopB),
+    ] {
+        let label = match pos {
+            Some(pos) => format!("{label} — {}", util::humanized_time(pos)),
+            None => label.to_string(),
+        };
+        menu_button.add_emit(
+            &menu_label(&label, action),
+            Shortcut::None,
+            MenuFlag::Normal,
+            sender,
+            action,
+        );
+    }
+    let has_loop = engine.loop_a.is_some() && engine.loop_b.is_some();
+    let mut flag = MenuFlag::Toggle;
+    if engine.looping {
+        flag |= MenuFlag::Value;
+    }
+    if !has_loop {
+        flag |= MenuFlag::Inactive;
+    }
+    menu_button.add_emit(
+        &menu_label("&Loop A–B", Action::ToggleLoop),
+        Shortcut::None,
+        flag,
+        sender,
+        Action::ToggleLoop,
+    );
+    let mut flag = MenuFlag::Normal;
+    if engine.loop_a.is_none() && engine.loop_b.is_none() {
+        flag |= MenuFlag::Inactive;
+    }
+    menu_button.add_emit(
+        &menu_label("&Clear Loop", Action::ClearLoop),
+        Shortcut::None,
+        flag,
+        sender,
+        Action::ClearLoop,
+    );
+}
+
+// Draws a tick mark above and below the time slider at the start of each
+// chapter (except the first if it starts at 0)
+pub(crate) fn add_chapter_marks(
+    time_slider: &mut HorFillSlider,
+    chapters: Rc<RefCell<Vec<Chapter>>>,
+) {
+    time_slider.draw(move |slider| {
+        let length = slider.maximum();
+        if length <= 0.0 {
+            return;
+        }
+        draw::set_draw_color(Color::DarkRed);
+        for chapter in chapters.borrow().iter() {
+            if chapter.pos <= 0.0 || chapter.pos >= length {
+                continue;
+            }
+            let x = slider.x()
+                + (slider.w() as f64 * chapter.pos / length) as i32;
+            draw::draw_line(x, slider.y(), x, slider.y() + 3);
+            draw::draw_line(
+                x,
+                slider.y() + slider.h() - 4,
+                x,
+                slider.y() + slider.h() - 1,
+            );
+        }
+    });
+}
+
+// Returns "title — time — note" (or "title — time" if there's no note)
+pub(crate) fn bookmark_label(bookmark: &Bookmark) -> String {
+    let mut label = format!(
+        "{} — {}",
+        util::get_track_title(&bookmark.track),
+        util::humanized_time(bookmark.pos)
+    );
+    if !bookmark.note.is_empty() {
+        label.push_str(" — ");
+        label.push_str(&bookmark.note);
+    }
+    label
+}
+
 fn track_menu_option(c: char, track: &Path) -> String {
     format!(
         "&{c} {}",
@@ -328,41 +567,144 @@ fn track_menu_option(c: char, track: &Path) -> String {
     )
 }
 
-fn initialize_menu_button(
+pub(crate) fn initialize_menu_button(
     menu_button: &mut MenuButton,
     sender: Sender<Action>,
 ) {
+    menu_button.clear();
     menu_button.set_label("&Menu");
     menu_button.add_emit(
-        "&Options…",
+        &menu_label("Open &Folder…", Action::OpenFolder),
+        Shortcut::None,
+        MenuFlag::MenuDivider,
+        sender,
+        Action::OpenFolder,
+    );
+    menu_button.add_emit(
+        &menu_label("&Enqueue…", Action::Enqueue),
+        Shortcut::None,
+        MenuFlag::Normal,
+        sender,
+        Action::Enqueue,
+    );
+    menu_button.add_emit(
+        &menu_label("Show Q&ueue…", Action::ShowQueue),
+        Shortcut::None,
+        MenuFlag::MenuDivider,
+        sender,
+        Action::ShowQueue,
+    );
+    menu_button.add_emit(
+        &menu_label("Search Li&brary…", Action::SearchLibrary),
+        Shortcut::None,
+        MenuFlag::Normal,
+        sender,
+        Action::SearchLibrary,
+    );
+    menu_button.add_emit(
+        &menu_label("Resca&n Library", Action::RescanLibrary),
+        Shortcut::None,
+        MenuFlag::MenuDivider,
+        sender,
+        Action::RescanLibrary,
+    );
+    menu_button.add_emit(
+        &menu_label("Save &Playlist…", Action::SavePlaylist),
+        Shortcut::None,
+        MenuFlag::Normal,
+        sender,
+        Action::SavePlaylist,
+    );
+    menu_button.add_emit(
+        &menu_label("&Close Playlist", Action::ClosePlaylist),
+        Shortcut::None,
+        MenuFlag::MenuDivider,
+        sender,
+        Action::ClosePlaylist,
+    );
+    for minutes in SLEEP_MINUTES {
+        menu_button.add_emit(
+            &format!("&Sleep Timer/{minutes} Minutes"),
+            Shortcut::None,
+            MenuFlag::Normal,
+            sender,
+            Action::SleepTimer,
+        );
+    }
+    menu_button.add_emit(
+        &format!("&Sleep Timer/{SLEEP_CUSTOM}"),
+        Shortcut::None,
+        MenuFlag::MenuDivider,
+        sender,
+        Action::SleepTimer,
+    );
+    menu_button.add_emit(
+        &format!("&Sleep Timer/{SLEEP_END_OF_TRACK}"),
+        Shortcut::None,
+        MenuFlag::MenuDivider,
+        sender,
+        Action::SleepTimer,
+    );
+    menu_button.add_emit(
+        &format!("&Sleep Timer/{SLEEP_OFF}"),
+        Shortcut::None,
+        MenuFlag::Normal,
+        sender,
+        Action::SleepTimer,
+    );
+    menu_button.add_emit(
+        &menu_label("&Run Command…", Action::RunCommand),
+        Shortcut::None, // keys are handled by add_event_handlers
+        MenuFlag::Normal,
+        sender,
+        Action::RunCommand,
+    );
+    menu_button.add_emit(
+        &menu_label("Re&locate Missing Tracks…", Action::Relocate),
+        Shortcut::None,
+        MenuFlag::Normal,
+        sender,
+        Action::Relocate,
+    );
+    menu_button.add_emit(
+        &menu_label("&Options…", Action::Options),
         Shortcut::None,
         MenuFlag::MenuDivider,
         sender,
         Action::Options,
     );
     menu_button.add_emit(
-        "&Help • F1",
-        Shortcut::None, // handled elsewhere
+        &menu_label("&Help", Action::Help),
+        Shortcut::None, // keys are handled by add_event_handlers
         MenuFlag::Normal,
         sender,
         Action::Help,
     );
     menu_button.add_emit(
-        "&About",
+        &menu_label("&About", Action::About),
         Shortcut::None,
         MenuFlag::MenuDivider,
         sender,
         Action::About,
     );
     menu_button.add_emit(
-        "&Quit • Esc",
-        Shortcut::None, // handled elsewhere
+        &menu_label("&Quit", Action::Quit),
+        Shortcut::None, // keys are handled by add_event_handlers
         MenuFlag::Normal,
         sender,
         Action::Quit,
     );
 }
 
+fn menu_label(label: &str, action: Action) -> String {
+    let keys = CONFIG.get().read().unwrap().keys.chords_for(action);
+    if keys.is_empty() {
+        label.to_string()
+    } else {
+        format!("{label} • {}", util::menu_escape(&keys))
+    }
+}
+
 fn add_volume_row(width: i32) -> (Flex, HorFillSlider, Frame) {
     let (volume_box, mut volume_slider, volume_label) =
         add_slider_row(width, VOLUME_ICON, "0%");
@@ -371,6 +713,16 @@ fn add_volume_row(width: i32) -> (Flex, HorFillSlider, Frame) {
     (volume_box, volume_slider, volume_label)
 }
 
+fn add_speed_row(width: i32) -> (Flex, HorFillSlider, Frame) {
+    let (speed_box, mut speed_slider, speed_label) =
+        add_slider_row(width, SPEED_ICON, "1.00×");
+    speed_slider.set_range(MIN_SPEED, MAX_SPEED);
+    speed_slider.set_step(SPEED_STEP, 1);
+    speed_slider.set_value(1.0);
+    speed_slider.set_tooltip(&speed_tooltip());
+    (speed_box, speed_slider, speed_label)
+}
+
 fn add_slider_row(
     width: i32,
     icon: &str,
@@ -387,11 +739,12 @@ fn add_slider_row(
     let mut icon_image = SvgImage::from_data(icon).unwrap();
     icon_image.scale(TOOLBUTTON_SIZE, TOOLBUTTON_SIZE, true, true);
     icon_label.set_image(Some(icon_image));
-    let slider = HorFillSlider::default();
+    let mut slider = HorFillSlider::default();
+    slider.visible_focus(false); // Leave the arrow keys for skipping
     let mut label = Frame::default().with_label(label);
     label.set_frame(FrameType::EngravedFrame);
     row.set_size(&icon_label, icon_width);
-    row.set_size(&label, icon_width * 3);
+    row.set_size(&label, icon_width * 5);
     row.end();
     (row, slider, label)
 }
@@ -421,38 +774,61 @@ pub fn add_event_handlers(
     main_window: &mut Window,
     sender: Sender<Action>,
 ) {
-    // Both of these are really needed!
     main_window.set_callback(move |_| {
-        if app::event() == Event::Close || app::event_key() == Key::Escape {
+        if app::event() == Event::Close {
             sender.send(Action::Quit);
-        }
+        } // Escape is handled with the other keys below
     });
+    let mut activity = Instant::now();
+    let mut dropping = false; // a Paste is only a drop after a DndRelease
     main_window.handle(move |_, event| {
-        if event == Event::KeyUp {
-            let key = app::event_key();
-            if key.bits() == 0x20 {
-                sender.send(Action::SpacePressed); // Space → Play or Pause
-                return true;
-            }
-            if key.bits() == 0x2B || key.bits() == 0x3D {
-                sender.send(Action::VolumeUp); // + or =
+        match event {
+            // Accept drops of files and folders from file managers
+            Event::DndEnter | Event::DndDrag => return true,
+            Event::DndLeave => {
+                dropping = false;
                 return true;
             }
-            if key.bits() == 0x2D {
-                sender.send(Action::VolumeDown); // -
+            Event::DndRelease => {
+                dropping = true;
                 return true;
             }
-            if app::event_key() == Key::Help || app::event_key() == Key::F1
-            {
-                sender.send(Action::Help);
+            Event::Paste if dropping => {
+                dropping = false;
+                let paths = util::get_dropped_paths(&app::event_text());
+                if !paths.is_empty() {
+                    // Shift+drop enqueues; otherwise play at once
+                    let command = if app::is_event_shift() {
+                        Command::Queue(paths)
+                    } else {
+                        Command::Args(Options {
+                            paths,
+                            ..Default::default()
+                        })
+                    };
+                    ipc::send_command(command, &ActionSender::Fltk(sender));
+                }
                 return true;
             }
-            if app::event_key() == Key::F5 {
-                sender.send(Action::Replay);
-                return true;
-            }
-            if app::event_key() == Key::Menu {
-                sender.send(Action::MainMenu);
+            _ => {}
+        }
+        // Restarts the sleep timer; only sent now and then while it is on
+        if (event == Event::Move || event == Event::KeyDown)
+            && activity.elapsed().as_secs_f64() >= USER_ACTIVITY_INTERVAL
+            && STATUS.get().read().unwrap().sleeping
+        {
+            activity = Instant::now();
+            sender.send(Action::UserActivity);
+        }
+        if event == Event::KeyUp {
+            let action =
+                CONFIG.get().read().unwrap().keys.action_for_event();
+            if let Some(action) = action {
+                sender.send(if action == Action::PlayOrPause {
+                    Action::SpacePressed // Shows the button being pressed
+                } else {
+                    action
+                });
                 return true;
             }
         }
@@ -460,11 +836,10 @@ pub fn add_event_handlers(
     });
 }
 
-pub fn update_widgets_from_config(widgets: &mut Widgets) -> bool {
+pub fn update_widgets_from_config(widgets: &mut Widgets) {
     let config = CONFIG.get().read().unwrap();
     widgets.volume_slider.set_value(config.volume);
     widgets
         .volume_label
         .set_label(&format!("{}%", (config.volume * 100.0).round()));
-    config.track.exists()
 }
@@ -0,0 +1,331 @@
+// Copyright © 2021-22 Mark Summerfield. All rights reserved.
+// License: GPLv3
+
+// MPRIS2 (https://specifications.freedesktop.org/mpris-spec/latest/) so
+// that desktop media widgets, playerctl, and media keys can control AMP.
+// Incoming calls are sent to the application as Actions (or as Commands
+// if they carry data) and properties are read from the shared STATUS
+
+use crate::cli::Options;
+use crate::command::Command;
+use crate::fixed::{Action, APPNAME, AUDIO_SUFFIXES, MAX_SPEED, MIN_SPEED};
+use crate::ipc::{self, ActionSender};
+use crate::status::{Status, STATUS};
+use crate::util;
+use dbus::{
+    arg::{PropMap, RefArg, Variant},
+    blocking::{
+        stdintf::org_freedesktop_dbus::PropertiesPropertiesChanged,
+        Connection,
+    },
+    message::{MatchRule, SignalArgs},
+    Message,
+};
+use dbus_crossroads::{Crossroads, IfaceBuilder};
+use std::{path::PathBuf, thread, time::Duration};
+
+static MPRIS_PATH: &str = "/org/mpris/MediaPlayer2";
+static ROOT_IFACE: &str = "org.mpris.MediaPlayer2";
+static PLAYER_IFACE: &str = "org.mpris.MediaPlayer2.Player";
+static NO_TRACK: &str = "/org/mpris/MediaPlayer2/TrackList/NoTrack";
+
+// Runs the D-Bus service in a background thread; does nothing if there's
+// no session bus
+pub fn serve(sender: ActionSender) {
+    thread::spawn(move || {
+        let _ = run(sender); // Ignore error: MPRIS is optional
+    });
+}
+
+fn run(sender: ActionSender) -> Result<(), dbus::Error> {
+    let connection = Connection::new_session()?;
+    connection.request_name(
+        format!("{ROOT_IFACE}.{}", APPNAME.to_lowercase()),
+        false,
+        true,
+        true,
+    )?;
+    let mut crossroads = Crossroads::new();
+    let root = crossroads.register(ROOT_IFACE, |builder| {
+        add_root_interface(builder, sender.clone())
+    });
+    let player = crossroads.register(PLAYER_IFACE, |builder| {
+        add_player_interface(builder, sender)
+    });
+    crossroads.insert(MPRIS_PATH, &[root, player], ());
+    connection.start_receive(
+        MatchRule::new_method_call(),
+        Box::new(move |message, connection| {
+            let _ = crossroads.handle_message(message, connection);
+            true
+        }),
+    );
+    let mut old = status();
+    loop {
+        connection.process(Duration::from_millis(500))?;
+        let new = status();
+        notify_changes(&connection, &old, &new);
+        old = new;
+    }
+}
+
+fn add_root_interface(
+    builder: &mut IfaceBuilder<()>,
+    sender: ActionSender,
+) {
+    let raise_sender = sender.clone();
+    builder.method("Raise", (), (), move |_, _, _: ()| {
+        ipc::send_command(Command::Args(Options::default()), &raise_sender);
+        Ok(())
+    });
+    builder.method("Quit", (), (), move |_, _, _: ()| {
+        sender.send(Action::Quit);
+        Ok(())
+    });
+    builder.property("CanQuit").get(|_, _| Ok(true));
+    builder.property("CanRaise").get(|_, _| Ok(true));
+    builder.property("HasTrackList").get(|_, _| Ok(false));
+    builder.property("Identity").get(|_, _| Ok(APPNAME.to_string()));
+    builder
+        .property("SupportedUriSchemes")
+        .get(|_, _| Ok(vec!["file".to_string()]));
+    builder.property("SupportedMimeTypes").get(|_, _| {
+        Ok(AUDIO_SUFFIXES
+            .iter()
+            .map(|suffix| format!("audio/{suffix}"))
+            .collect::<Vec<String>>())
+    });
+}
+
+fn add_player_interface(
+    builder: &mut IfaceBuilder<()>,
+    sender: ActionSender,
+) {
+    // Each handler needs its own sender
+    let s = sender.clone();
+    builder.method("PlayPause", (), (), move |_, _, _: ()| {
+        s.send(Action::PlayOrPause);
+        Ok(())
+    });
+    let s = sender.clone();
+    builder.method("Play", (), (), move |_, _, _: ()| {
+        if !status().playing {
+            s.send(Action::PlayOrPause);
+        }
+        Ok(())
+    });
+    for name in ["Pause", "Stop"] {
+        let s = sender.clone();
+        builder.method(name, (), (), move |_, _, _: ()| {
+            if status().playing {
+                s.send(Action::PlayOrPause);
+            }
+            Ok(())
+        });
+    }
+    let s = sender.clone();
+    builder.method("Next", (), (), move |_, _, _: ()| {
+        s.send(Action::Next);
+        Ok(())
+    });
+    let s = sender.clone();
+    builder.method("Previous", (), (), move |_, _, _: ()| {
+        s.send(Action::Previous);
+        Ok(())
+    });
+    let s = sender.clone();
+    builder.method(
+        "Seek",
+        ("Offset",),
+        (),
+        move |_, _, (offset,): (i64,)| {
+            let status = status();
+            let pos = status.pos + micros_to_secs(offset);
+            // Seeking past the end goes to the next track
+            if pos >= status.length {
+                s.send(Action::NextTrack);
+            } else {
+                ipc::send_command(Command::Seek(pos.max(0.0)), &s);
+            }
+            Ok(())
+        },
+    );
+    let s = sender.clone();
+    builder.method(
+        "SetPosition",
+        ("TrackId", "Position"),
+        (),
+        move |_, _, (track_id, pos): (dbus::Path<'static>, i64)| {
+            // Stale track IDs must be ignored
+            if track_id == track_id_for(&status()) && pos >= 0 {
+                let pos = micros_to_secs(pos);
+                ipc::send_command(Command::Seek(pos), &s);
+            }
+            Ok(())
+        },
+    );
+    let s = sender.clone();
+    builder.method(
+        "OpenUri",
+        ("Uri",),
+        (),
+        move |_, _, (uri,): (String,)| {
+            if let Some(name) = uri.strip_prefix("file://") {
+                let name = name.strip_prefix("localhost").unwrap_or(name);
+                let options = Options {
+                    paths: vec![PathBuf::from(util::percent_decode(name))],
+                    ..Default::default()
+                };
+                ipc::send_command(Command::Args(options), &s);
+            }
+            Ok(())
+        },
+    );
+    builder
+        .property("PlaybackStatus")
+        .get(|_, _| Ok(playback_status(&status())));
+    let s = sender.clone();
+    builder.property("Rate").get(|_, _| Ok(status().speed)).set(
+        move |_, _, rate: f64| {
+            // A rate of 0.0 must be treated as Pause
+            if rate > 0.0 {
+                ipc::send_command(Command::Speed(rate), &s);
+                Ok(Some(rate.clamp(MIN_SPEED, MAX_SPEED)))
+            } else {
+                ipc::send_command(Command::Pause, &s);
+                Ok(None)
+            }
+        },
+    );
+    builder.property("MinimumRate").get(|_, _| Ok(MIN_SPEED));
+    builder.property("MaximumRate").get(|_, _| Ok(MAX_SPEED));
+    builder.property("Metadata").get(|_, _| Ok(metadata(&status())));
+    builder.property("Volume").get(|_, _| Ok(status().volume)).set(
+        move |_, _, volume: f64| {
+            ipc::send_command(Command::Volume(volume), &sender);
+            Ok(Some(volume.clamp(0.0, 1.0)))
+        },
+    );
+    builder
+        .property("Position")
+        .emits_changed_false()
+        .get(|_, _| Ok(secs_to_micros(status().pos)));
+    for name in [
+        "CanGoNext",
+        "CanGoPrevious",
+        "CanPlay",
+        "CanPause",
+        "CanSeek",
+        "CanControl",
+    ] {
+        builder.property(name).emits_changed_const().get(|_, _| Ok(true));
+    }
+}
+
+// Emits PropertiesChanged for the player's properties that have changed
+// and Seeked if the position has jumped
+fn notify_changes(connection: &Connection, old: &Status, new: &Status) {
+    let mut changed = PropMap::new();
+    if old.playing != new.playing || old.serial != new.serial {
+        changed.insert(
+            "PlaybackStatus".to_string(),
+            Variant(Box::new(playback_status(new))),
+        );
+    }
+    if old.serial != new.serial || old.length != new.length {
+        changed.insert(
+            "Metadata".to_string(),
+            Variant(Box::new(metadata(new))),
+        );
+    }
+    if old.volume != new.volume {
+        changed.insert("Volume".to_string(), Variant(Box::new(new.volume)));
+    }
+    if old.speed != new.speed {
+        changed.insert("Rate".to_string(), Variant(Box::new(new.speed)));
+    }
+    let path = dbus::Path::from(MPRIS_PATH);
+    if !changed.is_empty() {
+        let signal = PropertiesPropertiesChanged {
+            interface_name: PLAYER_IFACE.to_string(),
+            changed_properties: changed,
+            invalidated_properties: vec![],
+        };
+        let _ = connection.channel().send(signal.to_emit_message(&path));
+    }
+    // While playing the position advances by about half a second (times
+    // the speed) between checks
+    let expected =
+        if new.playing { old.pos + 0.5 * new.speed } else { old.pos };
+    if old.serial == new.serial && (new.pos - expected).abs() > 2.0 {
+        let message =
+            Message::signal(&path, &PLAYER_IFACE.into(), &"Seeked".into())
+                .append1(secs_to_micros(new.pos));
+        let _ = connection.channel().send(message);
+    }
+}
+
+fn status() -> Status {
+    STATUS.get().read().unwrap().clone()
+}
+
+fn playback_status(status: &Status) -> String {
+    if status.playing {
+        "Playing"
+    } else if status.track.as_os_str().is_empty() {
+        "Stopped"
+    } else {
+        "Paused"
+    }
+    .to_string()
+}
+
+fn track_id_for(status: &Status) -> dbus::Path<'static> {
+    if status.serial == 0 {
+        dbus::Path::from(NO_TRACK)
+    } else {
+        dbus::Path::from(format!(
+            "/eu/qtrac/{}/track/{}",
+            APPNAME.to_lowercase(),
+            status.serial
+        ))
+    }
+}
+
+fn metadata(status: &Status) -> PropMap {
+    let mut map = PropMap::new();
+    let mut insert = |key: &str, value: Box<dyn RefArg>| {
+        map.insert(key.to_string(), Variant(value));
+    };
+    insert("mpris:trackid", Box::new(track_id_for(status)));
+    if status.serial == 0 {
+        return map;
+    }
+    insert("mpris:length", Box::new(secs_to_micros(status.length)));
+    insert(
+        "xesam:url",
+        Box::new(format!(
+            "file://{}",
+            util::percent_encode(&status.track.to_string_lossy())
+        )),
+    );
+    insert("xesam:title", Box::new(status.data.title.clone()));
+    if !status.data.album.is_empty() {
+        insert("xesam:album", Box::new(status.data.album.clone()));
+    }
+    if !status.data.artist.is_empty() {
+        insert("xesam:artist", Box::new(vec![status.data.artist.clone()]));
+    }
+    if status.data.number > 0 {
+        insert("xesam:trackNumber", Box::new(status.data.number));
+    }
+    map
+}
+
+fn secs_to_micros(secs: f64) -> i64 {
+    (secs * 1_000_000.0).round() as i64
+}
+
+fn micros_to_secs(micros: i64) -> f64 {
+    micros as f64 / 1_000_000.0
+}
@@ -3,22 +3,30 @@
 
 use super::CONFIG;
 use crate::fixed::{
-    APPNAME, BUTTON_HEIGHT, BUTTON_WIDTH, DEF_HISTORY_SIZE, ICON,
-    MAX_HISTORY_SIZE, MIN_HISTORY_SIZE, PAD, SCALE_MAX, SCALE_MIN,
+    APPNAME, BUTTON_HEIGHT, BUTTON_WIDTH, DEF_AUTOSAVE, DEF_HISTORY_SIZE,
+    DEF_POSITIONS_SIZE, DEF_REWIND, DEF_SKIP_LONG, DEF_SKIP_MEDIUM,
+    DEF_SKIP_SHORT, DEF_SLEEP_FADE, DEF_STREAM_SIZE, ICON, MAX_AUTOSAVE,
+    MAX_CROSSFADE, MAX_HISTORY_SIZE, MAX_POSITIONS_SIZE, MAX_RESUME_REWIND,
+    MAX_SKIP, MAX_SLEEP_FADE, MAX_STREAM_SIZE, MIN_HISTORY_SIZE,
+    MIN_POSITIONS_SIZE, PAD, SCALE_MAX, SCALE_MIN,
 };
+use crate::keys::{Binding, Keymap};
 use crate::util;
 use fltk::{
     app,
-    button::Button,
-    enums::{Align, FrameType},
+    browser::HoldBrowser,
+    button::{Button, CheckButton},
+    enums::{Align, CallbackTrigger, Color, FrameType},
     frame::Frame,
     group::Flex,
     image::SvgImage,
+    input::Input,
     misc::Spinner,
     prelude::*,
     window::Window,
 };
 use std::cell::RefCell;
+use std::env;
 use std::rc::Rc;
 
 pub struct Form {
@@ -35,14 +43,17 @@ impl Form {
         vbox.set_pad(PAD);
         make_config_row();
         let mut spinners = make_spinners();
+        let mut keys_editor = make_keys_editor(&mut vbox);
         let (button_row, mut buttons) = make_buttons();
         vbox.set_size(&button_row, BUTTON_HEIGHT);
         vbox.end();
         form.end();
         form.make_modal(true);
+        add_keys_editor_event_handlers(&mut keys_editor);
         add_event_handlers(
             &mut form,
             &spinners,
+            &keys_editor,
             &mut buttons,
             Rc::clone(&ok),
         );
@@ -63,9 +74,30 @@ impl Drop for Form {
 
 struct Spinners {
     pub history_size_spinner: Spinner,
+    pub positions_size_spinner: Spinner,
+    pub stream_size_spinner: Spinner,
+    pub crossfade_spinner: Spinner,
+    pub sleep_fade_spinner: Spinner,
+    pub skip_short_spinner: Spinner,
+    pub skip_medium_spinner: Spinner,
+    pub skip_long_spinner: Spinner,
+    pub rewind_spinner: Spinner,
+    pub resume_rewind_spinner: Spinner,
+    pub autosave_spinner: Spinner,
+    pub recursive_check: CheckButton,
+    pub keep_queue_check: CheckButton,
+    pub library_roots_input: Input,
     pub scale_spinner: Spinner,
 }
 
+struct KeysEditor {
+    pub browser: HoldBrowser,
+    pub input: Input,
+    pub default_button: Button,
+    pub message: Frame,
+    pub keymap: Rc<RefCell<Keymap>>, // the edited copy of the keys
+}
+
 struct Buttons {
     pub ok_button: Button,
     pub cancel_button: Button,
@@ -107,6 +139,106 @@ fn make_spinners() -> Spinners {
         MAX_HISTORY_SIZE as f64,
         1.0,
     );
+    let positions_size_spinner = make_row(
+        "&Positions Size",
+        config.positions_size as f64,
+        &format!("The maximum number of tracks whose positions are remembered for resuming (default {DEF_POSITIONS_SIZE})", ),
+        MIN_POSITIONS_SIZE as f64,
+        MAX_POSITIONS_SIZE as f64,
+        10.0,
+    );
+    let stream_size_spinner = make_row(
+        "S&tream Size (MB)",
+        config.stream_size as f64,
+        &format!("Tracks of at least this size are streamed rather than fully loaded; 0 means always stream (default {DEF_STREAM_SIZE})", ),
+        0.0,
+        MAX_STREAM_SIZE as f64,
+        10.0,
+    );
+    let crossfade_spinner = make_row(
+        "&Crossfade (secs)",
+        config.crossfade,
+        "How long consecutive tracks overlap as one fades out and the next fades in; 0 means gapless (default 0)",
+        0.0,
+        MAX_CROSSFADE,
+        0.5,
+    );
+    let sleep_fade_spinner = make_row(
+        "Sleep &Fade (secs)",
+        config.sleep_fade,
+        &format!("How long the volume takes to fade out before the sleep timer pauses (default {DEF_SLEEP_FADE})", ),
+        1.0,
+        MAX_SLEEP_FADE,
+        5.0,
+    );
+    let skip_short_spinner = make_row(
+        "Short Sk&ip (secs)",
+        config.skip_short,
+        &format!("How far the short skip back or forward goes (default {DEF_SKIP_SHORT})"),
+        1.0,
+        MAX_SKIP,
+        5.0,
+    );
+    let skip_medium_spinner = make_row(
+        "&Medium Skip (secs)",
+        config.skip_medium,
+        &format!("How far the medium skip back or forward goes (default {DEF_SKIP_MEDIUM})"),
+        1.0,
+        MAX_SKIP,
+        10.0,
+    );
+    let skip_long_spinner = make_row(
+        "&Long Skip (secs)",
+        config.skip_long,
+        &format!("How far the long skip back or forward goes (default {DEF_SKIP_LONG})"),
+        1.0,
+        MAX_SKIP,
+        60.0,
+    );
+    let rewind_spinner = make_row(
+        "Re&wind (secs)",
+        config.rewind,
+        &format!(
+            "How far the rewind button goes back (default {DEF_REWIND})"
+        ),
+        1.0,
+        MAX_SKIP,
+        5.0,
+    );
+    let resume_rewind_spinner = make_row(
+        "Rewind on Res&ume (secs)",
+        config.resume_rewind,
+        "The most to go back when resuming after a pause: a second or two after a short pause, rising to this after a day; 0 means never (default 0)",
+        0.0,
+        MAX_RESUME_REWIND,
+        5.0,
+    );
+    let autosave_spinner = make_row(
+        "&Autosave (secs)",
+        config.autosave,
+        &format!("How often the position (and the rest of the configuration) is saved while playing; 0 means only when pausing or quitting (default {DEF_AUTOSAVE})"),
+        0.0,
+        MAX_AUTOSAVE,
+        30.0,
+    );
+    let recursive_check = make_check_row(
+        "&Recursive Folders",
+        config.recursive,
+        "Folders include their subfolders' tracks, and at the end of a folder play continues into the next folder (default off)",
+    );
+    let keep_queue_check = make_check_row(
+        "Remember &Queue",
+        config.keep_queue,
+        "Save the queue of tracks to play next so that it is restored at startup (default off)",
+    );
+    let separator = if cfg!(windows) { ';' } else { ':' };
+    let library_roots_input = make_input_row(
+        "Librar&y Roots",
+        &env::join_paths(&config.library_roots)
+            .unwrap_or_default()
+            .to_string_lossy(),
+        &format!("The music library's folders (separated by '{separator}'); their tracks can be searched (after Rescan Library), and are remembered relative to them so that if the library moves only its folder need be changed here (default none)"),
+    );
     let scale_spinner = make_row(
         "&Scale",
         config.window_scale as f64,
@@ -115,7 +247,57 @@ fn make_spinners() -> Spinners {
         SCALE_MAX as f64,
         0.1,
     );
-    Spinners { history_size_spinner, scale_spinner }
+    Spinners {
+        history_size_spinner,
+        positions_size_spinner,
+        stream_size_spinner,
+        crossfade_spinner,
+        sleep_fade_spinner,
+        skip_short_spinner,
+        skip_medium_spinner,
+        skip_long_spinner,
+        rewind_spinner,
+        resume_rewind_spinner,
+        autosave_spinner,
+        recursive_check,
+        keep_queue_check,
+        library_roots_input,
+        scale_spinner,
+    }
+}
+
+// The check button lines up with the spinners
+fn make_check_row(label: &str, value: bool, tooltip: &str) -> CheckButton {
+    let mut row = Flex::default().row();
+    row.set_pad(PAD);
+    Frame::default(); // pad where the spinners' labels go
+    let mut check = CheckButton::default().with_label(label);
+    check.set_checked(value);
+    check.set_tooltip(tooltip);
+    row.end();
+    check
+}
+
+// The input lines up with the spinners
+fn make_input_row(label: &str, value: &str, tooltip: &str) -> Input {
+    let mut row = Flex::default().row();
+    row.set_pad(PAD);
+    let mut label = Button::default()
+        .with_label(label)
+        .with_align(Align::Inside | Align::Left);
+    label.set_frame(FrameType::NoBox);
+    label.clear_visible_focus();
+    let mut input = Input::default();
+    input.set_value(value);
+    input.set_tooltip(tooltip);
+    row.end();
+    label.set_callback({
+        let mut input = input.clone();
+        move |_| {
+            input.take_focus().unwrap();
+        }
+    });
+    input
 }
 
 fn make_row(
@@ -149,6 +331,144 @@ fn make_row(
     spinner
 }
 
+// Lists each action and its keys; the selected action's keys are edited
+// below the list and only accepted if they're valid and not already used
+fn make_keys_editor(vbox: &mut Flex) -> KeysEditor {
+    let keymap = CONFIG.get().read().unwrap().keys.clone();
+    let mut browser = HoldBrowser::default();
+    browser.set_column_char('\t');
+    browser.set_column_widths(&[WIDTH * 2 / 5, WIDTH]);
+    for binding in keymap.bindings() {
+        browser.add(&browser_line(binding));
+    }
+    browser.set_tooltip("Choose an action to change its keys");
+    vbox.set_size(&browser, KEYS_HEIGHT);
+    let mut row = Flex::default().row();
+    row.set_pad(PAD);
+    let mut label = Button::default()
+        .with_label("&Keys")
+        .with_align(Align::Inside | Align::Left);
+    label.set_frame(FrameType::NoBox);
+    label.clear_visible_focus();
+    let mut input = Input::default();
+    input.set_tooltip(
+        "The chosen action's keys separated by spaces, e.g., \
+F5 r, Ctrl+Right, Shift+Alt+p, Space, PageUp, or F12",
+    );
+    let default_button = Button::default().with_label("&Default");
+    row.set_size(&label, WIDTH / 6);
+    row.set_size(&default_button, BUTTON_WIDTH);
+    row.end();
+    vbox.set_size(&row, BUTTON_HEIGHT);
+    let mut message =
+        Frame::default().with_align(Align::Inside | Align::Left);
+    message.set_label_color(Color::DarkRed);
+    vbox.set_size(&message, BUTTON_HEIGHT);
+    label.set_callback({
+        let mut input = input.clone();
+        move |_| {
+            input.take_focus().unwrap();
+        }
+    });
+    browser.select(1);
+    if let Some(binding) = keymap.bindings().first() {
+        input.set_value(&binding.chords_text(" "));
+    }
+    KeysEditor {
+        browser,
+        input,
+        default_button,
+        message,
+        keymap: Rc::from(RefCell::from(keymap)),
+    }
+}
+
+// The chords follow "@." so that FLTK shows any '@' literally
+fn browser_line(binding: &Binding) -> String {
+    format!("{}\t@.{}", binding.name, binding.chords_text(" "))
+}
+
+fn add_keys_editor_event_handlers(keys_editor: &mut KeysEditor) {
+    keys_editor.browser.set_callback({
+        let keymap = Rc::clone(&keys_editor.keymap);
+        let mut input = keys_editor.input.clone();
+        let mut message = keys_editor.message.clone();
+        move |browser| {
+            let keymap = keymap.borrow();
+            if let Some(binding) = selected_binding(browser, &keymap) {
+                input.set_value(&binding.chords_text(" "));
+                message.set_label("");
+            }
+        }
+    });
+    keys_editor.input.set_trigger(CallbackTrigger::Changed);
+    keys_editor.input.set_callback({
+        let keymap = Rc::clone(&keys_editor.keymap);
+        let mut browser = keys_editor.browser.clone();
+        let mut message = keys_editor.message.clone();
+        move |input| {
+            apply_keys(&keymap, &mut browser, input, &mut message);
+        }
+    });
+    keys_editor.default_button.set_callback({
+        let keymap = Rc::clone(&keys_editor.keymap);
+        let browser = keys_editor.browser.clone();
+        let mut input = keys_editor.input.clone();
+        move |_| {
+            let chords = {
+                let keymap = keymap.borrow();
+                selected_binding(&browser, &keymap)
+                    .map(|binding| binding.default_chords_text())
+            };
+            if let Some(chords) = chords {
+                input.set_value(chords);
+                input.do_callback();
+            }
+        }
+    });
+}
+
+fn selected_binding<'a>(
+    browser: &HoldBrowser,
+    keymap: &'a Keymap,
+) -> Option<&'a Binding> {
+    let index = browser.value(); // 1-based; 0 means none
+    if index < 1 {
+        return None;
+    }
+    keymap.bindings().get(index as usize - 1)
+}
+
+// Sets the selected action's keys to the input's keys if they're valid;
+// returns false (and explains why) if they aren't
+fn apply_keys(
+    keymap: &Rc<RefCell<Keymap>>,
+    browser: &mut HoldBrowser,
+    input: &Input,
+    message: &mut Frame,
+) -> bool {
+    let index = browser.value();
+    let mut keymap = keymap.borrow_mut();
+    let name = match selected_binding(browser, &keymap) {
+        Some(binding) => binding.name,
+        None => return true,
+    };
+    match keymap.set(name, &input.value()) {
+        Ok(()) => {
+            browser.set_text(
+                index,
+                &browser_line(&keymap.bindings()[index as usize - 1]),
+            );
+            message.set_label("");
+            true
+        }
+        Err(err) => {
+            message.set_label(&util::capitalize_first(&err));
+            false
+        }
+    }
+}
+
 fn make_buttons() -> (Flex, Buttons) {
     let mut row = Flex::default().size_of_parent().row();
     row.set_pad(PAD);
@@ -165,14 +485,37 @@ fn make_buttons() -> (Flex, Buttons) {
 fn add_event_handlers(
     form: &mut Window,
     spinners: &Spinners,
+    keys_editor: &KeysEditor,
     buttons: &mut Buttons,
     ok: Rc<RefCell<bool>>,
 ) {
     buttons.ok_button.set_callback({
+        let keymap = Rc::clone(&keys_editor.keymap);
+        let mut browser = keys_editor.browser.clone();
+        let mut input = keys_editor.input.clone();
+        let mut message = keys_editor.message.clone();
         let history_size_spinner = spinners.history_size_spinner.clone();
+        let positions_size_spinner =
+            spinners.positions_size_spinner.clone();
+        let stream_size_spinner = spinners.stream_size_spinner.clone();
+        let crossfade_spinner = spinners.crossfade_spinner.clone();
+        let sleep_fade_spinner = spinners.sleep_fade_spinner.clone();
+        let skip_short_spinner = spinners.skip_short_spinner.clone();
+        let skip_medium_spinner = spinners.skip_medium_spinner.clone();
+        let skip_long_spinner = spinners.skip_long_spinner.clone();
+        let rewind_spinner = spinners.rewind_spinner.clone();
+        let resume_rewind_spinner = spinners.resume_rewind_spinner.clone();
+        let autosave_spinner = spinners.autosave_spinner.clone();
+        let recursive_check = spinners.recursive_check.clone();
+        let keep_queue_check = spinners.keep_queue_check.clone();
+        let library_roots_input = spinners.library_roots_input.clone();
         let scale_spinner = spinners.scale_spinner.clone();
         let mut form = form.clone();
         move |_| {
+            if !apply_keys(&keymap, &mut browser, &input, &mut message) {
+                input.take_focus().unwrap();
+                return;
+            }
             *ok.borrow_mut() = true;
             let mut config = CONFIG.get().write().unwrap();
             let scale = scale_spinner.value() as f32;
@@ -181,6 +524,25 @@ fn add_event_handlers(
                 app::set_screen_scale(0, scale);
             }
             config.history_size = history_size_spinner.value() as usize;
+            config.positions_size = positions_size_spinner.value() as usize;
+            let size = config.positions_size;
+            config.positions.truncate(size);
+            config.stream_size = stream_size_spinner.value() as u64;
+            config.crossfade = crossfade_spinner.value();
+            config.sleep_fade = sleep_fade_spinner.value();
+            config.skip_short = skip_short_spinner.value();
+            config.skip_medium = skip_medium_spinner.value();
+            config.skip_long = skip_long_spinner.value();
+            config.rewind = rewind_spinner.value();
+            config.resume_rewind = resume_rewind_spinner.value();
+            config.autosave = autosave_spinner.value();
+            config.recursive = recursive_check.is_checked();
+            config.keep_queue = keep_queue_check.is_checked();
+            config.library_roots =
+                env::split_paths(library_roots_input.value().trim())
+                    .filter(|root| !root.as_os_str().is_empty())
+                    .collect();
+            config.keys = keymap.borrow().clone();
             form.hide();
         }
     });
@@ -193,4 +555,5 @@ fn add_event_handlers(
 }
 
 const WIDTH: i32 = 340;
-const HEIGHT: i32 = 120;
+const HEIGHT: i32 = 770;
+const KEYS_HEIGHT: i32 = 140;
@@ -0,0 +1,435 @@
+// Copyright © 2021-22 Mark Summerfield. All rights reserved.
+// License: GPLv3
+
+use crate::util::{self, WhichTrack};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+pub static PLAYLIST_SUFFIXES: [&str; 3] = ["m3u", "m3u8", "pls"];
+
+#[derive(Clone, Debug, Default)]
+pub struct Playlist {
+    pub name: String,
+    pub filename: PathBuf, // empty if never loaded or saved
+    pub tracks: Vec<PlaylistTrack>,
+    pub index: usize, // of the current track
+}
+
+#[derive(Clone, Debug)]
+pub struct PlaylistTrack {
+    pub track: PathBuf,
+    pub title: String,     // empty if unknown
+    pub secs: Option<f64>, // None if unknown
+}
+
+impl PlaylistTrack {
+    pub fn new(track: PathBuf) -> Self {
+        Self { track, title: String::new(), secs: None }
+    }
+}
+
+impl Playlist {
+    pub fn new(name: &str, tracks: Vec<PathBuf>) -> Self {
+        Self {
+            name: name.to_string(),
+            tracks: tracks.into_iter().map(PlaylistTrack::new).collect(),
+            ..Default::default()
+        }
+    }
+
+    // Reads an M3U, extended M3U (.m3u or .m3u8), or PLS playlist;
+    // relative paths are relative to the playlist's own folder
+    pub fn load(filename: &Path) -> io::Result<Self> {
+        let data = fs::read(filename)?;
+        let text = String::from_utf8_lossy(&data);
+        let dir = filename.parent().unwrap_or_else(|| Path::new("."));
+        let tracks = if has_suffix(filename, "pls") {
+            parse_pls(&text, dir)
+        } else {
+            parse_m3u(&text, dir)
+        };
+        if tracks.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "no playable tracks found",
+            ));
+        }
+        Ok(Self {
+            name: playlist_name(filename),
+            filename: filename.to_path_buf(),
+            tracks,
+            index: 0,
+        })
+    }
+
+    // Writes a PLS playlist if the filename ends with .pls, a plain M3U
+    // if it ends with .m3u, and otherwise an extended (UTF-8) M3U
+    pub fn save(&mut self, filename: &Path) -> io::Result<()> {
+        let dir = filename.parent().unwrap_or_else(|| Path::new("."));
+        let text = if has_suffix(filename, "pls") {
+            self.pls_text(dir)
+        } else if has_suffix(filename, "m3u") {
+            self.m3u_text(dir)
+        } else {
+            self.m3u8_text(dir)
+        };
+        fs::write(filename, text)?;
+        self.filename = filename.to_path_buf();
+        self.name = playlist_name(filename);
+        Ok(())
+    }
+
+    pub fn current(&self) -> Option<&Path> {
+        self.tracks.get(self.index).map(|t| t.track.as_path())
+    }
+
+    pub fn contains(&self, track: &Path) -> bool {
+        self.tracks.iter().any(|t| t.track == track)
+    }
+
+    // Makes the given track current if it is in the playlist; searches
+    // forward from the current track so that repeated tracks are handled
+    pub fn set_current(&mut self, track: &Path) -> bool {
+        match self.find(track) {
+            Some(index) => {
+                self.index = index;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn find(&self, track: &Path) -> Option<usize> {
+        if self.current() == Some(track) {
+            return Some(self.index);
+        }
+        let size = self.tracks.len();
+        (1..=size)
+            .map(|offset| (self.index + offset) % size)
+            .find(|&index| self.tracks[index].track == track)
+    }
+
+    // Returns the track before or after the given one skipping missing
+    // ones, or None if there isn't one; if wrap is true the first track
+    // follows the last (and vice versa)
+    pub fn get_prev_or_next_track(
+        &self,
+        track: &Path,
+        which: WhichTrack,
+        wrap: bool,
+    ) -> Option<PathBuf> {
+        let index = self.find(track)?;
+        let index = util::get_prev_or_next_index(
+            index,
+            self.tracks.len(),
+            which,
+            wrap,
+            |i| self.tracks[i].track.exists(),
+        )?;
+        Some(self.tracks[index].track.clone())
+    }
+
+    fn m3u_text(&self, dir: &Path) -> String {
+        let mut text = String::new();
+        for track in &self.tracks {
+            text.push_str(&relative_name(&track.track, dir));
+            text.push('\n');
+        }
+        text
+    }
+
+    fn m3u8_text(&self, dir: &Path) -> String {
+        let mut text = String::from("#EXTM3U\n");
+        for track in &self.tracks {
+            let (title, secs) = track_title_and_secs(track);
+            text.push_str(&format!("#EXTINF:{secs},{title}\n"));
+            text.push_str(&relative_name(&track.track, dir));
+            text.push('\n');
+        }
+        text
+    }
+
+    fn pls_text(&self, dir: &Path) -> String {
+        let mut text = String::from("[playlist]\n");
+        for (i, track) in self.tracks.iter().enumerate() {
+            let i = i + 1;
+            let (title, secs) = track_title_and_secs(track);
+            text.push_str(&format!(
+                "File{i}={}\nTitle{i}={title}\nLength{i}={secs}\n",
+                relative_name(&track.track, dir)
+            ));
+        }
+        text.push_str(&format!(
+            "NumberOfEntries={}\nVersion=2\n",
+            self.tracks.len()
+        ));
+        text
+    }
+}
+
+pub fn is_playlist(filename: &Path) -> bool {
+    PLAYLIST_SUFFIXES.iter().any(|suffix| has_suffix(filename, suffix))
+}
+
+fn has_suffix(filename: &Path, suffix: &str) -> bool {
+    if let Some(extension) = filename.extension() {
+        return extension.to_string_lossy().to_lowercase() == suffix;
+    }
+    false
+}
+
+fn playlist_name(filename: &Path) -> String {
+    if let Some(name) = filename.file_stem() {
+        name.to_string_lossy().to_string()
+    } else {
+        filename.to_string_lossy().to_string()
+    }
+}
+
+fn parse_m3u(text: &str, dir: &Path) -> Vec<PlaylistTrack> {
+    let mut tracks = vec![];
+    let mut title = String::new();
+    let mut secs = None;
+    for line in text.lines() {
+        let line = line.trim().trim_start_matches('\u{FEFF}');
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(info) = line.strip_prefix("#EXTINF:") {
+            // #EXTINF:secs,title (secs is -1 if unknown)
+            let (length, name) = info.split_once(',').unwrap_or((info, ""));
+            secs = match length.trim().parse::<f64>() {
+                Ok(n) if n >= 0.0 => Some(n),
+                _ => None,
+            };
+            title = name.trim().to_string();
+        } else if !line.starts_with('#') {
+            if let Some(track) = track_path(line, dir) {
+                tracks.push(PlaylistTrack {
+                    track,
+                    title: title.clone(),
+                    secs,
+                });
+            }
+            title.clear();
+            secs = None;
+        }
+    }
+    tracks
+}
+
+fn parse_pls(text: &str, dir: &Path) -> Vec<PlaylistTrack> {
+    let mut tracks: Vec<(usize, PlaylistTrack)> = vec![];
+    let mut titles = vec![];
+    let mut lengths = vec![];
+    for line in text.lines() {
+        if let Some((key, value)) = line.trim().split_once('=') {
+            let key = key.trim().to_lowercase();
+            let value = value.trim();
+            if let Some(n) = pls_number(&key, "file") {
+                if let Some(track) = track_path(value, dir) {
+                    tracks.push((n, PlaylistTrack::new(track)));
+                }
+            } else if let Some(n) = pls_number(&key, "title") {
+                titles.push((n, value.to_string()));
+            } else if let Some(n) = pls_number(&key, "length") {
+                if let Ok(secs) = value.parse::<f64>() {
+                    if secs >= 0.0 {
+                        lengths.push((n, secs));
+                    }
+                }
+            }
+        }
+    }
+    tracks.sort_by_key(|(n, _)| *n);
+    for (n, track) in tracks.iter_mut() {
+        if let Some((_, title)) = titles.iter().find(|(i, _)| i == n) {
+            track.title = title.clone();
+        }
+        if let Some((_, secs)) = lengths.iter().find(|(i, _)| i == n) {
+            track.secs = Some(*secs);
+        }
+    }
+    tracks.into_iter().map(|(_, track)| track).collect()
+}
+
+fn pls_number(key: &str, prefix: &str) -> Option<usize> {
+    key.strip_prefix(prefix).and_then(|n| n.parse::<usize>().ok())
+}
+
+// Returns the path for a playlist entry if it is a local file that exists
+fn track_path(name: &str, dir: &Path) -> Option<PathBuf> {
+    let name = if let Some(name) = name.strip_prefix("file://") {
+        util::percent_decode(name)
+    } else if name.contains("://") {
+        return None; // Streams aren't supported
+    } else {
+        name.to_string()
+    };
+    let path = PathBuf::from(name.replace('\\', "/"));
+    let path = if path.is_absolute() { path } else { dir.join(path) };
+    if path.is_file() {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+// Tracks inside the playlist's folder are saved relative to it
+fn relative_name(track: &Path, dir: &Path) -> String {
+    match track.strip_prefix(dir) {
+        Ok(name) => name.to_string_lossy().to_string(),
+        Err(_) => track.to_string_lossy().to_string(),
+    }
+}
+
+fn track_title_and_secs(track: &PlaylistTrack) -> (String, i64) {
+    let title = if track.title.is_empty() {
+        util::get_track_title(&track.track)
+    } else {
+        track.title.clone()
+    };
+    let secs = match track.secs {
+        Some(secs) => secs.round() as i64,
+        None => match util::get_track_secs(&track.track) {
+            Some(secs) => secs.round() as i64,
+            None => -1,
+        },
+    };
+    (title, secs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, process};
+
+    // Returns a new folder holding the named (empty) files
+    fn make_dir(test: &str, names: &[&str]) -> PathBuf {
+        let dir = env::temp_dir()
+            .join(format!("amp-playlist-{test}-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        for name in names {
+            fs::write(dir.join(name), b"").unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn prev_or_next_skips_missing() {
+        let dir = make_dir("skip", &["a.mp3", "c.mp3"]);
+        let [a, b, c] =
+            ["a.mp3", "b.mp3", "c.mp3"].map(|name| dir.join(name));
+        let playlist = Playlist::new("", vec![a.clone(), b, c.clone()]);
+        let next = |track: &Path, wrap| {
+            playlist.get_prev_or_next_track(track, WhichTrack::Next, wrap)
+        };
+        let previous = |track: &Path, wrap| {
+            playlist.get_prev_or_next_track(
+                track,
+                WhichTrack::Previous,
+                wrap,
+            )
+        };
+        assert_eq!(next(&a, false), Some(c.clone()));
+        assert_eq!(previous(&c, false), Some(a.clone()));
+        assert_eq!(next(&c, false), None);
+        assert_eq!(next(&c, true), Some(a.clone()));
+        assert_eq!(previous(&a, true), Some(c));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn prev_or_next_all_missing() {
+        let dir = make_dir("missing", &[]);
+        let tracks = vec![dir.join("a.mp3"), dir.join("b.mp3")];
+        let playlist = Playlist::new("", tracks.clone());
+        for which in [WhichTrack::Previous, WhichTrack::Next] {
+            assert_eq!(
+                playlist.get_prev_or_next_track(&tracks[0], which, true),
+                None
+            );
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn m3u_extinf() {
+        let dir = make_dir("extinf", &["a.mp3", "b.mp3", "c.mp3"]);
+        let text = "\u{FEFF}#EXTM3U\n#EXTINF:93,Artist - Title\na.mp3\n\n\
+                    #EXTINF:-1,Unknown Length\nb.mp3\n# comment\nc.mp3\n";
+        let tracks = parse_m3u(text, &dir);
+        assert_eq!(tracks.len(), 3);
+        assert_eq!(tracks[0].track, dir.join("a.mp3"));
+        assert_eq!(tracks[0].title, "Artist - Title");
+        assert_eq!(tracks[0].secs, Some(93.0));
+        assert_eq!(tracks[1].title, "Unknown Length");
+        assert_eq!(tracks[1].secs, None);
+        assert_eq!(tracks[2].title, ""); // #EXTINF isn't carried further
+        assert_eq!(tracks[2].secs, None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn m3u_bom_and_urls() {
+        let dir = make_dir("urls", &["a b#1.mp3", "c.mp3"]);
+        let text = format!(
+            "\u{FEFF}c.mp3\nfile://{}/a%20b%231.mp3\n\
+             http://example.com/stream.mp3\nmissing.mp3\n",
+            dir.display()
+        );
+        let tracks = parse_m3u(&text, &dir);
+        let paths: Vec<&Path> =
+            tracks.iter().map(|track| track.track.as_path()).collect();
+        assert_eq!(paths, [dir.join("c.mp3"), dir.join("a b#1.mp3")]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn pls_out_of_order() {
+        let dir = make_dir("pls", &["a.mp3", "b.mp3"]);
+        let text = "[playlist]\nTitle2=Second\nFile2=b.mp3\n\
+                    Length1=-1\nfile1 = a.mp3\nTitle1=First\n\
+                    Length2=61\nFile3=http://example.com/stream\n\
+                    NumberOfEntries=3\nVersion=2\n";
+        let tracks = parse_pls(text, &dir);
+        assert_eq!(tracks.len(), 2);
+        assert_eq!(tracks[0].track, dir.join("a.mp3"));
+        assert_eq!(tracks[0].title, "First");
+        assert_eq!(tracks[0].secs, None);
+        assert_eq!(tracks[1].track, dir.join("b.mp3"));
+        assert_eq!(tracks[1].title, "Second");
+        assert_eq!(tracks[1].secs, Some(61.0));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn save_and_load() {
+        let dir = make_dir("save", &["a b.mp3", "c.mp3"]);
+        let mut playlist =
+            Playlist::new("", vec![dir.join("a b.mp3"), dir.join("c.mp3")]);
+        playlist.tracks[0].title = "First".to_string();
+        playlist.tracks[0].secs = Some(93.0);
+        playlist.tracks[1].title = "Second".to_string();
+        playlist.tracks[1].secs = Some(61.0);
+        for name in ["list.m3u8", "list.pls", "list.m3u"] {
+            let filename = dir.join(name);
+            playlist.save(&filename).unwrap();
+            let loaded = Playlist::load(&filename).unwrap();
+            assert_eq!(loaded.name, "list");
+            assert_eq!(loaded.tracks.len(), 2);
+            for (track, saved) in loaded.tracks.iter().zip(&playlist.tracks)
+            {
+                assert_eq!(track.track, saved.track);
+                if name != "list.m3u" {
+                    assert_eq!(track.title, saved.title);
+                    assert_eq!(track.secs, saved.secs);
+                }
+            }
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
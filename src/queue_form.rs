@@ -0,0 +1,211 @@
+// Copyright © 2021-22 Mark Summerfield. All rights reserved.
+// License: GPLv3
+
+// Shows the queued tracks (which play next, before the folder's or
+// playlist's) so that they can be reordered or removed
+
+use crate::fixed::{APPNAME, BUTTON_HEIGHT, BUTTON_WIDTH, ICON, PAD};
+use crate::util;
+use fltk::{
+    app, browser::HoldBrowser, button::Button, enums::Color, frame::Frame,
+    group::Flex, image::SvgImage, prelude::*, window::Window,
+};
+use std::{cell::RefCell, collections::VecDeque, path::PathBuf, rc::Rc};
+
+type Queue = VecDeque<PathBuf>;
+
+pub struct Form {
+    form: Window,
+    pub ok: Rc<RefCell<bool>>,
+    pub queue: Rc<RefCell<Queue>>, // the edited copy of the queue
+}
+
+impl Form {
+    pub fn new(queue: &Queue) -> Self {
+        let ok = Rc::from(RefCell::from(false));
+        let queue = Rc::from(RefCell::from(queue.clone()));
+        let mut form = make_form();
+        let mut vbox = Flex::default().size_of_parent().column();
+        vbox.set_margin(PAD);
+        vbox.set_pad(PAD);
+        let mut browser = HoldBrowser::default();
+        let (edit_row, mut buttons) = make_edit_buttons();
+        vbox.set_size(&edit_row, BUTTON_HEIGHT);
+        let (button_row, mut ok_button, mut cancel_button) = make_buttons();
+        vbox.set_size(&button_row, BUTTON_HEIGHT);
+        vbox.end();
+        form.end();
+        form.make_modal(true);
+        populate_browser(&mut browser, &queue.borrow(), 1);
+        add_edit_event_handlers(&mut buttons, &browser, &queue);
+        add_event_handlers(
+            &mut form,
+            &mut ok_button,
+            &mut cancel_button,
+            Rc::clone(&ok),
+        );
+        browser.take_focus().unwrap();
+        form.show();
+        while form.shown() {
+            app::wait();
+        }
+        Self { form, ok, queue }
+    }
+}
+
+impl Drop for Form {
+    fn drop(&mut self) {
+        app::delete_widget(self.form.clone());
+    }
+}
+
+struct EditButtons {
+    pub up_button: Button,
+    pub down_button: Button,
+    pub remove_button: Button,
+    pub clear_button: Button,
+}
+
+fn make_form() -> Window {
+    let image = SvgImage::from_data(ICON).unwrap();
+    let mut form = Window::default()
+        .with_size(WIDTH, HEIGHT)
+        .with_label(&format!("Queue — {APPNAME}"));
+    if let Some(window) = app::first_window() {
+        form.set_pos(window.x() + 50, window.y() + 100);
+    }
+    form.make_resizable(true);
+    form.set_icon(Some(image));
+    form
+}
+
+fn make_edit_buttons() -> (Flex, EditButtons) {
+    let mut row = Flex::default().size_of_parent().row();
+    row.set_pad(PAD);
+    let up_button = Button::default().with_label("Move &Up");
+    let down_button = Button::default().with_label("Move &Down");
+    let remove_button = Button::default().with_label("&Remove");
+    let clear_button = Button::default().with_label("C&lear");
+    row.end();
+    (
+        row,
+        EditButtons { up_button, down_button, remove_button, clear_button },
+    )
+}
+
+fn make_buttons() -> (Flex, Button, Button) {
+    let mut row = Flex::default().size_of_parent().row();
+    row.set_pad(PAD);
+    Frame::default(); // pad left of buttons
+    let ok_button = Button::default().with_label("&OK");
+    let cancel_button = Button::default().with_label("&Cancel");
+    Frame::default(); // pad right of buttons
+    row.set_size(&ok_button, BUTTON_WIDTH);
+    row.set_size(&cancel_button, BUTTON_WIDTH);
+    row.end();
+    (row, ok_button, cancel_button)
+}
+
+// Lines are "title — folder" with format characters disabled (and greyed
+// out if the track is missing); selected is 1-based (the browser's
+// convention) and 0 for none
+fn populate_browser(
+    browser: &mut HoldBrowser,
+    queue: &Queue,
+    selected: i32,
+) {
+    browser.clear();
+    for track in queue {
+        let folder = match track.parent().and_then(|dir| dir.file_name()) {
+            Some(folder) => folder.to_string_lossy().to_string(),
+            None => String::new(),
+        };
+        let colour = if track.exists() {
+            String::new()
+        } else {
+            format!("@C{}", Color::Inactive.bits())
+        };
+        browser.add(&format!(
+            "{colour}@.{} — {folder}",
+            util::get_track_name(track)
+        ));
+    }
+    if selected > 0 && selected <= browser.size() {
+        browser.select(selected);
+    }
+}
+
+fn add_edit_event_handlers(
+    buttons: &mut EditButtons,
+    browser: &HoldBrowser,
+    queue: &Rc<RefCell<Queue>>,
+) {
+    buttons.up_button.set_callback({
+        let mut browser = browser.clone();
+        let queue = Rc::clone(queue);
+        move |_| {
+            let line = browser.value();
+            if line > 1 {
+                let index = (line - 1) as usize;
+                queue.borrow_mut().swap(index - 1, index);
+                populate_browser(&mut browser, &queue.borrow(), line - 1);
+            }
+        }
+    });
+    buttons.down_button.set_callback({
+        let mut browser = browser.clone();
+        let queue = Rc::clone(queue);
+        move |_| {
+            let line = browser.value();
+            if line > 0 && line < browser.size() {
+                let index = (line - 1) as usize;
+                queue.borrow_mut().swap(index, index + 1);
+                populate_browser(&mut browser, &queue.borrow(), line + 1);
+            }
+        }
+    });
+    buttons.remove_button.set_callback({
+        let mut browser = browser.clone();
+        let queue = Rc::clone(queue);
+        move |_| {
+            let line = browser.value();
+            if line > 0 {
+                queue.borrow_mut().remove((line - 1) as usize);
+                let line = line.min(queue.borrow().len() as i32);
+                populate_browser(&mut browser, &queue.borrow(), line);
+            }
+        }
+    });
+    buttons.clear_button.set_callback({
+        let mut browser = browser.clone();
+        let queue = Rc::clone(queue);
+        move |_| {
+            queue.borrow_mut().clear();
+            populate_browser(&mut browser, &queue.borrow(), 0);
+        }
+    });
+}
+
+fn add_event_handlers(
+    form: &mut Window,
+    ok_button: &mut Button,
+    cancel_button: &mut Button,
+    ok: Rc<RefCell<bool>>,
+) {
+    ok_button.set_callback({
+        let mut form = form.clone();
+        move |_| {
+            *ok.borrow_mut() = true;
+            form.hide();
+        }
+    });
+    cancel_button.set_callback({
+        let mut form = form.clone();
+        move |_| {
+            form.hide();
+        }
+    });
+}
+
+const WIDTH: i32 = 420;
+const HEIGHT: i32 = 320;
@@ -0,0 +1,411 @@
+// Copyright © 2021-22 Mark Summerfield. All rights reserved.
+// License: GPLv3
+
+// An optional HTTP/JSON remote control (e.g., from a phone on the LAN)
+// with a tiny web page. It is off by default and binds to localhost
+// unless the config's [Remote] section says otherwise. Commands are sent
+// to the application as Actions (or as Commands if they carry data) and
+// status is read from the shared STATUS and CONFIG
+//
+// GET  /                 the web page
+// GET  /api/status       track, tag data, position, length, volume, etc.
+// GET  /api/history      the history's tracks
+// GET  /api/bookmarks    the bookmarks
+// POST /api/play-pause   (also /api/next and /api/previous)
+// POST /api/seek?pos=TIME            TIME as for --position
+// POST /api/volume?volume=N          N from 0.0 to 1.0
+// POST /api/command?text=COMMANDS    e.g., seek +30; volume 50%
+//
+// If the config has a token every request must include ?token=TOKEN.
+// Requests from other sites' pages (i.e., whose Origin isn't this server)
+// are refused, and without a token so are those whose Host is a name
+// other than localhost (which could be a DNS rebinding attack). Only
+// playback commands may be sent, and requests are limited in size and
+// number
+
+use super::CONFIG;
+use crate::command::Command;
+use crate::fixed::{
+    Action, APPNAME, REMOTE_MAX_CONNECTIONS, REMOTE_MAX_REQUEST,
+    REMOTE_TIMEOUT_SECS,
+};
+use crate::ipc::{self, ActionSender};
+use crate::status::STATUS;
+use crate::util;
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader, Read, Write},
+    net::{IpAddr, TcpListener, TcpStream},
+    path::Path,
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+    time::Duration,
+};
+
+static REMOTE_HTML: &str = include_str!("../data/remote.html");
+static CONNECTIONS: AtomicUsize = AtomicUsize::new(0);
+
+struct Response {
+    status: &'static str,
+    content_type: &'static str,
+    body: String,
+}
+
+impl Response {
+    fn json(body: String) -> Self {
+        Self { status: "200 OK", content_type: "application/json", body }
+    }
+
+    fn error(status: &'static str) -> Self {
+        Self {
+            status,
+            content_type: "application/json",
+            body: format!("{{\"error\":{}}}", json_string(status)),
+        }
+    }
+
+    fn bad_request(message: &str) -> Self {
+        Self {
+            body: format!("{{\"error\":{}}}", json_string(message)),
+            ..Self::error("400 Bad Request")
+        }
+    }
+
+    fn forbidden(message: &str) -> Self {
+        Self {
+            body: format!("{{\"error\":{}}}", json_string(message)),
+            ..Self::error("403 Forbidden")
+        }
+    }
+}
+
+// Listens in a background thread if the remote control is enabled
+pub fn serve(sender: ActionSender) {
+    let (enabled, address, token) = {
+        let config = CONFIG.get().read().unwrap();
+        (
+            config.remote,
+            config.remote_address.clone(),
+            config.remote_token.clone(),
+        )
+    };
+    if !enabled {
+        return;
+    }
+    let listener = match TcpListener::bind(&address) {
+        Ok(listener) => listener,
+        Err(err) => {
+            eprintln!(
+                "{APPNAME}: failed to start remote control on \
+                 {address}: {err}"
+            );
+            return;
+        }
+    };
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            if CONNECTIONS.fetch_add(1, Ordering::SeqCst)
+                >= REMOTE_MAX_CONNECTIONS
+            {
+                CONNECTIONS.fetch_sub(1, Ordering::SeqCst);
+                continue; // Too busy: the connection is dropped
+            }
+            let sender = sender.clone();
+            let token = token.clone();
+            thread::spawn(move || {
+                let _ = handle(stream, &sender, &token); // Ignore error
+                CONNECTIONS.fetch_sub(1, Ordering::SeqCst);
+            });
+        }
+    });
+}
+
+fn handle(
+    mut stream: TcpStream,
+    sender: &ActionSender,
+    token: &str,
+) -> std::io::Result<()> {
+    let timeout = Some(Duration::from_secs(REMOTE_TIMEOUT_SECS));
+    stream.set_read_timeout(timeout)?;
+    stream.set_write_timeout(timeout)?;
+    let mut reader =
+        BufReader::new(stream.try_clone()?).take(REMOTE_MAX_REQUEST);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // Requests carry no body so only the headers are left to read
+    let mut headers = HashMap::new();
+    let mut line = String::new();
+    let mut complete = false;
+    while reader.read_line(&mut line)? > 0 {
+        if line.trim().is_empty() {
+            complete = true;
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.insert(
+                name.trim().to_lowercase(),
+                value.trim().to_string(),
+            );
+        }
+        line.clear();
+    }
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default();
+    let target = parts.next().unwrap_or_default();
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let query = parse_query(query);
+    let response = if !complete {
+        Response::error("431 Request Header Fields Too Large")
+    } else if !is_allowed_origin(&headers, token)
+        || (!token.is_empty()
+            && query.get("token").map(|t| t.as_str()) != Some(token))
+    {
+        Response::error("403 Forbidden")
+    } else {
+        respond(method, path, &query, sender)
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}; charset=utf-8\r\n\
+         Content-Length: {}\r\nCache-Control: no-store\r\n\
+         Connection: close\r\n\r\n{}",
+        response.status,
+        response.content_type,
+        response.body.len(),
+        response.body
+    )?;
+    stream.flush()
+}
+
+fn respond(
+    method: &str,
+    path: &str,
+    query: &HashMap<String, String>,
+    sender: &ActionSender,
+) -> Response {
+    match (method, path) {
+        ("GET", "/") | ("GET", "/index.html") => Response {
+            status: "200 OK",
+            content_type: "text/html",
+            body: REMOTE_HTML.to_string(),
+        },
+        ("GET", "/api/status") => Response::json(status_json()),
+        ("GET", "/api/history") => Response::json(history_json()),
+        ("GET", "/api/bookmarks") => Response::json(bookmarks_json()),
+        ("POST", "/api/play-pause") => {
+            sender.send(Action::PlayOrPause);
+            Response::json(status_json())
+        }
+        ("POST", "/api/next") => {
+            sender.send(Action::Next);
+            Response::json(status_json())
+        }
+        ("POST", "/api/previous") => {
+            sender.send(Action::Previous);
+            Response::json(status_json())
+        }
+        ("POST", "/api/seek") => {
+            match query.get("pos").and_then(|pos| util::parse_time(pos)) {
+                Some(pos) => {
+                    ipc::send_command(Command::Seek(pos), sender);
+                    Response::json(status_json())
+                }
+                None => Response::error("400 Bad Request"),
+            }
+        }
+        ("POST", "/api/command") => {
+            let text = query.get("text").map(|text| text.as_str());
+            match Command::parse(
+                text.unwrap_or_default(),
+                &util::get_track_dir(),
+            ) {
+                Ok(commands) if commands.iter().all(is_playback) => {
+                    for command in commands {
+                        ipc::send_command(command, sender);
+                    }
+                    Response::json(status_json())
+                }
+                Ok(_) => Response::forbidden(
+                    "only playback commands are allowed",
+                ),
+                Err(err) => Response::bad_request(&err),
+            }
+        }
+        ("POST", "/api/volume") => match query
+            .get("volume")
+            .and_then(|volume| volume.parse::<f64>().ok())
+        {
+            Some(volume) if (0.0..=1.0).contains(&volume) => {
+                ipc::send_command(Command::Volume(volume), sender);
+                Response::json(status_json())
+            }
+            _ => Response::error("400 Bad Request"),
+        },
+        ("GET", _) | ("POST", _) => Response::error("404 Not Found"),
+        _ => Response::error("405 Method Not Allowed"),
+    }
+}
+
+// A page from another site sends its own Origin; and without a token the
+// Host must be localhost or an IP address since any other name could
+// resolve to us just to get round the Origin check
+fn is_allowed_origin(
+    headers: &HashMap<String, String>,
+    token: &str,
+) -> bool {
+    let host = headers.get("host").map_or("", |host| host.as_str());
+    if let Some(origin) = headers.get("origin") {
+        if *origin != format!("http://{host}") {
+            return false;
+        }
+    }
+    if !token.is_empty() {
+        return true;
+    }
+    let name = host.rsplit_once(':').map_or(host, |(name, _)| name);
+    let name = name.trim_start_matches('[').trim_end_matches(']');
+    name.eq_ignore_ascii_case("localhost") || name.parse::<IpAddr>().is_ok()
+}
+
+// Only commands that control playback may be sent remotely, not those
+// that quit, open dialogs, load any path, or change the config
+fn is_playback(command: &Command) -> bool {
+    match command {
+        Command::Play
+        | Command::Pause
+        | Command::Seek(_)
+        | Command::Skip(_)
+        | Command::Volume(_)
+        | Command::Speed(_) => true,
+        Command::Do(action) => matches!(
+            action,
+            Action::ClearLoop
+                | Action::Next
+                | Action::PlayOrPause
+                | Action::Previous
+                | Action::Repeat
+                | Action::RepeatAll
+                | Action::RepeatOff
+                | Action::RepeatTrack
+                | Action::Replay
+                | Action::Rewind
+                | Action::SetLoopA
+                | Action::SetLoopB
+                | Action::Shuffle
+                | Action::ShuffleAlbums
+                | Action::ShuffleOff
+                | Action::ShuffleTracks
+                | Action::SkipBack
+                | Action::SkipBackLong
+                | Action::SkipBackMedium
+                | Action::SkipForward
+                | Action::SkipForwardLong
+                | Action::SkipForwardMedium
+                | Action::SpeedDown
+                | Action::SpeedNormal
+                | Action::SpeedUp
+                | Action::ToggleLoop
+                | Action::VolumeDown
+                | Action::VolumeUp
+        ),
+        _ => false,
+    }
+}
+
+fn status_json() -> String {
+    let status = STATUS.get().read().unwrap().clone();
+    format!(
+        "{{\"track\":{},\"title\":{},\"album\":{},\"artist\":{},\
+         \"number\":{},\"year\":{},\"playing\":{},\"pos\":{:.3},\
+         \"length\":{:.3},\"volume\":{:.3},\"speed\":{:.3}}}",
+        json_path(&status.track),
+        json_string(&status.data.title),
+        json_string(&status.data.album),
+        json_string(&status.data.artist),
+        status.data.number,
+        status.data.year,
+        status.playing,
+        status.pos,
+        status.length,
+        status.volume,
+        status.speed
+    )
+}
+
+fn history_json() -> String {
+    let config = CONFIG.get().read().unwrap();
+    let items: Vec<String> = config
+        .history
+        .iter()
+        .map(|track| {
+            format!(
+                "{{\"track\":{},\"name\":{},\"missing\":{}}}",
+                json_path(track),
+                json_string(&util::get_track_name(track)),
+                !track.exists()
+            )
+        })
+        .collect();
+    format!("[{}]", items.join(","))
+}
+
+fn bookmarks_json() -> String {
+    let config = CONFIG.get().read().unwrap();
+    let items: Vec<String> = config
+        .bookmarks
+        .iter()
+        .map(|bookmark| {
+            format!(
+                "{{\"track\":{},\"name\":{},\"pos\":{:.3},\"note\":{},\
+                 \"missing\":{}}}",
+                json_path(&bookmark.track),
+                json_string(&util::get_track_name(&bookmark.track)),
+                bookmark.pos,
+                json_string(&bookmark.note),
+                !bookmark.track.exists()
+            )
+        })
+        .collect();
+    format!("[{}]", items.join(","))
+}
+
+fn json_path(path: &Path) -> String {
+    json_string(&path.to_string_lossy())
+}
+
+fn json_string(text: &str) -> String {
+    let mut json = String::with_capacity(text.len() + 2);
+    json.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                json.push_str(&format!("\\u{:04x}", c as u32))
+            }
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+fn parse_query(query: &str) -> HashMap<String, String> {
+    query
+        .split('&')
+        .filter_map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            if key.is_empty() {
+                None
+            } else {
+                Some((
+                    util::percent_decode(&key.replace('+', " ")),
+                    util::percent_decode(&value.replace('+', " ")),
+                ))
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,109 @@
+// Copyright © 2021-22 Mark Summerfield. All rights reserved.
+// License: GPLv3
+
+// Shuffled play orders: each track once in a random order, or each album
+// once in a random order with its tracks in track number order
+
+use crate::util;
+use rand::{seq::SliceRandom, thread_rng};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ShuffleMode {
+    Off,
+    Tracks,
+    Albums,
+}
+
+impl ShuffleMode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ShuffleMode::Off => "off",
+            ShuffleMode::Tracks => "tracks",
+            ShuffleMode::Albums => "albums",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "off" => Some(ShuffleMode::Off),
+            "tracks" => Some(ShuffleMode::Tracks),
+            "albums" => Some(ShuffleMode::Albums),
+            _ => None,
+        }
+    }
+
+    // Off → Tracks → Albums → Off
+    pub fn cycled(&self) -> Self {
+        match self {
+            ShuffleMode::Off => ShuffleMode::Tracks,
+            ShuffleMode::Tracks => ShuffleMode::Albums,
+            ShuffleMode::Albums => ShuffleMode::Off,
+        }
+    }
+}
+
+// Returns the tracks in a random order that starts with first (or, for
+// albums, with first's album)
+pub fn shuffled(
+    tracks: &[PathBuf],
+    mode: ShuffleMode,
+    first: &Path,
+) -> Vec<PathBuf> {
+    let mut rng = thread_rng();
+    match mode {
+        ShuffleMode::Off => tracks.to_vec(),
+        ShuffleMode::Tracks => {
+            let mut order: Vec<PathBuf> =
+                tracks.iter().filter(|t| *t != first).cloned().collect();
+            order.shuffle(&mut rng);
+            if tracks.iter().any(|t| t == first) {
+                order.insert(0, first.to_path_buf());
+            }
+            order
+        }
+        ShuffleMode::Albums => {
+            let mut albums = get_albums(tracks);
+            albums.shuffle(&mut rng);
+            if let Some(index) = albums
+                .iter()
+                .position(|album| album.iter().any(|t| t == first))
+            {
+                let album = albums.remove(index);
+                albums.insert(0, album);
+            }
+            albums.into_iter().flatten().collect()
+        }
+    }
+}
+
+// Groups the tracks by album (or, if untagged, by folder), each in track
+// number then name order
+fn get_albums(tracks: &[PathBuf]) -> Vec<Vec<PathBuf>> {
+    let mut albums: HashMap<String, Vec<(i32, PathBuf)>> = HashMap::new();
+    for track in tracks {
+        let (album, number) = match util::get_track_tag(track) {
+            Ok(Some(data)) if !data.album.is_empty() => {
+                (data.album, data.number)
+            }
+            _ => (
+                track
+                    .parent()
+                    .map(|dir| dir.to_string_lossy().to_string())
+                    .unwrap_or_default(),
+                0,
+            ),
+        };
+        albums.entry(album).or_default().push((number, track.clone()));
+    }
+    albums
+        .into_values()
+        .map(|mut album| {
+            album.sort();
+            album.into_iter().map(|(_, track)| track).collect()
+        })
+        .collect()
+}
@@ -0,0 +1,42 @@
+// Copyright © 2021-22 Mark Summerfield. All rights reserved.
+// License: GPLv3
+
+use crate::util::{self, TrackData};
+use state::Storage;
+use std::{
+    path::{Path, PathBuf},
+    sync::RwLock,
+};
+
+// A snapshot of the player's state that the application keeps up to date
+// for other threads (e.g., MPRIS) to read
+pub static STATUS: Storage<RwLock<Status>> = Storage::new();
+
+#[derive(Clone, Debug, Default)]
+pub struct Status {
+    pub serial: u64, // incremented whenever the track changes
+    pub track: PathBuf,
+    pub data: TrackData, // title is the filename's stem if untagged
+    pub playing: bool,
+    pub pos: f64,    // secs
+    pub length: f64, // secs
+    pub volume: f64, // 0.0 to 1.0
+    pub speed: f64,
+    pub sleeping: bool, // the sleep timer is counting down
+}
+
+impl Status {
+    pub fn set_track(&mut self, track: &Path, length: f64) {
+        self.serial += 1;
+        self.track = track.to_path_buf();
+        self.data = match util::get_track_tag(track) {
+            Ok(Some(data)) => data,
+            _ => TrackData::default(),
+        };
+        if self.data.title.is_empty() {
+            self.data.title = util::get_track_title(track);
+        }
+        self.length = length;
+        self.pos = 0.0;
+    }
+}
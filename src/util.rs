@@ -2,11 +2,13 @@
 // License: GPLv3
 
 use super::CONFIG;
+use crate::fixed::{AUDIO_SUFFIXES, MAX_FOLDER_TRACKS};
 use fltk::app;
-use lofty::{self, Accessor, ItemKey, ItemValue, Probe};
+use lofty::{self, Accessor, ItemKey, ItemValue, Probe, Tag};
 use std::{
     cmp,
     collections::VecDeque,
+    iter,
     path::{Path, PathBuf},
     str,
 };
@@ -42,10 +44,18 @@ pub fn isclose32(a: f32, b: f32) -> bool {
     (a..=(a + f32::EPSILON)).contains(&b)
 }
 
+pub fn isclose64(a: f64, b: f64) -> bool {
+    (a - b).abs() < f64::EPSILON
+}
+
 pub fn isone32(n: f32) -> bool {
     (1.0..=(1.0 + f32::EPSILON)).contains(&n)
 }
 
+pub fn isone64(n: f64) -> bool {
+    isclose64(n, 1.0)
+}
+
 pub fn get_track_dir() -> PathBuf {
     let config = CONFIG.get().read().unwrap();
     if config.track.exists() {
@@ -62,6 +72,18 @@ pub fn get_track_dir() -> PathBuf {
     PathBuf::from(".")
 }
 
+// Returns the innermost folder that all the paths are in
+pub fn get_common_dir(paths: &[PathBuf]) -> PathBuf {
+    let mut dir = match paths.first().and_then(|path| path.parent()) {
+        Some(dir) => dir.to_path_buf(),
+        None => return PathBuf::new(),
+    };
+    for path in paths.iter().skip(1) {
+        while !path.starts_with(&dir) && dir.pop() {}
+    }
+    dir
+}
+
 pub fn humanized_time(secs: f64) -> String {
     const HR_SIGN: char = 'h';
     const MIN_SIGN: char = '′';
@@ -106,13 +128,95 @@ pub fn humanized_time(secs: f64) -> String {
     }
 }
 
-pub fn get_track_data_html(track: &Path) -> String {
+// Parses a time given as seconds (e.g., 90 or 90.5), as HH:MM:SS.sss
+// (or MM:SS.sss), or using units (e.g., 1h2m3s, 2m, or 45s, where a
+// trailing number is in the next unit down, e.g., 1h30 or 2m30)
+pub fn parse_time(text: &str) -> Option<f64> {
+    let text = text.trim();
+    if text.is_empty() {
+        return None;
+    }
+    let mut secs = 0.0;
+    if text.contains(':') {
+        for part in text.split(':') {
+            secs = secs * 60.0 + part.trim().parse::<f64>().ok()?;
+        }
+    } else if text.contains(&['h', 'm', 's'][..]) {
+        let mut number = String::new();
+        let mut factor = 0.0;
+        for c in text.chars() {
+            factor = match c {
+                'h' => 3600.0,
+                'm' => 60.0,
+                's' => 1.0,
+                _ => {
+                    number.push(c);
+                    continue;
+                }
+            };
+            secs += number.trim().parse::<f64>().ok()? * factor;
+            number.clear();
+        }
+        if !number.trim().is_empty() {
+            if factor <= 1.0 {
+                return None; // Nothing is smaller than seconds
+            }
+            secs += number.trim().parse::<f64>().ok()? * factor / 60.0;
+        }
+    } else {
+        secs = text.parse::<f64>().ok()?;
+    }
+    if secs >= 0.0 && secs.is_finite() {
+        Some(secs)
+    } else {
+        None
+    }
+}
+
+// Returns the track's title from its tag or else from its filename
+pub fn get_track_title(track: &Path) -> String {
+    match get_track_tag(track) {
+        Ok(Some(data)) if !data.title.is_empty() => data.title,
+        _ => get_track_name(track),
+    }
+}
+
+pub fn get_track_name(track: &Path) -> String {
     let name = if let Some(name) = track.file_stem() {
         name.to_string_lossy()
     } else {
         track.to_string_lossy()
     };
-    let name = name.replace(&['_', '-'][..], " ");
+    name.replace(&['_', '-'][..], " ")
+}
+
+// Escapes text so that FLTK menus show it literally
+pub fn menu_escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('/', "\\/").replace('&', "&&")
+}
+
+// Returns "pos/length" and, if the speed isn't normal, the remaining time
+// both as track time and as real (speed-adjusted) time
+pub fn humanized_pos_and_length(
+    pos: f64,
+    length: f64,
+    speed: f64,
+) -> String {
+    let mut text =
+        format!("{}/{}", humanized_time(pos), humanized_time(length));
+    if !isone64(speed) {
+        let remaining = (length - pos).max(0.0);
+        text.push_str(&format!(
+            " −{} (−{})",
+            humanized_time(remaining),
+            humanized_time(remaining / speed)
+        ));
+    }
+    text
+}
+
+pub fn get_track_data_html(track: &Path) -> String {
+    let name = get_track_name(track);
     match get_track_tag(track) {
         Ok(Some(data)) => {
             let mut text = String::from("<font color=navy><b>");
@@ -171,6 +275,7 @@ pub fn get_track_data_html(track: &Path) -> String {
     }
 }
 
+#[derive(Clone, Debug, Default)]
 pub struct TrackData {
     pub title: String,
     pub album: String,
@@ -179,58 +284,82 @@ pub struct TrackData {
     pub year: i32,
 }
 
-fn get_track_tag(track: &Path) -> lofty::Result<Option<TrackData>> {
+pub fn get_track_tag(track: &Path) -> lofty::Result<Option<TrackData>> {
     let tags = Probe::open(track)?.guess_file_type()?.read(false)?;
-    if let Some(tag) = tags.primary_tag() {
-        Ok(Some(TrackData {
-            title: if let Some(title) = tag.title() {
-                title.to_owned()
-            } else {
-                String::new()
-            },
-            album: if let Some(album) = tag.album() {
-                album.to_owned()
-            } else {
-                String::new()
-            },
-            artist: if let Some(artist) = tag.artist() {
-                artist.to_owned()
-            } else {
-                String::new()
-            },
-            number: if let Some(num_item) =
-                tag.get_item_ref(&ItemKey::TrackNumber)
+    Ok(tags.primary_tag().map(get_tag_data))
+}
+
+// Returns the track's tag data (if tagged) and duration in seconds from
+// a single read of the file
+pub fn get_track_tag_and_secs(
+    track: &Path,
+) -> lofty::Result<(Option<TrackData>, f64)> {
+    let tagged_file = Probe::open(track)?.guess_file_type()?.read(true)?;
+    Ok((
+        tagged_file.primary_tag().map(get_tag_data),
+        tagged_file.properties().duration().as_secs_f64(),
+    ))
+}
+
+fn get_tag_data(tag: &Tag) -> TrackData {
+    TrackData {
+        title: if let Some(title) = tag.title() {
+            title.to_owned()
+        } else {
+            String::new()
+        },
+        album: if let Some(album) = tag.album() {
+            album.to_owned()
+        } else {
+            String::new()
+        },
+        artist: if let Some(artist) = tag.artist() {
+            artist.to_owned()
+        } else {
+            String::new()
+        },
+        number: if let Some(num_item) =
+            tag.get_item_ref(&ItemKey::TrackNumber)
+        {
+            match num_item.value() {
+                ItemValue::Text(text) => match text.parse::<i32>() {
+                    Ok(n) => n,
+                    _ => 0,
+                },
+                _ => 0,
+            }
+        } else {
+            0
+        },
+        year: {
+            if let Some(date) = tag.get_string(&ItemKey::RecordingDate) {
+                get_year_from_date(date)
+            } else if let Some(date) =
+                tag.get_string(&ItemKey::OriginalReleaseDate)
             {
-                match num_item.value() {
-                    ItemValue::Text(text) => match text.parse::<i32>() {
-                        Ok(n) => n,
-                        _ => 0,
-                    },
+                get_year_from_date(date)
+            } else if let Some(year) = tag.get_string(&ItemKey::Year) {
+                match year.parse::<i32>() {
+                    Ok(y) => y,
                     _ => 0,
                 }
             } else {
                 0
-            },
-            year: {
-                if let Some(date) = tag.get_string(&ItemKey::RecordingDate)
-                {
-                    get_year_from_date(date)
-                } else if let Some(date) =
-                    tag.get_string(&ItemKey::OriginalReleaseDate)
-                {
-                    get_year_from_date(date)
-                } else if let Some(year) = tag.get_string(&ItemKey::Year) {
-                    match year.parse::<i32>() {
-                        Ok(y) => y,
-                        _ => 0,
-                    }
-                } else {
-                    0
-                }
-            },
-        }))
-    } else {
-        Ok(None)
+            }
+        },
+    }
+}
+
+// Returns the track's duration in seconds if it can be determined
+pub fn get_track_secs(track: &Path) -> Option<f64> {
+    match Probe::open(track).and_then(|probe| probe.guess_file_type()) {
+        Ok(probe) => match probe.read(true) {
+            Ok(tagged_file) => {
+                Some(tagged_file.properties().duration().as_secs_f64())
+            }
+            Err(_) => None,
+        },
+        Err(_) => None,
     }
 }
 
@@ -245,62 +374,221 @@ fn get_year_from_date(date: &str) -> i32 {
     }
 }
 
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum WhichTrack {
     Previous,
     Next,
 }
 
+// Returns the index of the nearest track before or after the one at index
+// for which exists() is true, or None if there isn't one; if wrap is true
+// the first track follows the last (and vice versa) for one full cycle
+pub fn get_prev_or_next_index(
+    index: usize,
+    size: usize,
+    which: WhichTrack,
+    wrap: bool,
+    exists: impl Fn(usize) -> bool,
+) -> Option<usize> {
+    let mut index = index;
+    for _ in 0..size {
+        index = match which {
+            WhichTrack::Previous if index > 0 => index - 1,
+            WhichTrack::Previous if wrap => size - 1,
+            WhichTrack::Next if index + 1 < size => index + 1,
+            WhichTrack::Next if wrap => 0,
+            _ => return None,
+        };
+        if exists(index) {
+            return Some(index);
+        }
+    }
+    None
+}
+
+// If wrap is true the first track follows the last (and vice versa); if
+// recursive the tracks include those in subfolders, and the first (or
+// last) track of the next (or previous) sibling folder follows on
 pub fn get_prev_or_next_track(
     track: &Path,
     which: WhichTrack,
+    wrap: bool,
+    recursive: bool,
 ) -> Option<PathBuf> {
-    let tracks = get_sorted_tracks(track);
-    if let Ok(index) = tracks.binary_search(&track.to_path_buf()) {
-        match which {
-            WhichTrack::Previous => {
-                if index > 0 {
-                    return Some(tracks[index - 1].clone());
-                }
-            }
-            WhichTrack::Next => {
-                if index + 1 < tracks.len() {
-                    return Some(tracks[index + 1].clone());
-                }
+    let tracks = get_sorted_tracks(track, recursive);
+    let index = tracks.iter().position(|t| t == track)?;
+    match which {
+        WhichTrack::Previous if index > 0 => {
+            return Some(tracks[index - 1].clone());
+        }
+        WhichTrack::Next if index + 1 < tracks.len() => {
+            return Some(tracks[index + 1].clone());
+        }
+        _ => {}
+    }
+    if recursive {
+        if let Some(dir) = track.parent() {
+            let sibling_track = get_sibling_folder_track(dir, which, wrap);
+            if sibling_track.is_some() {
+                return sibling_track;
             }
         }
     }
+    if wrap {
+        match which {
+            WhichTrack::Previous => tracks.last().cloned(),
+            WhichTrack::Next => tracks.first().cloned(),
+        }
+    } else {
+        None
+    }
+}
+
+// Returns the first track of the nearest following folder that has any
+// (or the last track of the nearest preceding one)
+fn get_sibling_folder_track(
+    dir: &Path,
+    which: WhichTrack,
+    wrap: bool,
+) -> Option<PathBuf> {
+    let folders = get_subfolders(dir.parent()?);
+    let index = folders.iter().position(|folder| folder == dir)?;
+    let (before, after) = (&folders[..index], &folders[index + 1..]);
+    let candidates: Vec<&PathBuf> = match which {
+        WhichTrack::Next if wrap => after.iter().chain(before).collect(),
+        WhichTrack::Next => after.iter().collect(),
+        WhichTrack::Previous if wrap => {
+            before.iter().rev().chain(after.iter().rev()).collect()
+        }
+        WhichTrack::Previous => before.iter().rev().collect(),
+    };
+    for folder in candidates {
+        let tracks = get_dir_tracks(folder, true);
+        let track = match which {
+            WhichTrack::Previous => tracks.last(),
+            WhichTrack::Next => tracks.first(),
+        };
+        if track.is_some() {
+            return track.cloned();
+        }
+    }
     None
 }
 
-fn get_sorted_tracks(track: &Path) -> Vec<PathBuf> {
+pub fn get_sorted_tracks(track: &Path, recursive: bool) -> Vec<PathBuf> {
+    match track.parent() {
+        Some(dir) => get_dir_tracks(dir, recursive),
+        None => vec![],
+    }
+}
+
+// Returns the audio files in the given folder in natural name order; if
+// recursive these are followed by those in each subfolder in turn
+pub fn get_dir_tracks(dir: &Path, recursive: bool) -> Vec<PathBuf> {
     let mut tracks = vec![];
-    let suffixes = vec!["flac", "mogg", "mp3", "oga", "ogg", "wav"];
-    if let Some(dir) = track.parent() {
-        if let Ok(walker) = dir.read_dir() {
-            #[allow(clippy::manual_flatten)]
-            for entry in walker {
-                if let Ok(entry) = entry {
-                    if let Ok(kind) = entry.file_type() {
-                        if !kind.is_file() {
-                            continue;
-                        }
-                    }
-                    let path = entry.path();
-                    if let Some(extension) = path.extension() {
-                        let extension = extension.to_string_lossy();
-                        for suffix in &suffixes {
-                            if *suffix == extension {
-                                tracks.push(path);
-                                break;
-                            }
-                        }
-                    }
+    add_dir_tracks(dir, recursive, &mut tracks);
+    tracks
+}
+
+fn add_dir_tracks(dir: &Path, recursive: bool, tracks: &mut Vec<PathBuf>) {
+    let mut files = vec![];
+    if let Ok(walker) = dir.read_dir() {
+        for entry in walker.flatten() {
+            if let Ok(kind) = entry.file_type() {
+                if !kind.is_file() {
+                    continue;
                 }
             }
-            tracks.sort();
+            let path = entry.path();
+            if is_audio_file(&path) {
+                files.push(path);
+            }
         }
     }
-    tracks
+    files.sort_by(|a, b| natural_path_cmp(a, b));
+    tracks.extend(files);
+    if recursive {
+        for folder in get_subfolders(dir) {
+            if tracks.len() >= MAX_FOLDER_TRACKS {
+                break;
+            }
+            add_dir_tracks(&folder, true, tracks);
+        }
+    }
+}
+
+// Returns the (non-hidden) subfolders in natural name order; symlinks
+// aren't followed so there can be no cycles
+pub fn get_subfolders(dir: &Path) -> Vec<PathBuf> {
+    let mut folders = vec![];
+    if let Ok(walker) = dir.read_dir() {
+        for entry in walker.flatten() {
+            if matches!(entry.file_type(), Ok(kind) if kind.is_dir())
+                && !entry.file_name().to_string_lossy().starts_with('.')
+            {
+                folders.push(entry.path());
+            }
+        }
+    }
+    folders.sort_by(|a, b| natural_path_cmp(a, b));
+    folders
+}
+
+fn is_audio_file(path: &Path) -> bool {
+    match path.extension() {
+        Some(extension) => {
+            let extension = extension.to_string_lossy();
+            AUDIO_SUFFIXES.iter().any(|suffix| *suffix == extension)
+        }
+        None => false,
+    }
+}
+
+fn natural_path_cmp(a: &Path, b: &Path) -> cmp::Ordering {
+    match (a.file_name(), b.file_name()) {
+        (Some(a), Some(b)) => {
+            natural_cmp(&a.to_string_lossy(), &b.to_string_lossy())
+        }
+        _ => a.cmp(b),
+    }
+}
+
+// Compares so that, e.g., "CD2" comes before "CD10"; case is ignored
+// unless the texts are otherwise equal
+pub fn natural_cmp(a: &str, b: &str) -> cmp::Ordering {
+    let mut x = a.chars().peekable();
+    let mut y = b.chars().peekable();
+    loop {
+        let ordering = match (x.peek().copied(), y.peek().copied()) {
+            (None, None) => return a.cmp(b),
+            (None, Some(_)) => return cmp::Ordering::Less,
+            (Some(_), None) => return cmp::Ordering::Greater,
+            (Some(c), Some(d))
+                if c.is_ascii_digit() && d.is_ascii_digit() =>
+            {
+                let m = take_number(&mut x);
+                let n = take_number(&mut y);
+                m.len().cmp(&n.len()).then_with(|| m.cmp(&n))
+            }
+            (Some(c), Some(d)) => {
+                x.next();
+                y.next();
+                c.to_lowercase().cmp(d.to_lowercase())
+            }
+        };
+        if ordering != cmp::Ordering::Equal {
+            return ordering;
+        }
+    }
+}
+
+// Returns the leading digits without leading zeros
+fn take_number(chars: &mut iter::Peekable<str::Chars>) -> String {
+    let mut digits = String::new();
+    while let Some(c) = chars.next_if(|c| c.is_ascii_digit()) {
+        digits.push(c);
+    }
+    digits.trim_start_matches('0').to_string()
 }
 
 pub fn maybe_add_to_deque<T: cmp::PartialEq>(
@@ -321,3 +609,101 @@ pub fn maybe_add_to_deque<T: cmp::PartialEq>(
     deque.truncate(max_size);
     true
 }
+
+// Returns the existing files and folders in text dropped from a file
+// manager: one per line, either as paths or as file:// URLs
+pub fn get_dropped_paths(text: &str) -> Vec<PathBuf> {
+    text.lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| match line.strip_prefix("file://") {
+            Some(name) => {
+                let name = name.strip_prefix("localhost").unwrap_or(name);
+                PathBuf::from(percent_decode(name))
+            }
+            None => PathBuf::from(line),
+        })
+        .filter(|path| path.exists())
+        .collect()
+}
+
+// Encodes all but unreserved characters and slashes as %XX escapes
+// (e.g., for file:// URLs); the inverse of percent_decode()
+pub fn percent_encode(text: &str) -> String {
+    let mut encoded = String::with_capacity(text.len());
+    for byte in text.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~/".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    encoded
+}
+
+// Decodes %XX escapes (e.g., in file:// URLs)
+pub fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            if let Some(byte) = std::str::from_utf8(&bytes[i + 1..i + 3])
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_time_formats() {
+        for (text, secs) in [
+            ("90", 90.0),
+            ("90.5", 90.5),
+            (" 0 ", 0.0),
+            ("1:30", 90.0),
+            ("1:02:03.5", 3723.5),
+            ("1h2m3s", 3723.0),
+            ("2m", 120.0),
+            ("45s", 45.0),
+            ("1h30", 5400.0),
+            ("2m30", 150.0),
+            ("1h 30m", 5400.0),
+        ] {
+            assert_eq!(parse_time(text), Some(secs), "{text:?}");
+        }
+    }
+
+    #[test]
+    fn percent_encoding() {
+        let path = "/music/AC/DC #1 100% Ünïcode~_-.mp3";
+        let encoded = percent_encode(path);
+        assert_eq!(
+            encoded,
+            "/music/AC/DC%20%231%20100%25%20%C3%9Cn%C3%AFcode~_-.mp3"
+        );
+        assert_eq!(percent_decode(&encoded), path);
+    }
+
+    #[test]
+    fn parse_time_rejects() {
+        for text in [
+            "", "-5", "soon", "1:x", "1s30", "h", "1x2m", "inf", "-inf",
+            "NaN", "1e400", "inf:00",
+        ] {
+            assert_eq!(parse_time(text), None, "{text:?}");
+        }
+    }
+}